@@ -12,8 +12,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &[
                 "../protos/relay/datatransfer.proto",
                 "../protos/relay/events.proto",
+                "../protos/relay/admin.proto",
                 "../protos/networks/networks.proto",
                 "../protos/driver/driver.proto",
+                "../protos/common/access_control.proto",
             ],
             &[
                 "../protos/",
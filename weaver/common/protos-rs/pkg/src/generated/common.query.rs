@@ -23,4 +23,9 @@ pub struct Query {
     pub requesting_org: ::prost::alloc::string::String,
     #[prost(bool, tag = "10")]
     pub confidential: bool,
+    /// Relays the query has passed through, starting with the requesting relay.
+    /// A query for a network that is reached through intermediary relays is
+    /// forwarded by each of them, and its result goes back along the same path.
+    #[prost(string, repeated, tag = "11")]
+    pub relay_path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
@@ -17,6 +17,10 @@ pub struct Meta {
     /// The data field's serialization format (e.g. JSON, XML, Protobuf)
     #[prost(string, tag = "4")]
     pub serialization_format: ::prost::alloc::string::String,
+    /// Set by the requesting relay when the view was answered from its result
+    /// cache instead of the remote network.
+    #[prost(bool, tag = "5")]
+    pub from_cache: bool,
 }
 /// Nested message and enum types in `Meta`.
 pub mod meta {
@@ -83,6 +87,10 @@ pub struct View {
 pub struct ViewPayload {
     #[prost(string, tag = "1")]
     pub request_id: ::prost::alloc::string::String,
+    /// Relays the query passed through, from the requesting relay to the relay
+    /// serving the network.
+    #[prost(string, repeated, tag = "4")]
+    pub relay_path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
     #[prost(oneof = "view_payload::State", tags = "2, 3")]
     pub state: ::core::option::Option<view_payload::State>,
 }
@@ -108,6 +116,13 @@ pub struct RequestState {
     pub request_id: ::prost::alloc::string::String,
     #[prost(enumeration = "request_state::Status", tag = "2")]
     pub status: i32,
+    /// Relays the query passed through, from the requesting relay to the relay
+    /// serving the network, once the result is received.
+    #[prost(string, repeated, tag = "5")]
+    pub relay_path: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// Delivery of the state to the callback of the query, if it has one.
+    #[prost(message, optional, tag = "6")]
+    pub callback: ::core::option::Option<CallbackDelivery>,
     #[prost(oneof = "request_state::State", tags = "3, 4")]
     pub state: ::core::option::Option<request_state::State>,
 }
@@ -143,6 +158,8 @@ pub mod request_state {
         EventWriteError = 6,
         /// Once network fetches this request state, mark it delete for cleanup later on
         Deleted = 7,
+        /// The network cancelled the request before its result was received
+        Cancelled = 8,
     }
     impl Status {
         /// String value of the enum field names used in the ProtoBuf definition.
@@ -159,6 +176,7 @@ pub mod request_state {
                 Status::EventWritten => "EVENT_WRITTEN",
                 Status::EventWriteError => "EVENT_WRITE_ERROR",
                 Status::Deleted => "DELETED",
+                Status::Cancelled => "CANCELLED",
             }
         }
         /// Creates an enum from field names used in the ProtoBuf definition.
@@ -172,6 +190,7 @@ pub mod request_state {
                 "EVENT_WRITTEN" => Some(Self::EventWritten),
                 "EVENT_WRITE_ERROR" => Some(Self::EventWriteError),
                 "DELETED" => Some(Self::Deleted),
+                "CANCELLED" => Some(Self::Cancelled),
                 _ => None,
             }
         }
@@ -186,3 +205,61 @@ pub mod request_state {
         Error(::prost::alloc::string::String),
     }
 }
+/// Delivery of the final state of a request to the callback given with its query.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CallbackDelivery {
+    #[prost(enumeration = "callback_delivery::Status", tag = "1")]
+    pub status: i32,
+    #[prost(uint32, tag = "2")]
+    pub attempts: u32,
+    /// The error of the last failed attempt.
+    #[prost(string, tag = "3")]
+    pub error: ::prost::alloc::string::String,
+}
+/// Nested message and enum types in `CallbackDelivery`.
+pub mod callback_delivery {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[derive(
+        Clone,
+        Copy,
+        Debug,
+        PartialEq,
+        Eq,
+        Hash,
+        PartialOrd,
+        Ord,
+        ::prost::Enumeration
+    )]
+    #[repr(i32)]
+    pub enum Status {
+        /// Not delivered yet, attempts are still being made
+        Pending = 0,
+        Delivered = 1,
+        /// All attempts failed
+        Failed = 2,
+    }
+    impl Status {
+        /// String value of the enum field names used in the ProtoBuf definition.
+        ///
+        /// The values are not transformed in any way and thus are considered stable
+        /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                Status::Pending => "PENDING",
+                Status::Delivered => "DELIVERED",
+                Status::Failed => "FAILED",
+            }
+        }
+        /// Creates an enum from field names used in the ProtoBuf definition.
+        pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+            match value {
+                "PENDING" => Some(Self::Pending),
+                "DELIVERED" => Some(Self::Delivered),
+                "FAILED" => Some(Self::Failed),
+                _ => None,
+            }
+        }
+    }
+}
@@ -44,6 +44,66 @@ pub struct NetworkQuery {
     pub requesting_org: ::prost::alloc::string::String,
     #[prost(bool, tag = "9")]
     pub confidential: bool,
+    /// Fetch the view from the remote network even if the relay has a cached result.
+    #[prost(bool, tag = "10")]
+    pub bypass_cache: bool,
+    /// Where the relay pushes the final RequestState of the query, once it is
+    /// completed or failed: posted as JSON to an app url, or written to the
+    /// ledger with a contract transaction through a driver.
+    #[prost(message, optional, tag = "11")]
+    pub callback: ::core::option::Option<super::super::common::events::EventPublication>,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NetworkQuerySync {
+    #[prost(message, optional, tag = "1")]
+    pub query: ::core::option::Option<NetworkQuery>,
+    /// How long the relay waits for the final state, capped by the relay.
+    #[prost(uint32, tag = "2")]
+    pub timeout_msec: u32,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CancelRequestMessage {
+    #[prost(string, tag = "1")]
+    pub request_id: ::prost::alloc::string::String,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NetworkQueryBatch {
+    #[prost(message, repeated, tag = "1")]
+    pub queries: ::prost::alloc::vec::Vec<NetworkQuery>,
+}
+/// The acks of the queries of a batch, in the order of the queries. Each ack
+/// carries the request id of its query, which can also be polled with GetState.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BatchAck {
+    #[prost(string, tag = "1")]
+    pub batch_id: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub acks: ::prost::alloc::vec::Vec<super::super::common::ack::Ack>,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetBatchStateMessage {
+    #[prost(string, tag = "1")]
+    pub batch_id: ::prost::alloc::string::String,
+}
+/// The states of the queries of a batch, in the order of the queries.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BatchState {
+    #[prost(string, tag = "1")]
+    pub batch_id: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub states: ::prost::alloc::vec::Vec<super::super::common::state::RequestState>,
 }
 /// Below message is used for network/client to dest-relay communication
 #[derive(serde::Serialize, serde::Deserialize)]
@@ -210,6 +270,113 @@ pub mod network_client {
                 .insert(GrpcMethod::new("networks.networks.Network", "GetState"));
             self.inner.unary(req, path, codec).await
         }
+        /// endpoint for a network to request many remote relay states in one call.
+        /// Queries of the same remote relay are sent to it together.
+        pub async fn request_state_batch(
+            &mut self,
+            request: impl tonic::IntoRequest<super::NetworkQueryBatch>,
+        ) -> std::result::Result<tonic::Response<super::BatchAck>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/networks.networks.Network/RequestStateBatch",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new("networks.networks.Network", "RequestStateBatch"),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// This rpc endpoint is for polling the local relay for the states of all
+        /// queries of a batch.
+        pub async fn get_batch_state(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetBatchStateMessage>,
+        ) -> std::result::Result<tonic::Response<super::BatchState>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/networks.networks.Network/GetBatchState",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("networks.networks.Network", "GetBatchState"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// endpoint for a network to abandon a pending request. The remote relay is
+        /// told to drop the request, and a result received later is discarded.
+        pub async fn cancel_request(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CancelRequestMessage>,
+        ) -> std::result::Result<
+            tonic::Response<super::super::super::common::ack::Ack>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/networks.networks.Network/CancelRequest",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("networks.networks.Network", "CancelRequest"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// endpoint for a network to request remote relay state and wait for it in
+        /// one call. The relay answers with the final state, or with the state as it
+        /// stands when the timeout is reached, whose request id can still be polled.
+        pub async fn request_state_sync(
+            &mut self,
+            request: impl tonic::IntoRequest<super::NetworkQuerySync>,
+        ) -> std::result::Result<
+            tonic::Response<super::super::super::common::state::RequestState>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/networks.networks.Network/RequestStateSync",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new("networks.networks.Network", "RequestStateSync"),
+                );
+            self.inner.unary(req, path, codec).await
+        }
         /// NOTE: This rpc is just for debugging.
         pub async fn request_database(
             &mut self,
@@ -372,6 +539,37 @@ pub mod network_server {
             tonic::Response<super::super::super::common::state::RequestState>,
             tonic::Status,
         >;
+        /// endpoint for a network to request many remote relay states in one call.
+        /// Queries of the same remote relay are sent to it together.
+        async fn request_state_batch(
+            &self,
+            request: tonic::Request<super::NetworkQueryBatch>,
+        ) -> std::result::Result<tonic::Response<super::BatchAck>, tonic::Status>;
+        /// This rpc endpoint is for polling the local relay for the states of all
+        /// queries of a batch.
+        async fn get_batch_state(
+            &self,
+            request: tonic::Request<super::GetBatchStateMessage>,
+        ) -> std::result::Result<tonic::Response<super::BatchState>, tonic::Status>;
+        /// endpoint for a network to abandon a pending request. The remote relay is
+        /// told to drop the request, and a result received later is discarded.
+        async fn cancel_request(
+            &self,
+            request: tonic::Request<super::CancelRequestMessage>,
+        ) -> std::result::Result<
+            tonic::Response<super::super::super::common::ack::Ack>,
+            tonic::Status,
+        >;
+        /// endpoint for a network to request remote relay state and wait for it in
+        /// one call. The relay answers with the final state, or with the state as it
+        /// stands when the timeout is reached, whose request id can still be polled.
+        async fn request_state_sync(
+            &self,
+            request: tonic::Request<super::NetworkQuerySync>,
+        ) -> std::result::Result<
+            tonic::Response<super::super::super::common::state::RequestState>,
+            tonic::Status,
+        >;
         /// NOTE: This rpc is just for debugging.
         async fn request_database(
             &self,
@@ -581,6 +779,188 @@ pub mod network_server {
                     };
                     Box::pin(fut)
                 }
+                "/networks.networks.Network/RequestStateBatch" => {
+                    #[allow(non_camel_case_types)]
+                    struct RequestStateBatchSvc<T: Network>(pub Arc<T>);
+                    impl<
+                        T: Network,
+                    > tonic::server::UnaryService<super::NetworkQueryBatch>
+                    for RequestStateBatchSvc<T> {
+                        type Response = super::BatchAck;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::NetworkQueryBatch>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Network>::request_state_batch(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = RequestStateBatchSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/networks.networks.Network/GetBatchState" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetBatchStateSvc<T: Network>(pub Arc<T>);
+                    impl<
+                        T: Network,
+                    > tonic::server::UnaryService<super::GetBatchStateMessage>
+                    for GetBatchStateSvc<T> {
+                        type Response = super::BatchState;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetBatchStateMessage>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Network>::get_batch_state(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = GetBatchStateSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/networks.networks.Network/CancelRequest" => {
+                    #[allow(non_camel_case_types)]
+                    struct CancelRequestSvc<T: Network>(pub Arc<T>);
+                    impl<
+                        T: Network,
+                    > tonic::server::UnaryService<super::CancelRequestMessage>
+                    for CancelRequestSvc<T> {
+                        type Response = super::super::super::common::ack::Ack;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CancelRequestMessage>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Network>::cancel_request(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = CancelRequestSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/networks.networks.Network/RequestStateSync" => {
+                    #[allow(non_camel_case_types)]
+                    struct RequestStateSyncSvc<T: Network>(pub Arc<T>);
+                    impl<T: Network> tonic::server::UnaryService<super::NetworkQuerySync>
+                    for RequestStateSyncSvc<T> {
+                        type Response = super::super::super::common::state::RequestState;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::NetworkQuerySync>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as Network>::request_state_sync(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = RequestStateSyncSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 "/networks.networks.Network/RequestDatabase" => {
                     #[allow(non_camel_case_types)]
                     struct RequestDatabaseSvc<T: Network>(pub Arc<T>);
@@ -1,3 +1,28 @@
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CancelQuery {
+    #[prost(string, tag = "1")]
+    pub request_id: ::prost::alloc::string::String,
+    /// The relay cancelling the query, which is the relay the query was received from.
+    #[prost(string, tag = "2")]
+    pub requesting_relay: ::prost::alloc::string::String,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct QueryBatch {
+    #[prost(message, repeated, tag = "1")]
+    pub queries: ::prost::alloc::vec::Vec<super::super::common::query::Query>,
+}
+/// The acks of the queries of a batch, in the order of the queries.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct AckBatch {
+    #[prost(message, repeated, tag = "1")]
+    pub acks: ::prost::alloc::vec::Vec<super::super::common::ack::Ack>,
+}
 /// Generated client implementations.
 pub mod data_transfer_client {
     #![allow(unused_variables, dead_code, missing_docs, clippy::let_unit_value)]
@@ -142,6 +167,64 @@ pub mod data_transfer_client {
                 .insert(GrpcMethod::new("relay.datatransfer.DataTransfer", "SendState"));
             self.inner.unary(req, path, codec).await
         }
+        /// the requesting relay sends several queries to the remote relay in one call.
+        /// Their results are sent back with SendState one by one.
+        pub async fn request_state_batch(
+            &mut self,
+            request: impl tonic::IntoRequest<super::QueryBatch>,
+        ) -> std::result::Result<tonic::Response<super::AckBatch>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/relay.datatransfer.DataTransfer/RequestStateBatch",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new(
+                        "relay.datatransfer.DataTransfer",
+                        "RequestStateBatch",
+                    ),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        /// the requesting relay tells the remote relay that a query was cancelled. The
+        /// remote relay drops the work left for the query and discards its result.
+        pub async fn cancel_request(
+            &mut self,
+            request: impl tonic::IntoRequest<super::CancelQuery>,
+        ) -> std::result::Result<
+            tonic::Response<super::super::super::common::ack::Ack>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::new(
+                        tonic::Code::Unknown,
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/relay.datatransfer.DataTransfer/CancelRequest",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new("relay.datatransfer.DataTransfer", "CancelRequest"),
+                );
+            self.inner.unary(req, path, codec).await
+        }
         /// Handling state sent from the driver.
         pub async fn send_driver_state(
             &mut self,
@@ -199,6 +282,21 @@ pub mod data_transfer_server {
             tonic::Response<super::super::super::common::ack::Ack>,
             tonic::Status,
         >;
+        /// the requesting relay sends several queries to the remote relay in one call.
+        /// Their results are sent back with SendState one by one.
+        async fn request_state_batch(
+            &self,
+            request: tonic::Request<super::QueryBatch>,
+        ) -> std::result::Result<tonic::Response<super::AckBatch>, tonic::Status>;
+        /// the requesting relay tells the remote relay that a query was cancelled. The
+        /// remote relay drops the work left for the query and discards its result.
+        async fn cancel_request(
+            &self,
+            request: tonic::Request<super::CancelQuery>,
+        ) -> std::result::Result<
+            tonic::Response<super::super::super::common::ack::Ack>,
+            tonic::Status,
+        >;
         /// Handling state sent from the driver.
         async fn send_driver_state(
             &self,
@@ -386,6 +484,95 @@ pub mod data_transfer_server {
                     };
                     Box::pin(fut)
                 }
+                "/relay.datatransfer.DataTransfer/RequestStateBatch" => {
+                    #[allow(non_camel_case_types)]
+                    struct RequestStateBatchSvc<T: DataTransfer>(pub Arc<T>);
+                    impl<T: DataTransfer> tonic::server::UnaryService<super::QueryBatch>
+                    for RequestStateBatchSvc<T> {
+                        type Response = super::AckBatch;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::QueryBatch>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as DataTransfer>::request_state_batch(&inner, request)
+                                    .await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = RequestStateBatchSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/relay.datatransfer.DataTransfer/CancelRequest" => {
+                    #[allow(non_camel_case_types)]
+                    struct CancelRequestSvc<T: DataTransfer>(pub Arc<T>);
+                    impl<T: DataTransfer> tonic::server::UnaryService<super::CancelQuery>
+                    for CancelRequestSvc<T> {
+                        type Response = super::super::super::common::ack::Ack;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::CancelQuery>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as DataTransfer>::cancel_request(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let inner = inner.0;
+                        let method = CancelRequestSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 "/relay.datatransfer.DataTransfer/SendDriverState" => {
                     #[allow(non_camel_case_types)]
                     struct SendDriverStateSvc<T: DataTransfer>(pub Arc<T>);
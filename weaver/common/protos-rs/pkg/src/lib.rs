@@ -9,6 +9,9 @@ pub mod relay {
     pub mod events {
         include!(concat!("./generated", "/relay.events.rs"));
     }
+    pub mod admin {
+        include!(concat!("./generated", "/relay.admin.rs"));
+    }
 }
 pub mod networks {
     pub mod networks {
@@ -34,4 +37,7 @@ pub mod common {
     pub mod events {
         include!(concat!("./generated", "/common.events.rs"));
     }
+    pub mod access_control {
+        include!(concat!("./generated", "/common.access_control.rs"));
+    }
 }
@@ -177,6 +177,7 @@ impl DriverCommunication for DriverCommunicationService {
             nonce: received_query.nonce,
             request_id: received_query.request_id,
             confidential: received_query.confidential,
+            relay_path: received_query.relay_path,
         };
         return Ok(Response::new(signed_query));
     }
@@ -223,11 +224,13 @@ fn send_driver_mock_state_helper(client: DataTransferClient<Channel>, request_id
                     timestamp: "I am time".to_string(),
                     proof_type: "I am proof".to_string(),
                     serialization_format: "Proto".to_string(),
+                    from_cache: false,
                     protocol: meta::Protocol::Fabric as i32
                 }),
                 data: "This is a mocked payload".as_bytes().to_vec(),
             })),
             request_id: request_id.to_string(),
+            relay_path: vec![],
         };
         println!("Sending state to remote relay...");
         let response = client.clone().send_driver_state(state).await;
@@ -0,0 +1,271 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::common::access_control::{AccessControlPolicy, Rule};
+use weaverpb::common::query::Query;
+
+use crate::error::Error;
+use crate::relay_config::RelayConfig;
+use crate::relay_proto::{parse_address, Address};
+use crate::routing::previous_hop;
+
+use std::fs;
+use tracing::debug;
+
+// Policy files use the same JSON layout as the access control policies
+// recorded on the ledger by the interop contracts, i.e. camelCase field names.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PolicyFile {
+    security_domain: String,
+    #[serde(default)]
+    rules: Vec<RuleFile>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RuleFile {
+    principal: String,
+    principal_type: String,
+    resource: String,
+    #[serde(default)]
+    read: bool,
+}
+
+/// Reads the access control policies file a local network names in
+/// `networks.<network_id>.access_control_policies`. The policies are loaded
+/// with the rest of the relay config, so that a config reload also picks up
+/// changes to the file.
+pub fn load_policies(path: &str) -> Result<Vec<AccessControlPolicy>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("unable to read file \"{}\": {}", path, e))?;
+    let policy_files: Vec<PolicyFile> =
+        serde_json::from_str(&contents).map_err(|e| format!("file \"{}\" is not well-formatted: {}", path, e))?;
+    let policies = policy_files
+        .into_iter()
+        .map(|policy| AccessControlPolicy {
+            security_domain: policy.security_domain,
+            rules: policy
+                .rules
+                .into_iter()
+                .map(|rule| Rule {
+                    principal: rule.principal,
+                    principal_type: rule.principal_type,
+                    resource: rule.resource,
+                    read: rule.read,
+                })
+                .collect(),
+        })
+        .collect();
+    Ok(policies)
+}
+
+/// Checks that the relay a query comes from is one this relay knows, i.e. it is
+/// listed in `[relays]` or can be discovered (`relay_known`), and that the
+/// requesting relay is permitted by the optional `allowed_relays` and
+/// `denied_relays` lists of the targeted local network. A forwarded query
+/// comes from the last relay on its path rather than the requesting relay.
+/// Returns the parsed address of an admitted query. The relay is checked before
+/// the address is parsed, so that an unknown relay is turned away whatever
+/// address it sends.
+pub fn check_relay_admission(query: &Query, conf: &RelayConfig, relay_known: bool) -> Result<Address, Error> {
+    let relay_name = &query.requesting_relay;
+    if !relay_known {
+        let from = previous_hop(query);
+        return Err(Error::AccessDenied(format!(
+            "Access denied: {} relay \"{}\" is not a known relay",
+            if from == *relay_name { "requesting" } else { "forwarding" },
+            from
+        )));
+    }
+    let address = parse_address(query.address.to_string())?;
+    let network = conf.networks.get(&address.network_id);
+    let denied_relays = network.and_then(|network| network.denied_relays.as_ref());
+    if denied_relays.is_some_and(|relays| relays.contains(relay_name)) {
+        return Err(Error::AccessDenied(format!(
+            "Access denied: requesting relay \"{}\" is not permitted to access network \"{}\"",
+            relay_name, address.network_id
+        )));
+    }
+    let allowed_relays = network.and_then(|network| network.allowed_relays.as_ref());
+    if allowed_relays.is_some_and(|relays| !relays.contains(relay_name)) {
+        return Err(Error::AccessDenied(format!(
+            "Access denied: requesting relay \"{}\" is not permitted to access network \"{}\"",
+            relay_name, address.network_id
+        )));
+    }
+    Ok(address)
+}
+
+/// Checks the view requested by `query` against the access control policies of
+/// the local network it targets. Queries are denied with an error describing
+/// the requester and the view when no rule of the requesting network's policy
+/// grants read access. A network without a policy file is not checked, and the
+/// decision is left to the ledger.
+pub fn check_access(query: &Query, address: &Address, conf: &RelayConfig) -> Result<(), Error> {
+    match conf.access_control_policies.get(&address.network_id) {
+        Some(policies) => verify_access(query, &address.view, policies),
+        None => Ok(()),
+    }
+}
+
+fn verify_access(query: &Query, view: &str, policies: &[AccessControlPolicy]) -> Result<(), Error> {
+    let policy = policies
+        .iter()
+        .find(|policy| policy.security_domain == query.requesting_network)
+        .ok_or(Error::AccessDenied(format!(
+            "Access denied: no access control policy exists for requesting network \"{}\"",
+            query.requesting_network
+        )))?;
+    for rule in policy.rules.iter() {
+        if rule.read && resource_matches(&rule.resource, view) && principal_matches(rule, query) {
+            debug!(
+                "Access Control Policy permits the request '{}' from network '{}'",
+                view, query.requesting_network
+            );
+            return Ok(());
+        }
+    }
+    let requester = if !query.requesting_org.is_empty() && query.certificate.is_empty() {
+        format!("org \"{}\"", query.requesting_org)
+    } else {
+        "the requestor certificate".to_string()
+    };
+    Err(Error::AccessDenied(format!(
+        "Access denied: access control policy of network \"{}\" does not permit {} to read view \"{}\"",
+        query.requesting_network, requester, view
+    )))
+}
+
+// A principal of type "certificate" has to be the requestor's certificate, a
+// principal of type "ca" names the requesting organization.
+fn principal_matches(rule: &Rule, query: &Query) -> bool {
+    match rule.principal_type.as_str() {
+        "certificate" => !query.certificate.is_empty() && rule.principal.trim() == query.certificate.trim(),
+        "ca" => !query.requesting_org.is_empty() && rule.principal == query.requesting_org,
+        _ => false,
+    }
+}
+
+// Resources are either a full view address or a pattern with a single
+// trailing '*', following the same rules as the interop contracts.
+fn resource_matches(resource: &str, view: &str) -> bool {
+    match resource.matches('*').count() {
+        0 => resource == view,
+        1 if resource.ends_with('*') => view.contains(resource.trim_end_matches('*')),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_config::TestConfig;
+
+    fn policies() -> Vec<AccessControlPolicy> {
+        vec![AccessControlPolicy {
+            security_domain: "network1".to_string(),
+            rules: vec![
+                Rule {
+                    principal: "Org1MSP".to_string(),
+                    principal_type: "ca".to_string(),
+                    resource: "mychannel:simplestate:Read:*".to_string(),
+                    read: true,
+                },
+                Rule {
+                    principal: "cert-pem".to_string(),
+                    principal_type: "certificate".to_string(),
+                    resource: "mychannel:simpleasset:Read:a".to_string(),
+                    read: true,
+                },
+            ],
+        }]
+    }
+
+    fn query(network: &str, org: &str, certificate: &str) -> Query {
+        Query {
+            requesting_network: network.to_string(),
+            requesting_org: org.to_string(),
+            certificate: certificate.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rules_grant_access_by_org_and_certificate() {
+        let policies = policies();
+        assert!(verify_access(&query("network1", "Org1MSP", ""), "mychannel:simplestate:Read:a", &policies).is_ok());
+        assert!(verify_access(&query("network1", "", "cert-pem"), "mychannel:simpleasset:Read:a", &policies).is_ok());
+        assert!(verify_access(&query("network1", "Org2MSP", ""), "mychannel:simplestate:Read:a", &policies).is_err());
+        assert!(verify_access(&query("network1", "", "cert-pem"), "mychannel:simpleasset:Read:b", &policies).is_err());
+    }
+
+    fn relay_conf(dir: &TestConfig, network_table: &str) -> RelayConfig {
+        let tables = format!(
+            "[drivers.Fabric]\nhostname=\"localhost\"\nport=\"9090\"\ntls=false\ntlsca_cert_path=\"\"\n\
+             [relays.Corda_Relay]\nhostname=\"localhost\"\nport=\"9081\"\ntls=false\ntlsca_cert_path=\"\"\n\
+             [relays.Fabric_Relay2]\nhostname=\"localhost\"\nport=\"9083\"\ntls=false\ntlsca_cert_path=\"\"\n\
+             [networks.network1]\nnetwork=\"Fabric\"\n{}",
+            network_table
+        );
+        dir.relay_config("Fabric_Relay", "9080", &tables)
+    }
+
+    fn address(network_id: &str) -> Address {
+        crate::relay_proto::parse_address(format!("localhost:9080/{}/mychannel:simplestate:Read:a", network_id)).unwrap()
+    }
+
+    #[test]
+    fn relays_are_admitted_by_relays_table_and_network_lists() {
+        let admit = |relay: &str, conf: &RelayConfig| {
+            let query = Query {
+                requesting_relay: relay.to_string(),
+                address: "localhost:9080/network1/mychannel:simplestate:Read:a".to_string(),
+                ..Default::default()
+            };
+            check_relay_admission(&query, conf, conf.relays.contains_key(relay))
+        };
+        let dir = TestConfig::default();
+        let conf = relay_conf(&dir, "");
+        assert!(admit("Corda_Relay", &conf).is_ok());
+        assert!(admit("Unknown_Relay", &conf).is_err());
+
+        let conf = relay_conf(&dir, "allowed_relays=[\"Corda_Relay\"]");
+        assert!(admit("Corda_Relay", &conf).is_ok());
+        assert!(admit("Fabric_Relay2", &conf).is_err());
+
+        let conf = relay_conf(&dir, "denied_relays=[\"Corda_Relay\"]");
+        assert!(admit("Corda_Relay", &conf).is_err());
+        assert!(admit("Fabric_Relay2", &conf).is_ok());
+    }
+
+    #[test]
+    fn policies_are_read_when_the_config_is_loaded() {
+        let dir = TestConfig::default();
+        let policies_file = dir.path().join("policies.json");
+        let write_policies = |resource: &str| {
+            let policies = format!(
+                "[{{\"securityDomain\":\"network2\",\"rules\":[{{\"principal\":\"Org2MSP\",\"principalType\":\"ca\",\"resource\":\"{}\",\"read\":true}}]}}]",
+                resource
+            );
+            std::fs::write(&policies_file, policies).unwrap();
+        };
+        let network_table = format!("access_control_policies=\"{}\"", policies_file.display());
+        let query = query("network2", "Org2MSP", "");
+
+        write_policies("mychannel:simplestate:Read:*");
+        let conf = relay_conf(&dir, &network_table);
+        assert!(check_access(&query, &address("network1"), &conf).is_ok());
+
+        // The loaded config keeps its policies until the config is reloaded.
+        write_policies("mychannel:simplestate:Read:b");
+        assert!(check_access(&query, &address("network1"), &conf).is_ok());
+        let reloaded = relay_conf(&dir, &network_table);
+        assert!(check_access(&query, &address("network1"), &reloaded).is_err());
+    }
+
+    #[test]
+    fn unknown_requesting_network_is_denied() {
+        assert!(verify_access(&query("network2", "Org1MSP", ""), "mychannel:simplestate:Read:a", &policies()).is_err());
+    }
+}
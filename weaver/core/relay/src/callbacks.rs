@@ -0,0 +1,361 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::common::events::{event_publication, EventPublication};
+use weaverpb::common::state::{callback_delivery, request_state, view_payload, CallbackDelivery, RequestState, ViewPayload};
+
+use crate::db::Database;
+use crate::error::Error;
+use crate::relay_config::RelayConfig;
+use crate::services::event_publish_service::write_to_ledger;
+use crate::services::helpers::get_callback_key;
+use crate::shutdown::{spawn_tracked, PendingWork};
+
+use std::time::Duration;
+use tracing::{info, warn, Instrument};
+
+/// The `[callbacks]` section of the relay config.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct CallbackSettings {
+    // How many times the final state of a request is pushed to its callback before giving up.
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    // Wait before the second attempt, doubled for each further attempt.
+    #[serde(default = "default_retry_backoff_msec")]
+    pub retry_backoff_msec: u64,
+    // How long an app url may take to answer a post.
+    #[serde(default = "default_timeout_msec")]
+    pub timeout_msec: u64,
+}
+
+fn default_max_attempts() -> u32 {
+    5
+}
+
+fn default_retry_backoff_msec() -> u64 {
+    1000
+}
+
+fn default_timeout_msec() -> u64 {
+    10000
+}
+
+impl Default for CallbackSettings {
+    fn default() -> Self {
+        CallbackSettings {
+            max_attempts: default_max_attempts(),
+            retry_backoff_msec: default_retry_backoff_msec(),
+            timeout_msec: default_timeout_msec(),
+        }
+    }
+}
+
+impl CallbackSettings {
+    /// Reads the `[callbacks]` table, defaults apply when it is missing.
+    pub fn from_config(conf: &config::Config) -> Result<CallbackSettings, String> {
+        let settings = match conf.get::<CallbackSettings>("callbacks") {
+            Ok(settings) => settings,
+            Err(config::ConfigError::NotFound(_)) => CallbackSettings::default(),
+            Err(e) => return Err(format!("callbacks: {}", e)),
+        };
+        if settings.max_attempts == 0 {
+            return Err("callbacks.max_attempts: must be at least 1".to_string());
+        }
+        Ok(settings)
+    }
+
+    // How long to wait after a failed attempt before the next one.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        Duration::from_millis(self.retry_backoff_msec.saturating_mul(factor))
+    }
+}
+
+fn local_db(conf: &RelayConfig) -> Database {
+    Database {
+        db_path: conf.db_path.to_string(),
+        db_open_max_retries: conf.db_open_max_retries,
+        db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+    }
+}
+
+/// Records the callback of a query, so that the final state of its request
+/// is pushed to it. Queries without a callback are left alone.
+pub fn remember_callback(db: &Database, request_id: &str, callback: Option<&EventPublication>) -> Result<(), Error> {
+    match callback {
+        Some(callback) if callback.publication_target.is_some() => {
+            db.set(&get_callback_key(request_id.to_string()), callback)?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Forgets the callback of a request, e.g. once it is cancelled.
+pub fn forget_callback(db: &Database, request_id: &str) {
+    let _ = db.unset::<EventPublication>(get_callback_key(request_id.to_string()));
+}
+
+/// Pushes the state of a request to its callback once the state is final,
+/// i.e. the request is completed or failed. A request's callback is only
+/// used once.
+pub fn deliver_final_state(conf: &RelayConfig, request_id: &str) {
+    let db = local_db(conf);
+    let Ok(mut state) = db.get::<RequestState>(request_id.to_string()) else {
+        return;
+    };
+    if !matches!(
+        request_state::Status::try_from(state.status),
+        Ok(request_state::Status::Completed) | Ok(request_state::Status::Error)
+    ) {
+        return;
+    }
+    let Ok(callback) = db.unset::<EventPublication>(get_callback_key(request_id.to_string())) else {
+        return;
+    };
+    state.callback = Some(CallbackDelivery::default());
+    record_delivery(conf, request_id, &CallbackDelivery::default());
+    spawn_deliver_callback(conf.clone(), state, callback);
+}
+
+// Pushes the state to the callback until it is accepted or the attempts run
+// out, recording each attempt in the request's state.
+pub fn spawn_deliver_callback(conf: RelayConfig, state: RequestState, callback: EventPublication) {
+    let work = PendingWork::DeliverCallback {
+        state: state.clone(),
+        callback: callback.clone(),
+    };
+    spawn_tracked(work, async move {
+        let settings = conf.callbacks.clone();
+        let request_id = state.request_id.to_string();
+        let mut delivery = state.callback.clone().unwrap_or_default();
+        let pushed = RequestState { callback: None, ..state };
+        while delivery.attempts < settings.max_attempts {
+            if delivery.attempts > 0 {
+                tokio::time::sleep(settings.backoff(delivery.attempts)).await;
+            }
+            delivery.attempts += 1;
+            match push_state(&pushed, &callback, &conf).await {
+                Ok(()) => {
+                    info!("Delivered state of request {} to its callback", request_id);
+                    delivery.status = callback_delivery::Status::Delivered as i32;
+                    delivery.error = "".to_string();
+                }
+                Err(e) => {
+                    warn!("Attempt {} to deliver state of request {} to its callback failed: {:?}", delivery.attempts, request_id, e);
+                    delivery.error = format!("{:?}", e);
+                    if delivery.attempts >= settings.max_attempts {
+                        delivery.status = callback_delivery::Status::Failed as i32;
+                    }
+                }
+            }
+            record_delivery(&conf, &request_id, &delivery);
+            if delivery.status != callback_delivery::Status::Pending as i32 {
+                break;
+            }
+        }
+    }.in_current_span());
+}
+
+// Posts the state as JSON to an app url, or writes it to the ledger through a driver.
+async fn push_state(state: &RequestState, callback: &EventPublication, conf: &RelayConfig) -> Result<(), Error> {
+    match &callback.publication_target {
+        Some(event_publication::PublicationTarget::AppUrl(app_url)) => {
+            let response = reqwest::Client::new()
+                .post(app_url)
+                .timeout(Duration::from_millis(conf.callbacks.timeout_msec))
+                .json(state)
+                .send()
+                .await
+                .map_err(|e| Error::Simple(format!("Post to {} failed: {}", app_url, e)))?;
+            match response.status() {
+                status if status.is_success() => Ok(()),
+                status => Err(Error::Simple(format!("{} answered with {}", app_url, status))),
+            }
+        }
+        Some(event_publication::PublicationTarget::Ctx(ctx)) => {
+            let payload = ViewPayload {
+                request_id: state.request_id.to_string(),
+                relay_path: state.relay_path.clone(),
+                state: match &state.state {
+                    Some(request_state::State::View(view)) => Some(view_payload::State::View(view.clone())),
+                    Some(request_state::State::Error(error)) => Some(view_payload::State::Error(error.to_string())),
+                    None => None,
+                },
+            };
+            write_to_ledger(payload, ctx.clone(), conf).await.map(|_| ())
+        }
+        None => Err(Error::Simple("The callback has no target".to_string())),
+    }
+}
+
+// Updates the callback delivery in the stored state of the request. Only the
+// delivery is changed, so a state fetched and marked deleted meanwhile stays
+// deleted, and a state that is gone is not written back.
+fn record_delivery(conf: &RelayConfig, request_id: &str, delivery: &CallbackDelivery) {
+    let result = local_db(conf).update(request_id.to_string(), |state: RequestState| {
+        Some(RequestState {
+            callback: Some(delivery.clone()),
+            ..state
+        })
+    });
+    match result {
+        Ok(Some(_)) => {}
+        Ok(None) => info!("Request {} is gone, not recording its callback delivery", request_id),
+        Err(e) => warn!("Unable to record the callback delivery of request {}: {:?}", request_id, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_config::TestConfig;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response, StatusCode};
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    // An app url answering posts with `statuses` in turn, and with the last of
+    // them once they run out. Returns the url and the bodies posted to it.
+    fn serve_app(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let app_url = format!("http://{}/callback", listener.local_addr().unwrap());
+        let posted = Arc::new(Mutex::new(vec![]));
+        let bodies = posted.clone();
+        let make_service = make_service_fn(move |_| {
+            let (posted, statuses) = (posted.clone(), statuses.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: hyper::Request<Body>| {
+                    let (posted, statuses) = (posted.clone(), statuses.clone());
+                    async move {
+                        let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let mut posted = posted.lock().unwrap();
+                        posted.push(String::from_utf8_lossy(&body).to_string());
+                        let status = statuses.get(posted.len() - 1).or(statuses.last()).copied().unwrap_or(200);
+                        Ok::<_, Infallible>(Response::builder().status(StatusCode::from_u16(status).unwrap()).body(Body::empty()).unwrap())
+                    }
+                }))
+            }
+        });
+        tokio::spawn(hyper::Server::from_tcp(listener).unwrap().serve(make_service));
+        (app_url, bodies)
+    }
+
+    fn relay_config(dir: &TestConfig) -> RelayConfig {
+        dir.relay_config("Fabric_Relay", "9080", "[callbacks]\nmax_attempts=3\nretry_backoff_msec=10\ntimeout_msec=1000\n")
+    }
+
+    fn app_url_callback(app_url: &str) -> EventPublication {
+        EventPublication {
+            publication_target: Some(event_publication::PublicationTarget::AppUrl(app_url.to_string())),
+        }
+    }
+
+    fn request_state(request_id: &str, status: request_state::Status) -> RequestState {
+        RequestState {
+            status: status as i32,
+            request_id: request_id.to_string(),
+            state: Some(request_state::State::Error("failed".to_string())),
+            relay_path: vec![],
+            callback: None,
+        }
+    }
+
+    // Stores a final state with a callback to `app_url` and has it delivered.
+    async fn deliver(conf: &RelayConfig, request_id: &str, app_url: &str) -> CallbackDelivery {
+        let db = local_db(conf);
+        db.set(&request_id.to_string(), &request_state(request_id, request_state::Status::Error)).unwrap();
+        remember_callback(&db, request_id, Some(&app_url_callback(app_url))).unwrap();
+        deliver_final_state(conf, request_id);
+        assert!(!db.has_key(get_callback_key(request_id.to_string())).unwrap(), "a callback is only used once");
+        for _ in 0..100 {
+            let delivery = db.get::<RequestState>(request_id.to_string()).unwrap().callback.unwrap();
+            if delivery.status != callback_delivery::Status::Pending as i32 {
+                return delivery;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("the state of request {} was not delivered", request_id);
+    }
+
+    #[tokio::test]
+    async fn final_states_are_posted_to_their_app_url() {
+        let (app_url, posted) = serve_app(vec![200]);
+        let dir = TestConfig::default();
+        let delivery = deliver(&relay_config(&dir), "r1", &app_url).await;
+        assert_eq!(delivery.status, callback_delivery::Status::Delivered as i32);
+        assert_eq!(delivery.attempts, 1);
+        let posted = posted.lock().unwrap();
+        assert_eq!(posted.len(), 1);
+        assert!(posted[0].contains("r1") && posted[0].contains("failed"), "{}", posted[0]);
+    }
+
+    #[tokio::test]
+    async fn rejected_posts_are_retried() {
+        let (app_url, posted) = serve_app(vec![503, 200]);
+        let dir = TestConfig::default();
+        let delivery = deliver(&relay_config(&dir), "r1", &app_url).await;
+        assert_eq!(delivery.status, callback_delivery::Status::Delivered as i32);
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.error, "");
+        assert_eq!(posted.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn delivery_gives_up_after_max_attempts() {
+        let (app_url, posted) = serve_app(vec![500]);
+        let dir = TestConfig::default();
+        let delivery = deliver(&relay_config(&dir), "r1", &app_url).await;
+        assert_eq!(delivery.status, callback_delivery::Status::Failed as i32);
+        assert_eq!(delivery.attempts, 3);
+        assert!(delivery.error.contains("500"), "{}", delivery.error);
+        assert_eq!(posted.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn states_that_are_not_final_are_not_delivered() {
+        let dir = TestConfig::default();
+        let conf = relay_config(&dir);
+        let db = local_db(&conf);
+        db.set(&"r1".to_string(), &request_state("r1", request_state::Status::Pending)).unwrap();
+        remember_callback(&db, "r1", Some(&app_url_callback("http://localhost:1/callback"))).unwrap();
+        deliver_final_state(&conf, "r1");
+        assert!(db.has_key(get_callback_key("r1".to_string())).unwrap());
+        assert_eq!(db.get::<RequestState>("r1".to_string()).unwrap().callback, None);
+    }
+
+    #[test]
+    fn deliveries_do_not_bring_back_fetched_states() {
+        let dir = TestConfig::default();
+        let conf = relay_config(&dir);
+        let db = local_db(&conf);
+        let delivered = CallbackDelivery {
+            status: callback_delivery::Status::Delivered as i32,
+            attempts: 1,
+            error: "".to_string(),
+        };
+        // The state was passed to the network while its callback was being delivered.
+        db.set(&"r1".to_string(), &request_state("r1", request_state::Status::Deleted)).unwrap();
+        record_delivery(&conf, "r1", &delivered);
+        let stored = db.get::<RequestState>("r1".to_string()).unwrap();
+        assert_eq!(stored.status, request_state::Status::Deleted as i32);
+        assert_eq!(stored.callback, Some(delivered.clone()));
+
+        record_delivery(&conf, "r2", &delivered);
+        assert!(!db.has_key("r2".to_string()).unwrap());
+    }
+
+    #[test]
+    fn retries_back_off_exponentially() {
+        let settings = CallbackSettings {
+            retry_backoff_msec: 500,
+            ..Default::default()
+        };
+        assert_eq!(settings.backoff(1), Duration::from_millis(500));
+        assert_eq!(settings.backoff(2), Duration::from_millis(1000));
+        assert_eq!(settings.backoff(4), Duration::from_millis(4000));
+        assert_eq!(settings.backoff(200), Duration::from_millis(u64::MAX));
+    }
+}
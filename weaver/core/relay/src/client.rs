@@ -2,6 +2,8 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+// Only get_url is used here, the rest of the module serves the relay.
+#[allow(dead_code)]
 mod relay_proto;
 use futures::future::{BoxFuture, FutureExt};
 use weaverpb::common::ack::ack;
@@ -41,8 +43,10 @@ async fn datasharing() -> Result<(), Box<dyn std::error::Error>> {
         requesting_org: "".to_string(),
         certificate: "test".to_string(),
         requestor_signature: "test".to_string(),
-        nonce: "test".to_string(),
+        nonce: format!("{}@{}", uuid::Uuid::new_v4(), time::SystemTime::now().duration_since(time::UNIX_EPOCH)?.as_secs()),
         confidential: false,
+        bypass_cache: false,
+        callback: None,
     });
     let response = network_client.request_state(request).await?;
     println!("RESPONSE={:?}", response);
@@ -123,6 +127,8 @@ async fn event_suscribe(driver: bool) -> Result<(), Box<dyn std::error::Error>>
         requestor_signature: "test".to_string(),
         nonce: "test".to_string(),
         confidential: false,
+        bypass_cache: false,
+        callback: None,
     };
     let event_matcher = EventMatcher {
         event_type: 0,
@@ -204,6 +210,8 @@ async fn event_unsuscribe(request_id: String, driver: bool) -> Result<(), Box<dy
         requestor_signature: "test".to_string(),
         nonce: "test".to_string(),
         confidential: false,
+        bypass_cache: false,
+        callback: None,
     };
     let event_matcher = EventMatcher {
         event_type: 0,
@@ -317,6 +325,7 @@ async fn event_publish_test(request_id: String, driver: bool) -> Result<(), Box<
         proof_type: "abc".to_string(),
         protocol: meta::Protocol::Fabric as i32,
         serialization_format: "abc".to_string(),
+        from_cache: false,
         timestamp: "abc".to_string(),
     };
     let data: Vec<u8> = vec![104, 101, 108, 108, 111];
@@ -327,10 +336,12 @@ async fn event_publish_test(request_id: String, driver: bool) -> Result<(), Box<
     let view_payload = ViewPayload {
         request_id: request_id.to_string(),
         state: Some(view_payload::State::View(view)),
+        relay_path: vec![],
     };
     let view_payload_err = ViewPayload {
         request_id: request_id.to_string(),
         state: Some(view_payload::State::Error("mock error".to_string())),
+        relay_path: vec![],
     };
     
     let response = client.send_driver_state(view_payload.clone()).await?;
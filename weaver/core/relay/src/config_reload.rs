@@ -0,0 +1,199 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::Error;
+use crate::logging::apply_logging_settings;
+use crate::registrations::Registrations;
+use crate::relay_config::RelayConfig;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+// Extensions tried by `config::File::with_name` when the name has none.
+const CONFIG_EXTENSIONS: [&str; 7] = ["toml", "json", "yaml", "yml", "ini", "hjson", "ron"];
+
+/// Loads the relay config from `config_file_name`, with settings from the
+/// environment (prefixed with `RELAY`) overriding the ones in the file.
+pub fn load_config(config_file_name: &str) -> Result<config::Config, Error> {
+    let mut settings = config::Config::default();
+    settings
+        .merge(config::File::with_name(config_file_name))?
+        // Add in settings from the environment (with a prefix of Relay) Can be used to override config file settings
+        .merge(config::Environment::with_prefix("RELAY"))?;
+    Ok(settings)
+}
+
+/// Loads the relay config from `config_file_name` with the registrations
+/// persisted in the relay db applied, and validates it, returning every
+/// problem found when it is invalid.
+pub fn load_relay_config(config_file_name: &str) -> Result<RelayConfig, Vec<String>> {
+    load_relay_config_with(config_file_name, None)
+}
+
+/// Like `load_relay_config`, applying `registrations` instead of the ones
+/// persisted in the relay db.
+pub fn load_relay_config_with(config_file_name: &str, registrations: Option<&Registrations>) -> Result<RelayConfig, Vec<String>> {
+    let conf = load_config(config_file_name).map_err(|e| vec![format!("{}: {:?}", config_file_name, e)])?;
+    RelayConfig::from_config(&conf, registrations)
+}
+
+/// Watches the relay config file and swaps a new config into `config_lock`
+/// whenever the file changes or the relay receives SIGHUP. An invalid config,
+/// or one changing a setting only read at startup, is reported and the
+/// current one is kept. Requests already being handled keep the snapshot of
+/// the config they started with.
+///
+/// The server's gRPC options are only read at startup too, but the relay's
+/// channels to other relays and drivers follow a change of them.
+pub fn spawn_config_watcher(config_file_name: String, config_lock: Arc<RwLock<RelayConfig>>, poll_interval: Duration) {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => Some(hangup),
+            Err(e) => {
+                warn!("Unable to listen for SIGHUP, config reload only follows file changes: {:?}", e);
+                None
+            }
+        };
+        #[cfg(not(unix))]
+        let mut hangup: Option<()> = None;
+        let mut last_modified = config_modified_time(&config_file_name);
+        let mut interval = tokio::time::interval(poll_interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let modified = config_modified_time(&config_file_name);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+                    info!("Config file {} changed, reloading", config_file_name);
+                }
+                Some(_) = hangup_received(&mut hangup) => {
+                    info!("Received SIGHUP, reloading config file {}", config_file_name);
+                }
+            }
+            // The lock is held while loading, so that registrations made meanwhile are not lost.
+            let mut current = config_lock.write().await;
+            match reload_relay_config(&config_file_name, &current) {
+                Ok(conf) => {
+                    apply_logging_settings(&conf.logging);
+                    *current = conf;
+                    info!("Relay config reloaded");
+                }
+                Err(problems) => warn!("Keeping current relay config, reload failed:\n  {}", problems.join("\n  ")),
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+async fn hangup_received(hangup: &mut Option<tokio::signal::unix::Signal>) -> Option<()> {
+    hangup.as_mut()?.recv().await
+}
+
+// Reloads are only triggered by file changes where there is no SIGHUP.
+#[cfg(not(unix))]
+async fn hangup_received(_hangup: &mut Option<()>) -> Option<()> {
+    std::future::pending().await
+}
+
+// Loads the config to replace `current` with, refusing changes to the
+// settings that are only read at startup.
+fn reload_relay_config(config_file_name: &str, current: &RelayConfig) -> Result<RelayConfig, Vec<String>> {
+    let conf = load_relay_config(config_file_name)?;
+    let problems = restart_only_changes(current, &conf);
+    if problems.is_empty() {
+        Ok(conf)
+    } else {
+        Err(problems)
+    }
+}
+
+// Settings that are only read at startup and that differ between the configs.
+fn restart_only_changes(current: &RelayConfig, conf: &RelayConfig) -> Vec<String> {
+    let changed = [
+        ("name", current.name != conf.name),
+        ("hostname", current.hostname != conf.hostname),
+        ("port", current.port != conf.port),
+        ("admin_hostname", current.admin_hostname != conf.admin_hostname),
+        ("admin_port", current.admin_port != conf.admin_port),
+        ("tls", current.tls != conf.tls),
+        ("cert_path", current.cert_path != conf.cert_path),
+        ("key_path", current.key_path != conf.key_path),
+        ("db_path", current.db_path != conf.db_path),
+        ("remote_db_path", current.remote_db_path != conf.remote_db_path),
+        ("config_reload_interval_msec", current.config_reload_interval_msec != conf.config_reload_interval_msec),
+        ("metrics", current.metrics != conf.metrics),
+        ("tracing", current.tracing != conf.tracing),
+    ];
+    changed
+        .iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(setting, _)| format!("{}: changing it requires a restart of the relay", setting))
+        .collect()
+}
+
+// The modification time of the file `config::File::with_name` would read.
+fn config_modified_time(config_file_name: &str) -> Option<SystemTime> {
+    let path = resolve_config_path(config_file_name)?;
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn resolve_config_path(config_file_name: &str) -> Option<PathBuf> {
+    let path = Path::new(config_file_name);
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|extension| PathBuf::from(format!("{}.{}", config_file_name, extension)))
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_config::TestConfig;
+
+    #[test]
+    fn config_path_is_resolved_with_or_without_extension() {
+        let dir = TestConfig::default();
+        let path = PathBuf::from(dir.write(""));
+        assert_eq!(resolve_config_path(path.to_str().unwrap()), Some(path.clone()));
+        assert_eq!(resolve_config_path(dir.path().join("relay").to_str().unwrap()), Some(path));
+        assert_eq!(resolve_config_path(dir.path().join("other").to_str().unwrap()), None);
+        // A directory is not a config file.
+        assert_eq!(resolve_config_path(dir.path().to_str().unwrap()), None);
+    }
+
+    #[test]
+    fn invalid_reloads_keep_the_current_config() {
+        let dir = TestConfig::default();
+        let file_name = dir.write(&dir.toml("Fabric_Relay", "9080", ""));
+        let current = load_relay_config(&file_name).unwrap();
+
+        dir.write("name=\"Fabric_Relay\"\nport=\"not a port\"\n");
+        let problems = reload_relay_config(&file_name, &current).unwrap_err();
+        assert!(problems.iter().any(|problem| problem.contains("db_path")));
+
+        dir.write(&dir.toml("Other_Relay", "9081", ""));
+        let problems = reload_relay_config(&file_name, &current).unwrap_err();
+        assert_eq!(
+            problems,
+            vec![
+                "name: changing it requires a restart of the relay".to_string(),
+                "port: changing it requires a restart of the relay".to_string(),
+            ]
+        );
+
+        dir.write(&dir.toml("Fabric_Relay", "9080", "admin_token=\"secret\"\n"));
+        assert_eq!(reload_relay_config(&file_name, &current).unwrap().admin_token, "secret");
+    }
+}
@@ -2,13 +2,28 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use bincode::Options;
 use serde::{de::DeserializeOwned, Serialize};
 use sled::{Db, open, IVec};
 use std::thread::sleep;
 use std::time;
+use std::time::Instant;
+use tracing::{error, info, warn};
 
 use crate::error::Error;
+use crate::metrics::metrics;
+
+/// Decodes a value that has to take up all of the bytes. bincode does not
+/// describe the layout of what it stores, and otherwise ignores bytes left over.
+pub fn decode_exact<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    Ok(bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(bytes)?)
+}
+
 /// Struct for managing all db interactions
+#[derive(Clone)]
 pub struct Database {
     pub db_path: String,
     pub db_open_max_retries: u32,
@@ -22,15 +37,16 @@ impl Database {
             Ok(db) => Ok(db),
             Err(error) => {
                 if retry.clone() >= self.db_open_max_retries.clone() {
-                    println!("Db open error: {:?}", error);
+                    error!("Db open error: {:?}", error);
                     return Err(Error::SledError(error));
                 }
                 let retry_error = "Resource temporarily unavailable";
                 return match error.to_string().find(retry_error) {
                     Some(_index) => {
-                        println!("Db locked temporarily with error: {:?}", error.to_string());
+                        warn!("Db locked temporarily with error: {:?}", error.to_string());
+                        metrics().record_db_open_retry();
                         sleep(time::Duration::from_millis(self.db_open_retry_backoff_msec.clone() as u64));
-                        println!("Retrying DB open attempt #{:?}...", retry.clone()+1);
+                        info!("Retrying DB open attempt #{:?}...", retry + 1);
                         let db_result = self.open_db(retry+1);
                         db_result
                     },
@@ -40,33 +56,111 @@ impl Database {
         }
     }
     pub fn set<T: Serialize>(&self, key: &String, value: &T) -> Result<Option<IVec>, Error> {
-        let req_db = self.open_db(0)?;
-        // serialises into binary to be stored in the db.
-        let encoded_value: Vec<u8> = bincode::serialize(&value).unwrap();
-        req_db
-            .insert(format!("b{}", key), encoded_value)
-            .map_err(|e| Error::SledError(e))
+        self.timed("set", || {
+            let req_db = self.open_db(0)?;
+            // serialises into binary to be stored in the db.
+            let encoded_value: Vec<u8> = bincode::serialize(&value).unwrap();
+            req_db
+                .insert(format!("b{}", key), encoded_value)
+                .map_err(Error::SledError)
+        })
     }
     pub fn get<T: DeserializeOwned>(&self, key: String) -> Result<T, Error> {
-        let req_db = self.open_db(0)?;
-        let db_value = (req_db.get(format!("b{}", key))?)
-            .ok_or_else(|| Error::Simple(format!("No value for key: {}", key)))?;
-        let decoded_result: Result<T, Error> =
-            bincode::deserialize(&db_value[..]).map_err(|e| Error::BincodeError(e));
-        decoded_result
+        self.timed("get", || {
+            let req_db = self.open_db(0)?;
+            let db_value = (req_db.get(format!("b{}", key))?)
+                .ok_or_else(|| Error::Simple(format!("No value for key: {}", key)))?;
+            let decoded_result: Result<T, Error> =
+                bincode::deserialize(&db_value[..]).map_err(Error::BincodeError);
+            decoded_result
+        })
     }
     pub fn unset<T: DeserializeOwned>(&self, key: String) -> Result<T, Error> {
-        let req_db = self.open_db(0)?;
-        let db_value = (req_db.get(format!("b{}", key))?)
-            .ok_or_else(|| Error::Simple(format!("No value for key: {}", key)))?;
-        let decoded_result: Result<T, Error> =
-            bincode::deserialize(&db_value[..]).map_err(|e| Error::BincodeError(e));
-        req_db.remove(format!("b{}", key))?;
-        decoded_result
+        self.timed("unset", || {
+            let req_db = self.open_db(0)?;
+            let db_value = (req_db.get(format!("b{}", key))?)
+                .ok_or_else(|| Error::Simple(format!("No value for key: {}", key)))?;
+            let decoded_result: Result<T, Error> =
+                bincode::deserialize(&db_value[..]).map_err(Error::BincodeError);
+            req_db.remove(format!("b{}", key))?;
+            decoded_result
+        })
+    }
+    // Replaces the value of a key with what `f` makes of it, unless the value
+    // changed in the meantime, in which case `f` is applied again. Nothing is
+    // written when the key has no value or `f` returns None.
+    pub fn update<T: Serialize + DeserializeOwned>(&self, key: String, mut f: impl FnMut(T) -> Option<T>) -> Result<Option<T>, Error> {
+        self.timed("update", || {
+            let req_db = self.open_db(0)?;
+            let db_key = format!("b{}", key);
+            loop {
+                let Some(db_value) = req_db.get(&db_key)? else {
+                    return Ok(None);
+                };
+                let Some(value) = f(bincode::deserialize(&db_value[..])?) else {
+                    return Ok(None);
+                };
+                let encoded_value: Vec<u8> = bincode::serialize(&value)?;
+                if req_db.compare_and_swap(&db_key, Some(db_value), Some(encoded_value))?.is_ok() {
+                    return Ok(Some(value));
+                }
+            }
+        })
+    }
+    pub fn remove(&self, key: String) -> Result<(), Error> {
+        self.timed("remove", || {
+            let req_db = self.open_db(0)?;
+            req_db.remove(format!("b{}", key))?;
+            Ok(())
+        })
     }
     pub fn has_key(&self, key: String) -> Result<bool, Error> {
-        let req_db = self.open_db(0)?;
-        let result = req_db.contains_key(format!("b{}", key))?;
-        Ok(result)
+        self.timed("has_key", || {
+            let req_db = self.open_db(0)?;
+            let result = req_db.contains_key(format!("b{}", key))?;
+            Ok(result)
+        })
+    }
+    // Returns all (key, value) pairs whose key starts with the given prefix.
+    pub fn scan_prefix<T: DeserializeOwned>(&self, prefix: String) -> Result<Vec<(String, T)>, Error> {
+        self.timed("scan_prefix", || {
+            let req_db = self.open_db(0)?;
+            let mut entries = Vec::new();
+            for entry in req_db.scan_prefix(format!("b{}", prefix)) {
+                let (db_key, db_value) = entry?;
+                let key = String::from_utf8_lossy(&db_key[1..]).to_string();
+                let value: T = bincode::deserialize(&db_value[..])?;
+                entries.push((key, value));
+            }
+            Ok(entries)
+        })
+    }
+    // Returns all (key, value) pairs whose key starts with the given prefix,
+    // with the values as they are stored.
+    pub fn scan_prefix_bytes(&self, prefix: String) -> Result<Vec<(String, Vec<u8>)>, Error> {
+        self.timed("scan_prefix", || {
+            let req_db = self.open_db(0)?;
+            let mut entries = Vec::new();
+            for entry in req_db.scan_prefix(format!("b{}", prefix)) {
+                let (db_key, db_value) = entry?;
+                let key = String::from_utf8_lossy(&db_key[1..]).to_string();
+                entries.push((key, db_value.to_vec()));
+            }
+            Ok(entries)
+        })
+    }
+    // Writes all buffered changes to disk.
+    pub fn flush(&self) -> Result<usize, Error> {
+        self.timed("flush", || {
+            let req_db = self.open_db(0)?;
+            Ok(req_db.flush()?)
+        })
+    }
+    // Records the duration of a db operation, including opening the db.
+    fn timed<R>(&self, operation: &str, f: impl FnOnce() -> Result<R, Error>) -> Result<R, Error> {
+        let start = Instant::now();
+        let result = f();
+        metrics().record_db_operation(operation, start.elapsed());
+        result
     }
 }
@@ -0,0 +1,382 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::Error;
+use crate::relay_config::RelayConfig;
+use crate::relay_proto::LocationSegment;
+
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use ring::signature::{UnparsedPublicKey, VerificationAlgorithm, ECDSA_P256_SHA256_ASN1, ED25519};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+// DER prefixes of the SubjectPublicKeyInfo of the supported registry signing keys.
+const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+const P256_SPKI_PREFIX: [u8; 26] = [
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d,
+    0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+/// The `[discovery]` section of the relay config.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct Discovery {
+    // Signed registry of relays shared between organizations.
+    #[serde(default)]
+    pub registry_file: String,
+    // Detached signature of the registry file, defaults to `<registry_file>.sig`.
+    #[serde(default)]
+    pub registry_signature_file: String,
+    // PEM public key of the registry signer, Ed25519 or ECDSA P-256.
+    #[serde(default)]
+    pub registry_public_key: String,
+    // Domain under which relays are published as DNS SRV records.
+    #[serde(default)]
+    pub dns_srv_domain: String,
+    #[serde(default)]
+    pub dns_tls: bool,
+    #[serde(default)]
+    pub dns_tlsca_cert_path: String,
+    // How long discovered endpoints are used before they are looked up again.
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    300
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery {
+            registry_file: "".to_string(),
+            registry_signature_file: "".to_string(),
+            registry_public_key: "".to_string(),
+            dns_srv_domain: "".to_string(),
+            dns_tls: false,
+            dns_tlsca_cert_path: "".to_string(),
+            cache_ttl_secs: default_cache_ttl_secs(),
+        }
+    }
+}
+
+impl Discovery {
+    /// Reads the `[discovery]` table. A missing table leaves discovery to the `[relays]` table.
+    pub fn from_config(conf: &config::Config) -> Result<Discovery, String> {
+        match conf.get::<Discovery>("discovery") {
+            Ok(mut settings) => {
+                if !settings.registry_file.is_empty() && settings.registry_signature_file.is_empty() {
+                    settings.registry_signature_file = format!("{}.sig", settings.registry_file);
+                }
+                Ok(settings)
+            }
+            Err(config::ConfigError::NotFound(_)) => Ok(Discovery::default()),
+            Err(e) => Err(format!("discovery: {}", e)),
+        }
+    }
+}
+
+// Registry file layout: relay names mapped to their endpoints, tried by
+// priority and then in the order listed, and the network ids they serve.
+#[derive(Clone, Default, serde::Deserialize, Debug)]
+struct Registry {
+    #[serde(default)]
+    relays: HashMap<String, RegistryEntry>,
+}
+
+#[derive(Clone, serde::Deserialize, Debug)]
+struct RegistryEntry {
+    endpoints: Vec<LocationSegment>,
+    #[serde(default)]
+    networks: Vec<String>,
+}
+
+impl RegistryEntry {
+    fn instances(&self) -> Vec<LocationSegment> {
+        let mut instances: Vec<LocationSegment> = self.endpoints.iter().flat_map(|endpoint| endpoint.instances()).collect();
+        instances.sort_by_key(|instance| instance.priority);
+        instances
+    }
+}
+
+struct LoadedRegistry {
+    path: String,
+    modified: Option<SystemTime>,
+    registry: Registry,
+}
+
+struct CachedEndpoints {
+    endpoints: Vec<LocationSegment>,
+    expires_at: Instant,
+}
+
+/// RelayDiscovery resolves relay names and network ids to relay endpoints,
+/// using the `[relays]` table first, then the signed registry file and then
+/// DNS SRV records. Discovered endpoints are cached for `cache_ttl_secs`, or
+/// the DNS record TTL when shorter, and the registry file is read again
+/// whenever it changes.
+pub struct RelayDiscovery {
+    resolver: TokioAsyncResolver,
+    registry: Mutex<Option<LoadedRegistry>>,
+    cache: Mutex<HashMap<String, CachedEndpoints>>,
+}
+
+impl RelayDiscovery {
+    pub fn new() -> RelayDiscovery {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|e| {
+            warn!("Unable to read system DNS config, using default resolver: {:?}", e);
+            TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default())
+        });
+        RelayDiscovery {
+            resolver,
+            registry: Mutex::new(None),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Resolves a relay name to its endpoints, in order of preference.
+    pub async fn resolve_relay(&self, relay_name: &str, conf: &RelayConfig) -> Result<Vec<LocationSegment>, Error> {
+        if let Some(relay) = conf.relays.get(relay_name) {
+            return Ok(relay.instances());
+        }
+        let registry = self.registry(conf)?;
+        if let Some(entry) = registry.relays.get(relay_name) {
+            return Ok(entry.instances());
+        }
+        self.resolve_srv(&format!("_weaver-relay._tcp.{}", relay_name), conf)
+            .await?
+            .ok_or(Error::Simple(format!("Unable to discover relay \"{}\"", relay_name)))
+    }
+
+    /// Resolves a network id to the endpoints of the relay serving it.
+    pub async fn resolve_network(&self, network_id: &str, conf: &RelayConfig) -> Result<Vec<LocationSegment>, Error> {
+        let registry = self.registry(conf)?;
+        let mut relay_names: Vec<&String> = registry
+            .relays
+            .iter()
+            .filter(|(_, entry)| entry.networks.iter().any(|network| network == network_id))
+            .map(|(name, _)| name)
+            .collect();
+        relay_names.sort();
+        if let Some(relay_name) = relay_names.first() {
+            return Ok(registry.relays[*relay_name].instances());
+        }
+        self.resolve_srv(&format!("_weaver-network._tcp.{}", network_id), conf)
+            .await?
+            .ok_or(Error::Simple(format!("Unable to discover a relay for network \"{}\"", network_id)))
+    }
+
+    /// Resolves the location segment of a view address. A `host:port`
+    /// location stands for all endpoints of the known relay with that
+    /// endpoint, and is used as it is otherwise, over TLS with the discovery
+    /// CA certificate if the address has the `grpcs` scheme. A location without a port
+    /// names a relay, and an empty location leaves the relay to be found by
+    /// the network id.
+    pub async fn resolve_location(
+        &self,
+        location: &LocationSegment,
+        network_id: &str,
+        conf: &RelayConfig,
+    ) -> Result<Vec<LocationSegment>, Error> {
+        if location.hostname.is_empty() {
+            return self.resolve_network(network_id, conf).await;
+        }
+        if location.port.is_empty() {
+            return self.resolve_relay(&location.hostname, conf).await;
+        }
+        let registry = self.registry(conf)?;
+        let known = conf
+            .relays
+            .values()
+            .map(|relay| relay.instances())
+            .chain(registry.relays.values().map(|entry| entry.instances()))
+            .find(|instances| instances.iter().any(|relay| relay.hostname == location.hostname && relay.port == location.port));
+        Ok(match known {
+            // A `grpcs://` address asks for TLS, with the CA certificate of the known relay.
+            Some(instances) => instances
+                .into_iter()
+                .map(|instance| LocationSegment {
+                    tls: instance.tls || location.tls,
+                    ..instance
+                })
+                .collect(),
+            None if location.tls => vec![LocationSegment {
+                tlsca_cert_path: conf.discovery.dns_tlsca_cert_path.to_string(),
+                ..location.clone()
+            }],
+            None => vec![location.clone()],
+        })
+    }
+
+    // The registry file, read again when it was modified since it was last verified.
+    fn registry(&self, conf: &RelayConfig) -> Result<Registry, Error> {
+        let settings = &conf.discovery;
+        if settings.registry_file.is_empty() {
+            return Ok(Registry::default());
+        }
+        let modified = fs::metadata(&settings.registry_file).and_then(|metadata| metadata.modified()).ok();
+        let mut loaded = self.registry.lock().unwrap();
+        if let Some(current) = loaded.as_ref() {
+            if current.path == settings.registry_file && current.modified == modified {
+                return Ok(current.registry.clone());
+            }
+        }
+        let registry = load_registry(settings)?;
+        info!("Loaded relay registry {} with {} relays", settings.registry_file, registry.relays.len());
+        // Endpoints cached from an earlier registry may be stale.
+        self.cache.lock().unwrap().clear();
+        *loaded = Some(LoadedRegistry {
+            path: settings.registry_file.to_string(),
+            modified,
+            registry: registry.clone(),
+        });
+        Ok(registry)
+    }
+
+    async fn resolve_srv(&self, service: &str, conf: &RelayConfig) -> Result<Option<Vec<LocationSegment>>, Error> {
+        let settings = &conf.discovery;
+        if settings.dns_srv_domain.is_empty() {
+            return Ok(None);
+        }
+        let name = format!("{}.{}.", service, settings.dns_srv_domain.trim_end_matches('.'));
+        if let Some(cached) = self.cache.lock().unwrap().get(&name) {
+            if cached.expires_at > Instant::now() {
+                return Ok(Some(cached.endpoints.clone()));
+            }
+        }
+        let lookup = match self.resolver.srv_lookup(name.to_string()).await {
+            Ok(lookup) => lookup,
+            Err(e) => {
+                warn!("DNS SRV lookup of {} failed: {}", name, e);
+                // Keep using what was discovered before while DNS is unavailable.
+                return Ok(self.cache.lock().unwrap().get(&name).map(|cached| cached.endpoints.clone()));
+            }
+        };
+        let mut records: Vec<_> = lookup.iter().collect();
+        records.sort_by(|a, b| a.priority().cmp(&b.priority()).then(b.weight().cmp(&a.weight())));
+        let endpoints: Vec<LocationSegment> = records
+            .iter()
+            .map(|record| LocationSegment {
+                hostname: record.target().to_utf8().trim_end_matches('.').to_string(),
+                port: record.port().to_string(),
+                tls: settings.dns_tls,
+                tlsca_cert_path: settings.dns_tlsca_cert_path.to_string(),
+                priority: record.priority() as u32,
+                endpoints: vec![],
+            })
+            .collect();
+        if endpoints.is_empty() {
+            return Ok(None);
+        }
+        let ttl = Duration::from_secs(settings.cache_ttl_secs);
+        let expires_at = lookup.as_lookup().valid_until().min(Instant::now() + ttl);
+        info!("Discovered {} via DNS: {:?}", name, endpoints);
+        self.cache.lock().unwrap().insert(name, CachedEndpoints { endpoints: endpoints.clone(), expires_at });
+        Ok(Some(endpoints))
+    }
+}
+
+// Reads the registry file, only accepting it if its signature verifies.
+fn load_registry(settings: &Discovery) -> Result<Registry, Error> {
+    let contents = fs::read(&settings.registry_file)
+        .map_err(|e| Error::Simple(format!("Unable to read relay registry {}: {:?}", settings.registry_file, e)))?;
+    let signature = fs::read(&settings.registry_signature_file).map_err(|e| {
+        Error::Simple(format!("Unable to read relay registry signature {}: {:?}", settings.registry_signature_file, e))
+    })?;
+    let public_key = fs::read_to_string(&settings.registry_public_key).map_err(|e| {
+        Error::Simple(format!("Unable to read relay registry public key {}: {:?}", settings.registry_public_key, e))
+    })?;
+    verify_signature(&contents, &signature, &public_key)
+        .map_err(|e| Error::Simple(format!("Relay registry {} is not trusted: {}", settings.registry_file, e)))?;
+    serde_json::from_slice(&contents)
+        .map_err(|e| Error::Simple(format!("Relay registry {} is not well-formatted: {:?}", settings.registry_file, e)))
+}
+
+fn verify_signature(contents: &[u8], signature: &[u8], public_key_pem: &str) -> Result<(), String> {
+    let der_base64: String = public_key_pem
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .map(|line| line.trim())
+        .collect();
+    let der = base64::decode(der_base64).map_err(|e| format!("public key is not a PEM public key: {:?}", e))?;
+    let (algorithm, key): (&'static dyn VerificationAlgorithm, &[u8]) = if der.starts_with(&ED25519_SPKI_PREFIX) {
+        (&ED25519, &der[ED25519_SPKI_PREFIX.len()..])
+    } else if der.starts_with(&P256_SPKI_PREFIX) {
+        (&ECDSA_P256_SHA256_ASN1, &der[P256_SPKI_PREFIX.len()..])
+    } else {
+        return Err("public key is neither an Ed25519 nor an ECDSA P-256 key".to_string());
+    };
+    UnparsedPublicKey::new(algorithm, key)
+        .verify(contents, signature)
+        .map_err(|_| "signature does not match the registry signer's public key".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    fn signer() -> (Ed25519KeyPair, String) {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let mut der = ED25519_SPKI_PREFIX.to_vec();
+        der.extend_from_slice(key_pair.public_key().as_ref());
+        let pem = format!("-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n", base64::encode(der));
+        (key_pair, pem)
+    }
+
+    #[test]
+    fn registry_signature_is_verified() {
+        let (key_pair, pem) = signer();
+        let contents = br#"{"relays": {}}"#;
+        let signature = key_pair.sign(contents);
+        assert!(verify_signature(contents, signature.as_ref(), &pem).is_ok());
+        assert!(verify_signature(br#"{"relays": {"Evil_Relay": {}}}"#, signature.as_ref(), &pem).is_err());
+        let (_, other_pem) = signer();
+        assert!(verify_signature(contents, signature.as_ref(), &other_pem).is_err());
+    }
+
+    #[tokio::test]
+    async fn relays_and_networks_are_resolved_from_registry() {
+        let dir = std::env::temp_dir().join(format!("relay-registry-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let (key_pair, pem) = signer();
+        let contents = br#"{"relays": {"Corda_Relay": {
+            "endpoints": [{"hostname": "relay.corda.example.org", "port": "9081", "tls": true, "tlsca_cert_path": "ca.pem"}],
+            "networks": ["Corda_Network"]}}}"#;
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        fs::write(path("registry.json"), contents).unwrap();
+        fs::write(path("registry.json.sig"), key_pair.sign(contents).as_ref()).unwrap();
+        fs::write(path("signer.pem"), pem).unwrap();
+
+        let mut conf = config::Config::default();
+        conf.merge(config::File::from_str(
+            &format!(
+                "name=\"Fabric_Relay\"\nport=\"9080\"\ndb_path=\"db/requests\"\nremote_db_path=\"db/remote_request\"\n\
+                 [discovery]\nregistry_file=\"{}\"\nregistry_public_key=\"{}\"\n",
+                path("registry.json"),
+                path("signer.pem")
+            ),
+            config::FileFormat::Toml,
+        ))
+        .unwrap();
+        let conf = RelayConfig::from_config(&conf, Some(&Default::default())).unwrap();
+        let discovery = RelayDiscovery::new();
+
+        let relay = discovery.resolve_relay("Corda_Relay", &conf).await.unwrap();
+        assert_eq!(relay[0].hostname, "relay.corda.example.org");
+        assert!(relay[0].tls);
+        let network = discovery.resolve_network("Corda_Network", &conf).await.unwrap();
+        assert_eq!(network, relay);
+        let location = LocationSegment { hostname: "relay.corda.example.org".to_string(), port: "9081".to_string(), tls: false, tlsca_cert_path: "".to_string(), priority: 0, endpoints: vec![] };
+        assert_eq!(discovery.resolve_location(&location, "Corda_Network", &conf).await.unwrap(), relay);
+        assert!(discovery.resolve_relay("Unknown_Relay", &conf).await.is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::driver::driver::driver_communication_client::DriverCommunicationClient;
+
+use crate::error::Error;
+use crate::grpc_options::GrpcOptions;
+use crate::services::helpers::get_driver_client;
+use crate::services::types::{Driver, DriverEndpoint, LEAST_LOADED};
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+use tracing::{info, warn};
+
+/// Tracks the instances of each driver: the requests they are working on
+/// and whether they could be reached, so that requests go to a healthy,
+/// lightly loaded instance.
+pub struct DriverPool {
+    drivers: Mutex<HashMap<String, DriverInstances>>,
+    // The driver and instance each outstanding request was sent to.
+    assignments: Mutex<HashMap<String, (String, String)>>,
+}
+
+#[derive(Default)]
+struct DriverInstances {
+    // Rotates the instance tried first.
+    next: usize,
+    instances: HashMap<String, InstanceState>,
+}
+
+#[derive(Default)]
+struct InstanceState {
+    outstanding: usize,
+    ejected_until: Option<Instant>,
+}
+
+static DRIVER_POOL: OnceLock<DriverPool> = OnceLock::new();
+
+/// The relay's driver pool, created on first use.
+pub fn driver_pool() -> &'static DriverPool {
+    DRIVER_POOL.get_or_init(DriverPool::new)
+}
+
+impl DriverPool {
+    fn new() -> DriverPool {
+        DriverPool {
+            drivers: Mutex::new(HashMap::new()),
+            assignments: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The instances of a driver in the order a request tries them. Instances
+    /// that were recently unreachable come last, as a last resort.
+    pub fn candidates(&self, driver_name: &str, driver: &Driver) -> Vec<DriverEndpoint> {
+        self.candidates_at(driver_name, driver, Instant::now())
+    }
+
+    fn candidates_at(&self, driver_name: &str, driver: &Driver, now: Instant) -> Vec<DriverEndpoint> {
+        let mut drivers = self.drivers.lock().unwrap();
+        let pool = drivers.entry(driver_name.to_string()).or_default();
+        let mut instances = driver.instances();
+        let len = instances.len();
+        instances.rotate_left(pool.next % len);
+        pool.next = pool.next.wrapping_add(1);
+        let state = |instance: &DriverEndpoint| pool.instances.get(&instance.address());
+        let (mut healthy, mut ejected): (Vec<_>, Vec<_>) = instances
+            .into_iter()
+            .partition(|instance| !matches!(state(instance).and_then(|s| s.ejected_until), Some(until) if until > now));
+        if driver.selection == LEAST_LOADED {
+            healthy.sort_by_key(|instance| state(instance).map_or(0, |s| s.outstanding));
+        }
+        ejected.sort_by_key(|instance| state(instance).and_then(|s| s.ejected_until));
+        healthy.extend(ejected);
+        healthy
+    }
+
+    /// Passes over an instance for the driver's `ejection_msec`.
+    pub fn eject(&self, driver_name: &str, driver: &Driver, instance: &DriverEndpoint) {
+        let mut drivers = self.drivers.lock().unwrap();
+        let state = drivers
+            .entry(driver_name.to_string())
+            .or_default()
+            .instances
+            .entry(instance.address())
+            .or_default();
+        if state.ejected_until.is_none() {
+            warn!("Driver {} instance {} is unreachable, passing over it", driver_name, instance.address());
+        }
+        state.ejected_until = Some(Instant::now() + Duration::from_millis(driver.ejection_msec));
+    }
+
+    /// Takes an instance back once it has been reached.
+    pub fn restore(&self, driver_name: &str, instance: &DriverEndpoint) {
+        let mut drivers = self.drivers.lock().unwrap();
+        let state = drivers.get_mut(driver_name).and_then(|pool| pool.instances.get_mut(&instance.address()));
+        if let Some(state) = state {
+            if state.ejected_until.take().is_some() {
+                info!("Driver {} instance {} is reachable again", driver_name, instance.address());
+            }
+        }
+    }
+
+    /// Counts a request towards the load of the instance it was sent to until
+    /// it is released.
+    pub fn assign(&self, request_id: &str, driver_name: &str, instance: &DriverEndpoint) {
+        let mut drivers = self.drivers.lock().unwrap();
+        let state = drivers
+            .entry(driver_name.to_string())
+            .or_default()
+            .instances
+            .entry(instance.address())
+            .or_default();
+        state.outstanding += 1;
+        self.assignments
+            .lock()
+            .unwrap()
+            .insert(request_id.to_string(), (driver_name.to_string(), instance.address()));
+    }
+
+    /// Releases a request once its driver has sent the result back.
+    pub fn release(&self, request_id: &str) {
+        let assignment = self.assignments.lock().unwrap().remove(request_id);
+        if let Some((driver_name, address)) = assignment {
+            let mut drivers = self.drivers.lock().unwrap();
+            let state = drivers.get_mut(&driver_name).and_then(|pool| pool.instances.get_mut(&address));
+            if let Some(state) = state {
+                state.outstanding = state.outstanding.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// Makes a call to a driver, failing over to its next instance while an
+/// instance cannot be reached. Returns the instance that took the call.
+pub async fn call_driver<T, F, Fut>(
+    driver_name: &str,
+    driver: &Driver,
+    grpc_options: &GrpcOptions,
+    call: F,
+) -> Result<(T, DriverEndpoint), Error>
+where
+    F: FnMut(DriverCommunicationClient<Channel>) -> Fut,
+    Fut: Future<Output = Result<T, tonic::Status>>,
+{
+    call_instances(driver_name, driver, grpc_options, None, call).await
+}
+
+/// Makes the call handing a request to a driver, like `call_driver`. The
+/// request counts towards the load of each instance from before it is sent
+/// to it, since the instance may send the result back before its reply to
+/// the call arrives, and stops counting when the call fails.
+pub async fn call_driver_for_request<T, F, Fut>(
+    request_id: &str,
+    driver_name: &str,
+    driver: &Driver,
+    grpc_options: &GrpcOptions,
+    call: F,
+) -> Result<(T, DriverEndpoint), Error>
+where
+    F: FnMut(DriverCommunicationClient<Channel>) -> Fut,
+    Fut: Future<Output = Result<T, tonic::Status>>,
+{
+    call_instances(driver_name, driver, grpc_options, Some(request_id), call).await
+}
+
+async fn call_instances<T, F, Fut>(
+    driver_name: &str,
+    driver: &Driver,
+    grpc_options: &GrpcOptions,
+    request_id: Option<&str>,
+    mut call: F,
+) -> Result<(T, DriverEndpoint), Error>
+where
+    F: FnMut(DriverCommunicationClient<Channel>) -> Fut,
+    Fut: Future<Output = Result<T, tonic::Status>>,
+{
+    let mut last_error = None;
+    for instance in driver_pool().candidates(driver_name, driver) {
+        if let Some(request_id) = request_id {
+            driver_pool().assign(request_id, driver_name, &instance);
+        }
+        let result = match get_driver_client(&instance, grpc_options).await {
+            Ok(client) => call(client).await.map_err(Error::from),
+            Err(e) => Err(e),
+        };
+        if let (Some(request_id), Err(_)) = (request_id, &result) {
+            driver_pool().release(request_id);
+        }
+        match result {
+            Ok(reply) => {
+                driver_pool().restore(driver_name, &instance);
+                return Ok((reply, instance));
+            }
+            Err(e) if e.is_unreachable() => {
+                warn!("Driver {} instance {} could not be reached: {:?}", driver_name, instance.address(), e);
+                driver_pool().eject(driver_name, driver, &instance);
+                last_error = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| Error::Simple(format!("Driver {} has no instances", driver_name))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::types::{default_ejection_msec, ROUND_ROBIN};
+
+    fn driver(selection: &str) -> Driver {
+        let endpoint = |port: &str| DriverEndpoint {
+            port: port.to_string(),
+            hostname: "localhost".to_string(),
+            tls: false,
+            tlsca_cert_path: "".to_string(),
+        };
+        Driver {
+            port: "9090".to_string(),
+            hostname: "localhost".to_string(),
+            tls: false,
+            tlsca_cert_path: "".to_string(),
+            endpoints: vec![endpoint("9091"), endpoint("9092")],
+            selection: selection.to_string(),
+            ejection_msec: default_ejection_msec(),
+        }
+    }
+
+    fn ports(candidates: Vec<DriverEndpoint>) -> Vec<String> {
+        candidates.into_iter().map(|instance| instance.port).collect()
+    }
+
+    #[test]
+    fn instances_are_selected_by_policy_and_health() {
+        let pool = DriverPool::new();
+        let now = Instant::now();
+        let round_robin = driver(ROUND_ROBIN);
+        assert_eq!(ports(pool.candidates_at("Fabric", &round_robin, now)), vec!["9090", "9091", "9092"]);
+        assert_eq!(ports(pool.candidates_at("Fabric", &round_robin, now)), vec!["9091", "9092", "9090"]);
+
+        // Unreachable instances are tried last until their ejection runs out.
+        let instances = round_robin.instances();
+        pool.eject("Fabric", &round_robin, &instances[2]);
+        assert_eq!(ports(pool.candidates_at("Fabric", &round_robin, now)), vec!["9090", "9091", "9092"]);
+        let later = now + Duration::from_millis(round_robin.ejection_msec + 1000);
+        assert_eq!(ports(pool.candidates_at("Fabric", &round_robin, later)), vec!["9090", "9091", "9092"]);
+        pool.restore("Fabric", &instances[2]);
+        assert_eq!(ports(pool.candidates_at("Fabric", &round_robin, now)), vec!["9091", "9092", "9090"]);
+
+        let least_loaded = driver(LEAST_LOADED);
+        pool.assign("r1", "Corda", &instances[0]);
+        pool.assign("r2", "Corda", &instances[0]);
+        pool.assign("r3", "Corda", &instances[1]);
+        assert_eq!(ports(pool.candidates_at("Corda", &least_loaded, now)), vec!["9092", "9091", "9090"]);
+        pool.release("r1");
+        pool.release("r2");
+        assert_eq!(ports(pool.candidates_at("Corda", &least_loaded, now)), vec!["9092", "9090", "9091"]);
+    }
+
+    #[tokio::test]
+    async fn failed_calls_leave_no_load_behind() {
+        let mut unreachable = driver(LEAST_LOADED);
+        unreachable.port = "1".to_string();
+        unreachable.endpoints.clear();
+        let result = call_driver_for_request("r-unreachable", "Unreachable", &unreachable, &GrpcOptions::default(), |mut client| async move {
+            client.request_driver_state(weaverpb::common::query::Query::default()).await
+        })
+        .await;
+        assert!(result.is_err());
+        assert!(!driver_pool().assignments.lock().unwrap().contains_key("r-unreachable"));
+        let drivers = driver_pool().drivers.lock().unwrap();
+        assert_eq!(drivers["Unreachable"].instances[&unreachable.instances()[0].address()].outstanding, 0);
+    }
+}
@@ -2,6 +2,10 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
+use crate::relay_proto::AddressError;
+
+use std::fmt;
+
 /// Custom error type for handling various external library errors.
 /// the 'From' trait is converting different error types into our Custom error type automatically
 /// when using the ? operator
@@ -12,10 +16,31 @@ pub enum Error {
     Simple(String),
     GetQuery(String),
     TransportError(tonic::transport::Error),
-    StatusError(tonic::Status),
+    // Boxed, as a Status is several times larger than the other variants.
+    StatusError(Box<tonic::Status>),
     SledError(sled::Error),
     ConfigError(config::ConfigError),
     ReqwestError(reqwest::Error),
+    AccessDenied(String),
+    Routing(String),
+    Address(AddressError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::LoadError(e) => write!(f, "{}", e),
+            Error::BincodeError(e) => write!(f, "{}", e),
+            Error::Simple(message) | Error::GetQuery(message) => write!(f, "{}", message),
+            Error::TransportError(e) => write!(f, "{}", e),
+            Error::StatusError(status) => write!(f, "{}", status),
+            Error::SledError(e) => write!(f, "{}", e),
+            Error::ConfigError(e) => write!(f, "{}", e),
+            Error::ReqwestError(e) => write!(f, "{}", e),
+            Error::AccessDenied(message) | Error::Routing(message) => write!(f, "{}", message),
+            Error::Address(e) => write!(f, "{}", e),
+        }
+    }
 }
 
 impl From<std::io::Error> for Error {
@@ -48,7 +73,13 @@ impl From<tonic::transport::Error> for Error {
 
 impl From<tonic::Status> for Error {
     fn from(e: tonic::Status) -> Self {
-        Error::StatusError(e)
+        Error::StatusError(Box::new(e))
+    }
+}
+
+impl From<AddressError> for Error {
+    fn from(e: AddressError) -> Self {
+        Error::Address(e)
     }
 }
 
@@ -57,3 +88,16 @@ impl From<reqwest::Error> for Error {
         Error::ReqwestError(e)
     }
 }
+
+impl Error {
+    /// Whether a driver or relay could not be reached, after which a call can
+    /// be tried on another of its endpoints. Errors the peer replied with
+    /// would be the same on every endpoint.
+    pub fn is_unreachable(&self) -> bool {
+        match self {
+            Error::TransportError(_) | Error::LoadError(_) => true,
+            Error::StatusError(status) => status.code() == tonic::Code::Unavailable,
+            _ => false,
+        }
+    }
+}
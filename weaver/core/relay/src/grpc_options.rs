@@ -0,0 +1,256 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::common::state::{view_payload, ViewPayload};
+use weaverpb::driver::driver::driver_communication_client::DriverCommunicationClient;
+use weaverpb::networks::networks::network_server::NetworkServer;
+use weaverpb::relay::admin::relay_admin_server::RelayAdminServer;
+use weaverpb::relay::datatransfer::data_transfer_client::DataTransferClient;
+use weaverpb::relay::datatransfer::data_transfer_server::DataTransferServer;
+use weaverpb::relay::events::event_publish_client::EventPublishClient;
+use weaverpb::relay::events::event_publish_server::EventPublishServer;
+use weaverpb::relay::events::event_subscribe_client::EventSubscribeClient;
+use weaverpb::relay::events::event_subscribe_server::EventSubscribeServer;
+
+use crate::services::admin_service::AdminService;
+use crate::services::data_transfer_service::DataTransferService;
+use crate::services::event_publish_service::EventPublishService;
+use crate::services::event_subscribe_service::EventSubscribeService;
+use crate::services::network_service::NetworkService;
+
+use tonic::codec::CompressionEncoding;
+use prost::Message;
+use tonic::transport::Channel;
+use tracing::warn;
+
+// tonic's limit on the messages a channel decodes, which peers apply unless they are configured otherwise.
+const DEFAULT_MAX_DECODING_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+// The `[grpc]` section of the relay config as it is written in the file.
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize, Debug)]
+struct GrpcSettings {
+    compression: Option<String>,
+    max_decoding_message_size: Option<usize>,
+    max_encoding_message_size: Option<usize>,
+}
+
+/// Compression and message size limits applied to every gRPC channel of the
+/// relay: the relay's own server, and its clients to other relays and drivers.
+/// Limits that are not configured keep tonic's defaults, i.e. 4 MiB for
+/// decoding and no limit for encoding.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct GrpcOptions {
+    pub compression: Option<CompressionEncoding>,
+    pub max_decoding_message_size: Option<usize>,
+    pub max_encoding_message_size: Option<usize>,
+}
+
+impl GrpcOptions {
+    /// Reads the `[grpc]` table. A missing table keeps tonic's defaults and sends messages uncompressed.
+    pub fn from_config(conf: &config::Config) -> Result<GrpcOptions, String> {
+        let settings = match conf.get::<GrpcSettings>("grpc") {
+            Ok(settings) => settings,
+            Err(config::ConfigError::NotFound(_)) => GrpcSettings::default(),
+            Err(e) => return Err(format!("grpc: {}", e)),
+        };
+        let compression = match settings.compression.as_deref() {
+            None | Some("none") => None,
+            Some("gzip") => Some(CompressionEncoding::Gzip),
+            Some("zstd") => Some(CompressionEncoding::Zstd),
+            Some(other) => {
+                return Err(format!("grpc.compression: unsupported compression \"{}\", expected one of gzip, zstd or none", other));
+            }
+        };
+        Ok(GrpcOptions {
+            compression,
+            max_decoding_message_size: settings.max_decoding_message_size,
+            max_encoding_message_size: settings.max_encoding_message_size,
+        })
+    }
+
+    /// Returns an error message when a message of `encoded_len` bytes is too
+    /// large to be sent with the configured `max_encoding_message_size`.
+    pub fn check_encoding_size(&self, encoded_len: usize) -> Result<(), String> {
+        match self.max_encoding_message_size {
+            Some(limit) if encoded_len > limit => Err(format!(
+                "Message of {} bytes exceeds the relay's maximum gRPC message size of {} bytes (grpc.max_encoding_message_size)",
+                encoded_len, limit
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Largest message the relay sends when it chooses how much to put in
+    /// it, e.g. a batch of queries: the configured `max_encoding_message_size`,
+    /// or else the 4 MiB that peers decode by default.
+    pub fn max_message_size(&self) -> usize {
+        self.max_encoding_message_size.unwrap_or(DEFAULT_MAX_DECODING_MESSAGE_SIZE)
+    }
+
+    /// Splits the items of a repeated message field, in order, into as few
+    /// messages as fit within `max_message_size`. An item too large on its
+    /// own is sent alone, and fails.
+    pub fn split_to_fit<T>(&self, items: Vec<T>, encoded_len: impl Fn(&T) -> usize) -> Vec<Vec<T>> {
+        let limit = self.max_message_size();
+        let mut messages: Vec<Vec<T>> = Vec::new();
+        let mut message_len = 0;
+        for item in items {
+            let len = encoded_len(&item);
+            // Each item is prefixed by its field tag and length.
+            let len = 1 + prost::encoding::encoded_len_varint(len as u64) + len;
+            match messages.last_mut() {
+                Some(message) if message_len + len <= limit => message.push(item),
+                _ => {
+                    messages.push(vec![item]);
+                    message_len = 0;
+                }
+            }
+            message_len += len;
+        }
+        messages
+    }
+}
+
+// Written back the way it is configured, e.g. when the config is shown to an operator.
+impl serde::Serialize for GrpcOptions {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let compression = match self.compression {
+            Some(CompressionEncoding::Gzip) => "gzip",
+            Some(CompressionEncoding::Zstd) => "zstd",
+            _ => "none",
+        };
+        let settings = GrpcSettings {
+            compression: Some(compression.to_string()),
+            max_decoding_message_size: self.max_decoding_message_size,
+            max_encoding_message_size: self.max_encoding_message_size,
+        };
+        serde::Serialize::serialize(&settings, serializer)
+    }
+}
+
+/// Applies `GrpcOptions` to generated gRPC clients and servers.
+pub trait WithGrpcOptions {
+    fn with_grpc_options(self, options: &GrpcOptions) -> Self;
+}
+
+// The generated clients and servers share these builder methods but no trait.
+macro_rules! impl_with_grpc_options {
+    ($($service:ty),* $(,)?) => {
+        $(
+            impl WithGrpcOptions for $service {
+                fn with_grpc_options(self, options: &GrpcOptions) -> Self {
+                    // Compressed messages are always accepted, so that peers can choose compression independently.
+                    let mut service = self
+                        .accept_compressed(CompressionEncoding::Gzip)
+                        .accept_compressed(CompressionEncoding::Zstd);
+                    if let Some(encoding) = options.compression {
+                        service = service.send_compressed(encoding);
+                    }
+                    if let Some(limit) = options.max_decoding_message_size {
+                        service = service.max_decoding_message_size(limit);
+                    }
+                    if let Some(limit) = options.max_encoding_message_size {
+                        service = service.max_encoding_message_size(limit);
+                    }
+                    service
+                }
+            }
+        )*
+    };
+}
+
+impl_with_grpc_options!(
+    DataTransferClient<Channel>,
+    EventSubscribeClient<Channel>,
+    EventPublishClient<Channel>,
+    DriverCommunicationClient<Channel>,
+    DataTransferServer<DataTransferService>,
+    EventSubscribeServer<EventSubscribeService>,
+    EventPublishServer<EventPublishService>,
+    NetworkServer<NetworkService>,
+    RelayAdminServer<AdminService>,
+);
+
+/// Replaces a view payload that is too large to be sent with the configured
+/// `max_encoding_message_size` by an error explaining why it was not delivered.
+pub fn limit_view_payload(state: ViewPayload, options: &GrpcOptions) -> ViewPayload {
+    match options.check_encoding_size(state.encoded_len()) {
+        Ok(()) => state,
+        Err(reason) => {
+            warn!("View payload for request {} is too large to be sent: {}", state.request_id, reason);
+            ViewPayload {
+                relay_path: state.relay_path,
+                ..message_too_large_payload(state.request_id, reason)
+            }
+        }
+    }
+}
+
+/// Error payload sent in place of a view the receiving side could not accept because of its size.
+pub fn message_too_large_payload(request_id: String, reason: String) -> ViewPayload {
+    ViewPayload {
+        request_id,
+        state: Some(view_payload::State::Error(format!("View payload is too large: {}", reason))),
+        relay_path: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use weaverpb::common::query::Query;
+    use weaverpb::relay::datatransfer::QueryBatch;
+
+    fn grpc_conf(grpc_table: &str) -> config::Config {
+        let mut conf = config::Config::default();
+        conf.merge(config::File::from_str(grpc_table, config::FileFormat::Toml)).unwrap();
+        conf
+    }
+
+    #[test]
+    fn options_are_read_from_grpc_table() {
+        assert_eq!(GrpcOptions::from_config(&grpc_conf("")), Ok(GrpcOptions::default()));
+        let options = GrpcOptions::from_config(&grpc_conf(
+            "[grpc]\ncompression=\"zstd\"\nmax_decoding_message_size=16777216\nmax_encoding_message_size=100",
+        ))
+        .unwrap();
+        assert_eq!(options.compression, Some(CompressionEncoding::Zstd));
+        assert_eq!(options.max_decoding_message_size, Some(16777216));
+        assert!(options.check_encoding_size(100).is_ok());
+        assert!(options.check_encoding_size(101).is_err());
+        assert!(GrpcOptions::from_config(&grpc_conf("[grpc]\ncompression=\"brotli\"")).is_err());
+    }
+
+    #[test]
+    fn oversized_view_payload_is_replaced_by_error() {
+        let options = GrpcOptions { max_encoding_message_size: Some(16), ..Default::default() };
+        let state = ViewPayload {
+            request_id: "request".to_string(),
+            state: Some(view_payload::State::Error("a very long error message".to_string())),
+            relay_path: vec!["A".to_string(), "B".to_string()],
+        };
+        let limited = limit_view_payload(state, &options);
+        match limited.state {
+            Some(view_payload::State::Error(e)) => assert!(e.starts_with("View payload is too large")),
+            other => panic!("unexpected state {:?}", other),
+        }
+        assert_eq!(limited.relay_path, vec!["A", "B"]);
+    }
+
+    #[test]
+    fn batches_are_split_to_fit_the_message_size() {
+        let options = GrpcOptions { max_encoding_message_size: Some(1000), ..Default::default() };
+        let query = |size: usize| Query { address: "a".repeat(size), ..Default::default() };
+        let queries = vec![query(400), query(400), query(400), query(2000), query(10)];
+        let batches = options.split_to_fit(queries, |query| query.encoded_len());
+        assert_eq!(batches.iter().map(|batch| batch.len()).collect::<Vec<_>>(), vec![2, 1, 1, 1]);
+        for batch in batches.iter().filter(|batch| batch[0].address.len() < 2000) {
+            let len = QueryBatch { queries: batch.clone() }.encoded_len();
+            assert!(len <= 1000, "batch of {} bytes", len);
+        }
+        let batches = options.split_to_fit(vec![query(10), query(10), query(10)], |query| query.encoded_len());
+        assert_eq!(batches.len(), 1);
+        assert_eq!(GrpcOptions::default().max_message_size(), 4 * 1024 * 1024);
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::db::Database;
+use crate::driver_pool::driver_pool;
+use crate::relay_config::RelayConfig;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::RwLock;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::info;
+
+/// Health service name under which the reachability of each driver is
+/// reported, followed by the driver name.
+pub const DRIVER_SERVICE_PREFIX: &str = "relay.driver.";
+
+/// The `[health]` section of the relay config.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct HealthChecks {
+    // How often the relay's databases and drivers are probed.
+    #[serde(default = "default_probe_interval_msec")]
+    pub probe_interval_msec: u64,
+    // How long a driver may take to accept a connection before it counts as unreachable.
+    #[serde(default = "default_driver_timeout_msec")]
+    pub driver_timeout_msec: u64,
+}
+
+fn default_probe_interval_msec() -> u64 {
+    5000
+}
+
+fn default_driver_timeout_msec() -> u64 {
+    2000
+}
+
+impl Default for HealthChecks {
+    fn default() -> Self {
+        HealthChecks {
+            probe_interval_msec: default_probe_interval_msec(),
+            driver_timeout_msec: default_driver_timeout_msec(),
+        }
+    }
+}
+
+impl HealthChecks {
+    /// Reads the `[health]` table, defaults apply when it is missing.
+    pub fn from_config(conf: &config::Config) -> Result<HealthChecks, String> {
+        let settings = match conf.get::<HealthChecks>("health") {
+            Ok(settings) => settings,
+            Err(config::ConfigError::NotFound(_)) => HealthChecks::default(),
+            Err(e) => return Err(format!("health: {}", e)),
+        };
+        if settings.probe_interval_msec == 0 || settings.driver_timeout_msec == 0 {
+            return Err("health: probe_interval_msec and driver_timeout_msec must be greater than 0".to_string());
+        }
+        Ok(settings)
+    }
+}
+
+/// What a gRPC service of the relay needs to be able to serve requests.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Readiness {
+    // The relay databases can be opened.
+    Database,
+    // The relay databases can be opened and an instance of every configured driver is reachable.
+    DatabaseAndDrivers,
+    // The relay databases can be opened, and an admin token and the admin service's port are configured,
+    // without which the service refuses every call or is not served at all.
+    DatabaseAndAdminToken,
+}
+
+impl Readiness {
+    fn is_ready(&self, db_ok: bool, drivers_ok: bool, conf: &RelayConfig) -> bool {
+        match self {
+            Readiness::Database => db_ok,
+            Readiness::DatabaseAndDrivers => db_ok && drivers_ok,
+            Readiness::DatabaseAndAdminToken => db_ok && !conf.admin_token.is_empty() && !conf.admin_port.is_empty(),
+        }
+    }
+}
+
+/// Probes the relay databases and drivers in the background and reports the
+/// result through the standard gRPC health service. Each of `services` is
+/// reported according to its readiness, the relay as a whole (the empty
+/// service name) once everything is ready, and each driver under
+/// `relay.driver.<driver-name>`.
+pub fn spawn_health_prober(
+    mut reporter: HealthReporter,
+    config_lock: Arc<RwLock<RelayConfig>>,
+    services: Vec<(&'static str, Readiness)>,
+) {
+    tokio::spawn(async move {
+        let mut last_statuses: HashMap<String, ServingStatus> = HashMap::new();
+        loop {
+            let conf = config_lock.read().await.clone();
+            // Opening the dbs may wait on a lock held by a request, off the async workers.
+            let db_conf = conf.clone();
+            let db_ok = tokio::task::spawn_blocking(move || databases_open(&db_conf)).await.unwrap_or(false);
+            let mut drivers_ok = true;
+            let mut statuses: HashMap<String, ServingStatus> = HashMap::new();
+            for (driver_name, driver) in conf.drivers.iter() {
+                // A driver is reachable while any of its instances is, the others are passed over.
+                let mut reachable = false;
+                for instance in driver.instances() {
+                    if tcp_reachable(&instance.hostname, &instance.port, conf.health.driver_timeout_msec).await {
+                        driver_pool().restore(driver_name, &instance);
+                        reachable = true;
+                    } else {
+                        driver_pool().eject(driver_name, driver, &instance);
+                    }
+                }
+                drivers_ok &= reachable;
+                statuses.insert(format!("{}{}", DRIVER_SERVICE_PREFIX, driver_name), serving_status(reachable));
+            }
+            for (service_name, readiness) in services.iter() {
+                statuses.insert(service_name.to_string(), serving_status(readiness.is_ready(db_ok, drivers_ok, &conf)));
+            }
+            statuses.insert("".to_string(), serving_status(db_ok && drivers_ok));
+
+            for (service_name, status) in statuses.iter() {
+                if last_statuses.get(service_name) != Some(status) {
+                    info!("Health: {} is {:?}", if service_name.is_empty() { "relay" } else { service_name }, status);
+                }
+                reporter.set_service_status(service_name, *status).await;
+            }
+            // Drivers removed from the config are no longer reported.
+            for service_name in last_statuses.keys().filter(|name| !statuses.contains_key(*name)) {
+                info!("Health: {} is no longer checked", service_name);
+                reporter.clear_service_status(service_name).await;
+            }
+            last_statuses = statuses;
+            tokio::time::sleep(Duration::from_millis(conf.health.probe_interval_msec)).await;
+        }
+    });
+}
+
+fn serving_status(ready: bool) -> ServingStatus {
+    if ready {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    }
+}
+
+fn databases_open(conf: &RelayConfig) -> bool {
+    [conf.db_path.to_string(), conf.remote_db_path.to_string()].into_iter().all(|db_path| {
+        let db = Database {
+            db_path,
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        db.open_db(0).is_ok()
+    })
+}
+
+async fn tcp_reachable(hostname: &str, port: &str, timeout_msec: u64) -> bool {
+    let connect = TcpStream::connect(format!("{}:{}", hostname, port));
+    matches!(tokio::time::timeout(Duration::from_millis(timeout_msec), connect).await, Ok(Ok(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health_conf(health_table: &str) -> config::Config {
+        let mut conf = config::Config::default();
+        conf.merge(config::File::from_str(health_table, config::FileFormat::Toml)).unwrap();
+        conf
+    }
+
+    #[test]
+    fn settings_are_read_from_health_table() {
+        assert_eq!(HealthChecks::from_config(&health_conf("")), Ok(HealthChecks::default()));
+        let settings = HealthChecks::from_config(&health_conf("[health]\nprobe_interval_msec=1000")).unwrap();
+        assert_eq!(settings.probe_interval_msec, 1000);
+        assert_eq!(settings.driver_timeout_msec, 2000);
+        assert!(HealthChecks::from_config(&health_conf("[health]\ndriver_timeout_msec=0")).is_err());
+    }
+
+    #[test]
+    fn admin_service_is_not_serving_without_a_token() {
+        let toml = "name=\"Fabric_Relay\"\nport=\"9080\"\ndb_path=\"db/requests\"\nremote_db_path=\"db/remote_request\"\n";
+        let mut conf = RelayConfig::from_config(&health_conf(toml), Some(&Default::default())).unwrap();
+        assert!(!Readiness::DatabaseAndAdminToken.is_ready(true, true, &conf));
+        assert!(Readiness::Database.is_ready(true, false, &conf));
+        assert!(!Readiness::DatabaseAndDrivers.is_ready(true, false, &conf));
+        conf.admin_token = "secret".to_string();
+        assert!(!Readiness::DatabaseAndAdminToken.is_ready(true, false, &conf));
+        conf.admin_port = "9180".to_string();
+        assert!(Readiness::DatabaseAndAdminToken.is_ready(true, false, &conf));
+        assert!(!Readiness::DatabaseAndAdminToken.is_ready(false, true, &conf));
+    }
+}
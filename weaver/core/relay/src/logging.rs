@@ -0,0 +1,278 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::common::events::{EventState, EventStates, EventSubscription};
+use weaverpb::common::query::Query;
+use weaverpb::common::state::{request_state, view_payload, RequestState, View, ViewPayload};
+use weaverpb::driver::driver::WriteExternalStateMessage;
+use weaverpb::networks::networks::{NetworkEventSubscription, NetworkEventUnsubscription, NetworkQuery};
+
+use crate::trace_context::set_remote_parent;
+
+use opentelemetry_sdk::trace::Tracer;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+const REDACTED: &str = "<redacted>";
+
+/// The `[logging]` section of the relay config.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct LoggingSettings {
+    // Level, or filter directives such as "info,relay::discovery=debug". RUST_LOG takes precedence.
+    #[serde(default = "default_level")]
+    pub level: String,
+    // "text" or "json"
+    #[serde(default = "default_format")]
+    pub format: String,
+    // Leaves view data, certificates and signatures out of the logs.
+    #[serde(default = "default_redact")]
+    pub redact: bool,
+}
+
+fn default_level() -> String {
+    "info".to_string()
+}
+
+fn default_format() -> String {
+    "text".to_string()
+}
+
+fn default_redact() -> bool {
+    true
+}
+
+impl Default for LoggingSettings {
+    fn default() -> Self {
+        LoggingSettings {
+            level: default_level(),
+            format: default_format(),
+            redact: default_redact(),
+        }
+    }
+}
+
+impl LoggingSettings {
+    /// Reads the `[logging]` table, defaults apply when it is missing.
+    pub fn from_config(conf: &config::Config) -> Result<LoggingSettings, String> {
+        let settings = match conf.get::<LoggingSettings>("logging") {
+            Ok(settings) => settings,
+            Err(config::ConfigError::NotFound(_)) => LoggingSettings::default(),
+            Err(e) => return Err(format!("logging: {}", e)),
+        };
+        if settings.format != "text" && settings.format != "json" {
+            return Err(format!("logging.format: unsupported format \"{}\", expected text or json", settings.format));
+        }
+        EnvFilter::try_new(&settings.level).map_err(|e| format!("logging.level: {}", e))?;
+        Ok(settings)
+    }
+}
+
+static REDACT: AtomicBool = AtomicBool::new(true);
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Installs the relay's logger, which also records its spans with `tracer`.
+/// The output format is fixed at startup, the level and redaction follow
+/// `apply_logging_settings`.
+pub fn init_logging(settings: &LoggingSettings, tracer: Tracer) {
+    let (filter, handle) = reload::Layer::new(env_filter(settings));
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer));
+    let result = if settings.format == "json" {
+        registry.with(tracing_subscriber::fmt::layer().json().flatten_event(true)).try_init()
+    } else {
+        registry.with(tracing_subscriber::fmt::layer()).try_init()
+    };
+    if let Err(e) = result {
+        println!("Unable to install the logger: {:?}", e);
+        return;
+    }
+    let _ = FILTER.set(handle);
+    REDACT.store(settings.redact, Ordering::Relaxed);
+}
+
+/// Applies the level and redaction of reloaded settings.
+pub fn apply_logging_settings(settings: &LoggingSettings) {
+    REDACT.store(settings.redact, Ordering::Relaxed);
+    if let Some(handle) = FILTER.get() {
+        if let Err(e) = handle.reload(env_filter(settings)) {
+            tracing::warn!("Unable to change the log level: {:?}", e);
+        }
+    }
+}
+
+fn env_filter(settings: &LoggingSettings) -> EnvFilter {
+    match std::env::var("RUST_LOG") {
+        Ok(directives) if !directives.is_empty() => EnvFilter::new(directives),
+        _ => EnvFilter::new(&settings.level),
+    }
+}
+
+/// Span every gRPC request of the relay is handled in, continuing the trace
+/// of the caller. Handlers record the request id once they know it, and
+/// tasks they spawn stay in the span.
+pub fn rpc_span(request: &hyper::Request<()>) -> tracing::Span {
+    let span = tracing::info_span!("rpc", path = %request.uri().path(), request_id = tracing::field::Empty);
+    set_remote_parent(&span, request.headers());
+    span
+}
+
+/// Records the request id in the current request's span.
+pub fn record_request_id(request_id: &str) {
+    tracing::Span::current().record("request_id", request_id);
+}
+
+/// Messages that carry data which is left out of the logs unless redaction is turned off.
+pub trait Redact: Clone {
+    fn redact(&mut self);
+}
+
+/// Logs a message with its sensitive fields redacted, e.g. `debug!(query = ?Redacted(&query))`.
+pub struct Redacted<'a, T>(pub &'a T);
+
+impl<T: Redact + fmt::Debug> fmt::Debug for Redacted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if REDACT.load(Ordering::Relaxed) {
+            let mut message = self.0.clone();
+            message.redact();
+            message.fmt(f)
+        } else {
+            self.0.fmt(f)
+        }
+    }
+}
+
+/// Logs view data as its size, or base64 encoded when redaction is turned off.
+pub struct ViewData<'a>(pub &'a [u8]);
+
+impl fmt::Display for ViewData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if REDACT.load(Ordering::Relaxed) {
+            write!(f, "<{} bytes redacted>", self.0.len())
+        } else {
+            write!(f, "{}", base64::encode(self.0))
+        }
+    }
+}
+
+fn redact_string(value: &mut String) {
+    if !value.is_empty() {
+        *value = REDACTED.to_string();
+    }
+}
+
+impl<T: Redact> Redact for Option<T> {
+    fn redact(&mut self) {
+        if let Some(message) = self.as_mut() {
+            message.redact();
+        }
+    }
+}
+
+impl Redact for Query {
+    fn redact(&mut self) {
+        redact_string(&mut self.certificate);
+        redact_string(&mut self.requestor_signature);
+    }
+}
+
+impl Redact for NetworkQuery {
+    fn redact(&mut self) {
+        redact_string(&mut self.certificate);
+        redact_string(&mut self.requestor_signature);
+    }
+}
+
+impl Redact for View {
+    fn redact(&mut self) {
+        self.data.clear();
+    }
+}
+
+impl Redact for ViewPayload {
+    fn redact(&mut self) {
+        if let Some(view_payload::State::View(view)) = self.state.as_mut() {
+            view.redact();
+        }
+    }
+}
+
+impl Redact for RequestState {
+    fn redact(&mut self) {
+        if let Some(request_state::State::View(view)) = self.state.as_mut() {
+            view.redact();
+        }
+    }
+}
+
+impl Redact for EventState {
+    fn redact(&mut self) {
+        self.state.redact();
+    }
+}
+
+impl Redact for EventStates {
+    fn redact(&mut self) {
+        self.states.iter_mut().for_each(|state| state.redact());
+    }
+}
+
+impl Redact for EventSubscription {
+    fn redact(&mut self) {
+        self.query.redact();
+    }
+}
+
+impl Redact for NetworkEventSubscription {
+    fn redact(&mut self) {
+        self.query.redact();
+    }
+}
+
+impl Redact for NetworkEventUnsubscription {
+    fn redact(&mut self) {
+        self.request.redact();
+    }
+}
+
+impl Redact for WriteExternalStateMessage {
+    fn redact(&mut self) {
+        self.view_payload.redact();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sensitive_fields_are_redacted() {
+        let query = Query {
+            address: "localhost:9080/network1/mychannel:simplestate:Read:a".to_string(),
+            certificate: "-----BEGIN CERTIFICATE-----".to_string(),
+            requestor_signature: "c2lnbmF0dXJl".to_string(),
+            ..Default::default()
+        };
+        let logged = format!("{:?}", Redacted(&query));
+        assert!(logged.contains("mychannel:simplestate:Read:a"));
+        assert!(!logged.contains("BEGIN CERTIFICATE") && !logged.contains("c2lnbmF0dXJl"));
+
+        let state = RequestState {
+            request_id: "1".to_string(),
+            status: request_state::Status::Completed as i32,
+            state: Some(request_state::State::View(View { meta: None, data: b"secret".to_vec() })),
+            relay_path: vec![],
+            callback: None,
+        };
+        let mut redacted = state.clone();
+        redacted.redact();
+        assert_eq!(redacted.request_id, "1");
+        assert_eq!(redacted.state, Some(request_state::State::View(View { meta: None, data: vec![] })));
+        assert_eq!(ViewData(b"secret").to_string(), "<6 bytes redacted>");
+    }
+}
@@ -7,88 +7,196 @@ use weaverpb::networks::networks::network_server::NetworkServer;
 use weaverpb::relay::datatransfer::data_transfer_server::DataTransferServer;
 use weaverpb::relay::events::event_subscribe_server::EventSubscribeServer;
 use weaverpb::relay::events::event_publish_server::EventPublishServer;
+use weaverpb::relay::admin::relay_admin_server::RelayAdminServer;
 
 // Internal modules
 use services::data_transfer_service::DataTransferService;
 use services::event_subscribe_service::EventSubscribeService;
 use services::event_publish_service::EventPublishService;
 use services::network_service::NetworkService;
+use services::admin_service::AdminService;
+use config_reload::{load_relay_config, spawn_config_watcher};
+use db::Database;
+use discovery::RelayDiscovery;
+use grpc_options::WithGrpcOptions;
+use health::{spawn_health_prober, Readiness};
+use logging::{init_logging, rpc_span};
+use metrics::{spawn_metrics_server, RpcMetricsLayer};
+use nonce_store::NonceStore;
+use rate_limit::RateLimiter;
+use shutdown::{drain_work, resume_pending_work, shutdown_signal};
+use trace_context::{init_tracer, shutdown_tracer};
 
 // External modules
-use config;
 use std::env;
+use std::process;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
+use tonic::server::NamedService;
+use tracing::{info, warn};
 use tonic::transport::{Identity, Server, ServerTlsConfig};
 
+mod access_control;
+mod callbacks;
+mod config_reload;
 mod db;
+mod discovery;
+mod driver_pool;
 mod error;
+mod grpc_options;
+mod health;
+mod logging;
+mod metrics;
+mod nonce_store;
+mod rate_limit;
+mod registrations;
+mod relay_config;
+mod relay_pool;
 mod relay_proto;
+mod result_cache;
+mod routing;
 mod services;
+mod shutdown;
+mod state_watch;
+#[cfg(test)]
+mod test_config;
+mod trace_context;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut settings = config::Config::default();
     // Either get config path from environment variable or uses default.
     let config_file_name = env::var("RELAY_CONFIG").unwrap_or_else(|_| {
         println!("Using default config `config/Settings`");
         "config/Settings".to_string()
     });
 
-    settings
-        .merge(config::File::with_name(&config_file_name))
-        .unwrap()
-        // Add in settings from the environment (with a prefix of Relay) Can be used to override config file settings
-        .merge(config::Environment::with_prefix("RELAY"))
-        .unwrap();
-
-    let relay_name = settings.get_str("name").expect("No Relay name provided");
-    println!("Relay Name: {:?}", relay_name);
-    let relay_port = settings.get_str("port").expect(&format!("Port does not exist for relay name {}. Make sure the config file <{}> has the name and port specified.", relay_name, config_file_name.to_string()));
-    let host = settings.get_str("hostname").unwrap_or("localhost".to_string());
+    // The config is validated as a whole, the relay does not start with an invalid config.
+    let settings = match load_relay_config(&config_file_name) {
+        Ok(settings) => settings,
+        Err(problems) => {
+            println!("Invalid relay config <{}>:\n  {}", config_file_name, problems.join("\n  "));
+            process::exit(1);
+        }
+    };
 
-    let with_tls = settings.get_bool("tls").unwrap_or(false);
+    // Spans are exported as configured, trace context is propagated either way.
+    let (tracer, exporter_error) = init_tracer(&settings.tracing, &settings.name);
+    init_logging(&settings.logging, tracer);
+    if let Some(e) = exporter_error {
+        warn!("Spans are not exported: {}", e);
+    }
+    info!("Relay Name: {:?}", settings.name);
+    let with_tls = settings.tls;
     // Converts port to a valid socket address
-    let addr: SocketAddr = format!("{}:{}", host, relay_port)
+    let addr: SocketAddr = format!("{}:{}", settings.hostname, settings.port)
         .to_socket_addrs()?
         .next()
         .expect("Port number is potentially invalid. Unable to create SocketAddr");
 
+    // Rate limits are shared by the services that serve requests from other relays.
+    let rate_limiter = Arc::new(RateLimiter::new());
+    rate_limiter.restore_subscriptions(&Database {
+        db_path: settings.remote_db_path.to_string(),
+        db_open_max_retries: settings.db_open_max_retries,
+        db_open_retry_backoff_msec: settings.db_open_retry_backoff_msec,
+    });
+
+    // All services share one config, which is swapped when the config file is reloaded.
+    let config_lock = Arc::new(RwLock::new(settings.clone()));
+    let config_reload_interval = Duration::from_millis(settings.config_reload_interval_msec);
+    spawn_config_watcher(config_file_name.to_string(), config_lock.clone(), config_reload_interval);
+
+    // Relays missing from [relays] are looked up in the relay registry and DNS, shared by all services.
+    let discovery = Arc::new(RelayDiscovery::new());
+    // Work left unfinished when the relay last stopped is picked up again.
+    resume_pending_work(&settings, rate_limiter.clone(), discovery.clone());
+
     let relay = DataTransferService {
-        config_lock: RwLock::new(settings.clone()),
+        config_lock: config_lock.clone(),
+        rate_limiter: rate_limiter.clone(),
+        nonce_store: NonceStore::new(),
+        discovery: discovery.clone(),
     };
     let event_subscribe = EventSubscribeService {
-        config_lock: RwLock::new(settings.clone()),
+        config_lock: config_lock.clone(),
+        rate_limiter: rate_limiter.clone(),
+        discovery: discovery.clone(),
     };
     let event_publish = EventPublishService {
-        config_lock: RwLock::new(settings.clone()),
+        config_lock: config_lock.clone(),
+        discovery: discovery.clone(),
     };
     let network = NetworkService {
-        config_lock: RwLock::new(settings.clone()),
+        config_lock: config_lock.clone(),
+        discovery: discovery.clone(),
+    };
+    let admin = AdminService {
+        config_lock: config_lock.clone(),
+        config_file_name: config_file_name.to_string(),
     };
-    println!("RelayServer listening on {}", addr);
-    if with_tls == true {
-        println!("Starting Server with TLS");
-        let cert = tokio::fs::read(settings.get_str("cert_path").unwrap()).await?;
-        let key = tokio::fs::read(settings.get_str("key_path").unwrap()).await?;
-        let identity = Identity::from_pem(cert, key);
-        // Spins up two gRPC services in a tonic server. One for relay to relay and one for network to relay communication.
-        let server = Server::builder()
-            .tls_config(ServerTlsConfig::new().identity(identity))?
-            .add_service(DataTransferServer::new(relay))
-            .add_service(EventSubscribeServer::new(event_subscribe))
-            .add_service(EventPublishServer::new(event_publish))
-            .add_service(NetworkServer::new(network));
-        server.serve(addr).await?;
+    // Readiness of each service is reported through the standard gRPC health service.
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    spawn_health_prober(
+        health_reporter,
+        config_lock.clone(),
+        vec![
+            (<DataTransferServer<DataTransferService> as NamedService>::NAME, Readiness::DatabaseAndDrivers),
+            (<EventSubscribeServer<EventSubscribeService> as NamedService>::NAME, Readiness::DatabaseAndDrivers),
+            (<EventPublishServer<EventPublishService> as NamedService>::NAME, Readiness::Database),
+            (<NetworkServer<NetworkService> as NamedService>::NAME, Readiness::Database),
+            (<RelayAdminServer<AdminService> as NamedService>::NAME, Readiness::DatabaseAndAdminToken),
+        ],
+    );
+    // Metrics are served on their own port, which is only read at startup.
+    if settings.metrics.enabled {
+        spawn_metrics_server(&settings.metrics, rate_limiter.clone());
+    }
+    let grpc_options = settings.grpc.clone();
+    let mut relay_server = Server::builder();
+    let mut admin_server = Server::builder();
+    if with_tls {
+        info!("Starting Server with TLS");
+        let cert = tokio::fs::read(&settings.cert_path).await?;
+        let key = tokio::fs::read(&settings.key_path).await?;
+        let tls_config = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+        relay_server = relay_server.tls_config(tls_config.clone())?;
+        admin_server = admin_server.tls_config(tls_config)?;
+    }
+    // Serves relay to relay and network to relay communication, along with the health service.
+    info!("RelayServer listening on {}", addr);
+    let relay_server = relay_server
+        .trace_fn(rpc_span)
+        .layer(RpcMetricsLayer)
+        .add_service(DataTransferServer::new(relay).with_grpc_options(&grpc_options))
+        .add_service(EventSubscribeServer::new(event_subscribe).with_grpc_options(&grpc_options))
+        .add_service(EventPublishServer::new(event_publish).with_grpc_options(&grpc_options))
+        .add_service(NetworkServer::new(network).with_grpc_options(&grpc_options))
+        .add_service(health_service)
+        .serve_with_shutdown(addr, shutdown_signal());
+    // The admin service has its own listener, on loopback unless configured otherwise, so that
+    // other relays and networks cannot reach it. It is only served when admin_port is set.
+    if settings.admin_port.is_empty() {
+        relay_server.await?;
     } else {
-        // Spins up two gRPC services in a tonic server. One for relay to relay and one for network to relay communication.
-        let server = Server::builder()
-            .add_service(DataTransferServer::new(relay))
-            .add_service(EventSubscribeServer::new(event_subscribe))
-            .add_service(EventPublishServer::new(event_publish))
-            .add_service(NetworkServer::new(network));
-        server.serve(addr).await?;
+        let admin_addr: SocketAddr = format!("{}:{}", settings.admin_hostname, settings.admin_port)
+            .to_socket_addrs()?
+            .next()
+            .expect("Admin port number is potentially invalid. Unable to create SocketAddr");
+        info!("Admin service listening on {}", admin_addr);
+        let admin_server = admin_server
+            .trace_fn(rpc_span)
+            .layer(RpcMetricsLayer)
+            .add_service(RelayAdminServer::new(admin).with_grpc_options(&grpc_options))
+            .serve_with_shutdown(admin_addr, shutdown_signal());
+        tokio::try_join!(relay_server, admin_server)?;
     }
+    // No more requests are served, the work they started gets until the drain timeout to finish.
+    let conf = config_lock.read().await.clone();
+    drain_work(&conf).await;
+    tokio::task::spawn_blocking(shutdown_tracer).await?;
+    info!("Relay stopped");
     Ok(())
 }
@@ -0,0 +1,369 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::common::ack::{ack, Ack};
+use weaverpb::common::state::request_state;
+
+use crate::rate_limit::RateLimiter;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Response, StatusCode};
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower::{Layer, Service};
+use tracing::{error, info};
+
+/// The `[metrics]` section of the relay config.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct MetricsSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_hostname")]
+    pub hostname: String,
+    #[serde(default = "default_port")]
+    pub port: String,
+}
+
+fn default_hostname() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_port() -> String {
+    "9464".to_string()
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        MetricsSettings {
+            enabled: false,
+            hostname: default_hostname(),
+            port: default_port(),
+        }
+    }
+}
+
+impl MetricsSettings {
+    /// Reads the `[metrics]` table. A missing table leaves the metrics endpoint disabled.
+    pub fn from_config(conf: &config::Config) -> Result<MetricsSettings, String> {
+        match conf.get::<MetricsSettings>("metrics") {
+            Ok(settings) => Ok(settings),
+            Err(config::ConfigError::NotFound(_)) => Ok(MetricsSettings::default()),
+            Err(e) => Err(format!("metrics: {}", e)),
+        }
+    }
+}
+
+/// Prometheus metrics of the relay. They are recorded from wherever the
+/// relay does the work, so a single instance is shared by the whole process.
+pub struct RelayMetrics {
+    registry: Registry,
+    rpc_requests: IntCounterVec,
+    rpc_duration: HistogramVec,
+    request_states: IntCounterVec,
+    active_subscriptions: IntGauge,
+    events: IntCounterVec,
+    driver_errors: IntCounterVec,
+    callback_failures: IntCounterVec,
+    db_operation_duration: HistogramVec,
+    db_open_retries: IntCounter,
+    rate_limited: IntCounterVec,
+    cache_lookups: IntCounterVec,
+}
+
+static METRICS: OnceLock<RelayMetrics> = OnceLock::new();
+
+/// The relay's metrics, created on first use.
+pub fn metrics() -> &'static RelayMetrics {
+    METRICS.get_or_init(RelayMetrics::new)
+}
+
+impl RelayMetrics {
+    fn new() -> RelayMetrics {
+        let registry = Registry::new();
+        let rpc_requests = IntCounterVec::new(
+            Opts::new("relay_grpc_requests_total", "gRPC requests served by the relay, by RPC and gRPC status code"),
+            &["service", "method", "code"],
+        )
+        .unwrap();
+        let rpc_duration = HistogramVec::new(
+            HistogramOpts::new("relay_grpc_request_duration_seconds", "Time taken to serve gRPC requests, by RPC"),
+            &["service", "method"],
+        )
+        .unwrap();
+        let request_states = IntCounterVec::new(
+            Opts::new("relay_request_states_total", "Times a RequestState was stored by the relay with each status"),
+            &["status"],
+        )
+        .unwrap();
+        let active_subscriptions = IntGauge::new(
+            "relay_active_event_subscriptions",
+            "Event subscriptions of remote relays currently served by the relay",
+        )
+        .unwrap();
+        let events = IntCounterVec::new(
+            Opts::new("relay_events_total", "Events of subscriptions made through the relay, by outcome: received, written or failed"),
+            &["outcome"],
+        )
+        .unwrap();
+        let driver_errors = IntCounterVec::new(
+            Opts::new("relay_driver_errors_total", "Failed calls to drivers, by driver"),
+            &["driver"],
+        )
+        .unwrap();
+        let callback_failures = IntCounterVec::new(
+            Opts::new("relay_callback_failures_total", "Results that could not be sent back to a requesting relay, by relay"),
+            &["peer"],
+        )
+        .unwrap();
+        let db_operation_duration = HistogramVec::new(
+            HistogramOpts::new("relay_db_operation_duration_seconds", "Time taken by relay database operations, including opening the database")
+                .buckets(vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]),
+            &["operation"],
+        )
+        .unwrap();
+        let db_open_retries = IntCounter::new(
+            "relay_db_open_retries_total",
+            "Attempts to open the relay database that were retried because it was locked",
+        )
+        .unwrap();
+        let rate_limited = IntCounterVec::new(
+            Opts::new("relay_rate_limited_requests_total", "Requests of remote relays refused by the rate limiter, by requester"),
+            &["requester_kind", "requester"],
+        )
+        .unwrap();
+        registry.register(Box::new(rpc_requests.clone())).unwrap();
+        registry.register(Box::new(rpc_duration.clone())).unwrap();
+        registry.register(Box::new(request_states.clone())).unwrap();
+        registry.register(Box::new(active_subscriptions.clone())).unwrap();
+        registry.register(Box::new(events.clone())).unwrap();
+        registry.register(Box::new(driver_errors.clone())).unwrap();
+        registry.register(Box::new(callback_failures.clone())).unwrap();
+        registry.register(Box::new(db_operation_duration.clone())).unwrap();
+        registry.register(Box::new(db_open_retries.clone())).unwrap();
+        let cache_lookups = IntCounterVec::new(
+            Opts::new("relay_result_cache_lookups_total", "Queries of networks looked up in the result cache, by outcome: hit or miss"),
+            &["outcome"],
+        )
+        .unwrap();
+        registry.register(Box::new(rate_limited.clone())).unwrap();
+        registry.register(Box::new(cache_lookups.clone())).unwrap();
+        RelayMetrics {
+            registry,
+            rpc_requests,
+            rpc_duration,
+            request_states,
+            active_subscriptions,
+            events,
+            driver_errors,
+            callback_failures,
+            db_operation_duration,
+            db_open_retries,
+            rate_limited,
+            cache_lookups,
+        }
+    }
+
+    /// Records a served gRPC request, `path` being the request path, e.g. `/relay.datatransfer.DataTransfer/RequestState`.
+    /// The path is chosen by the caller, so requests that no RPC of the relay
+    /// served are all labelled `unknown`.
+    pub fn record_rpc(&self, path: &str, code: &str, elapsed: Duration) {
+        let (service, method) = match path.trim_start_matches('/').split_once('/') {
+            Some((service, method)) if code != UNIMPLEMENTED && code != TRANSPORT_ERROR => (service, method),
+            _ => ("unknown", "unknown"),
+        };
+        self.rpc_requests.with_label_values(&[service, method, code]).inc();
+        self.rpc_duration.with_label_values(&[service, method]).observe(elapsed.as_secs_f64());
+    }
+
+    /// Counts a RequestState stored with `status`. A request is counted once
+    /// for every status it goes through, e.g. as `PENDING` and then `COMPLETED`.
+    pub fn record_request_state(&self, status: i32) {
+        let status = request_state::Status::try_from(status).map_or("UNKNOWN", |status| status.as_str_name());
+        self.request_states.with_label_values(&[status]).inc();
+    }
+
+    pub fn record_event(&self, outcome: &str) {
+        self.events.with_label_values(&[outcome]).inc();
+    }
+
+    pub fn record_driver_error(&self, driver: &str) {
+        self.driver_errors.with_label_values(&[driver]).inc();
+    }
+
+    pub fn record_callback_failure(&self, peer: &str) {
+        self.callback_failures.with_label_values(&[peer]).inc();
+    }
+
+    /// Counts a callback the requesting relay did not accept as failed.
+    pub fn record_callback_result(&self, peer: &str, response: &Result<tonic::Response<Ack>, tonic::Status>) {
+        let accepted = matches!(response, Ok(reply) if reply.get_ref().status == ack::Status::Ok as i32);
+        if !accepted {
+            self.record_callback_failure(peer);
+        }
+    }
+
+    pub fn record_cache_lookup(&self, hit: bool) {
+        self.cache_lookups.with_label_values(&[if hit { "hit" } else { "miss" }]).inc();
+    }
+
+    pub fn record_db_operation(&self, operation: &str, elapsed: Duration) {
+        self.db_operation_duration.with_label_values(&[operation]).observe(elapsed.as_secs_f64());
+    }
+
+    pub fn record_db_open_retry(&self) {
+        self.db_open_retries.inc();
+    }
+
+    // Renders all metrics in the Prometheus text format. Gauges kept by the
+    // rate limiter are read at this point.
+    fn render(&self, rate_limiter: &RateLimiter) -> Vec<u8> {
+        self.active_subscriptions.set(rate_limiter.active_subscriptions() as i64);
+        for (key, count) in rate_limiter.throttle_counts() {
+            let counter = self.rate_limited.with_label_values(&[&key.kind.to_string(), &key.id]);
+            counter.inc_by(count.saturating_sub(counter.get()));
+        }
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            error!("Unable to encode metrics: {:?}", e);
+        }
+        buffer
+    }
+}
+
+/// Serves the relay's metrics at `/metrics` on the configured address.
+pub fn spawn_metrics_server(settings: &MetricsSettings, rate_limiter: Arc<RateLimiter>) {
+    let addr: SocketAddr = match format!("{}:{}", settings.hostname, settings.port).to_socket_addrs().map(|mut addrs| addrs.next()) {
+        Ok(Some(addr)) => addr,
+        _ => {
+            error!("Unable to serve metrics, invalid address {}:{}", settings.hostname, settings.port);
+            return;
+        }
+    };
+    let make_service = make_service_fn(move |_conn| {
+        let rate_limiter = rate_limiter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: hyper::Request<Body>| {
+                let rate_limiter = rate_limiter.clone();
+                async move {
+                    let response = if request.method() == Method::GET && request.uri().path() == "/metrics" {
+                        Response::builder()
+                            .header(hyper::header::CONTENT_TYPE, TextEncoder::new().format_type())
+                            .body(Body::from(metrics().render(&rate_limiter)))
+                    } else {
+                        Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty())
+                    };
+                    response
+                }
+            }))
+        }
+    });
+    match hyper::Server::try_bind(&addr) {
+        Ok(builder) => {
+            info!("Serving metrics on http://{}/metrics", addr);
+            tokio::spawn(async move {
+                if let Err(e) = builder.serve(make_service).await {
+                    error!("Metrics server error: {:?}", e);
+                }
+            });
+        }
+        Err(e) => error!("Unable to serve metrics on {}: {:?}", addr, e),
+    }
+}
+
+// gRPC status of requests for a path no service of the relay serves.
+const UNIMPLEMENTED: &str = "12";
+
+const TRANSPORT_ERROR: &str = "transport_error";
+
+/// Tower layer recording the count, gRPC status and duration of every request
+/// served by the relay's gRPC server.
+#[derive(Clone, Debug, Default)]
+pub struct RpcMetricsLayer;
+
+impl<S> Layer<S> for RpcMetricsLayer {
+    type Service = RpcMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RpcMetrics { inner }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RpcMetrics<S> {
+    inner: S,
+}
+
+impl<S, ReqBody, ResBody> Service<hyper::Request<ReqBody>> for RpcMetrics<S>
+where
+    S: Service<hyper::Request<ReqBody>, Response = hyper::Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: hyper::Request<ReqBody>) -> Self::Future {
+        let path = request.uri().path().to_string();
+        let start = Instant::now();
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let response = response.await;
+            // Failed calls carry their status in the response headers, successful
+            // ones in the trailers, which are sent after the response is returned.
+            let code = match &response {
+                Ok(response) => response
+                    .headers()
+                    .get("grpc-status")
+                    .and_then(|code| code.to_str().ok())
+                    .unwrap_or("0")
+                    .to_string(),
+                Err(_) => TRANSPORT_ERROR.to_string(),
+            };
+            metrics().record_rpc(&path, &code, start.elapsed());
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpcs_are_labelled_by_service_and_method() {
+        let relay_metrics = RelayMetrics::new();
+        relay_metrics.record_rpc("/relay.datatransfer.DataTransfer/RequestState", "0", Duration::from_millis(5));
+        relay_metrics.record_rpc("/relay.datatransfer.DataTransfer/RequestState", "0", Duration::from_millis(5));
+        relay_metrics.record_request_state(request_state::Status::Completed as i32);
+        let rendered = String::from_utf8(relay_metrics.render(&RateLimiter::new())).unwrap();
+        assert!(rendered.contains(
+            "relay_grpc_requests_total{code=\"0\",method=\"RequestState\",service=\"relay.datatransfer.DataTransfer\"} 2"
+        ));
+        assert!(rendered.contains("relay_request_states_total{status=\"COMPLETED\"} 1"));
+        assert!(rendered.contains("relay_active_event_subscriptions 0"));
+    }
+
+    #[test]
+    fn unserved_paths_are_labelled_unknown() {
+        let relay_metrics = RelayMetrics::new();
+        relay_metrics.record_rpc("/made.up.Service/Method1", "12", Duration::from_millis(1));
+        relay_metrics.record_rpc("/relay.datatransfer.DataTransfer/Made_Up", "12", Duration::from_millis(1));
+        relay_metrics.record_rpc("/made.up.Service/Method2", "transport_error", Duration::from_millis(1));
+        relay_metrics.record_rpc("no-slash", "0", Duration::from_millis(1));
+        let rendered = String::from_utf8(relay_metrics.render(&RateLimiter::new())).unwrap();
+        assert!(rendered.contains("relay_grpc_requests_total{code=\"12\",method=\"unknown\",service=\"unknown\"} 2"));
+        assert!(!rendered.contains("made.up"));
+        assert!(!rendered.contains("Made_Up"));
+    }
+}
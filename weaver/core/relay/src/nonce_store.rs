@@ -0,0 +1,202 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Separator between the random part of a nonce and an optional unix timestamp
+/// in seconds, e.g. `8c1f0a4e-6d7b-4c2d-9a8e-2b1f6f0d3c5a@1700000000`. Since
+/// the nonce is one of the signed fields of a query, the timestamp is covered
+/// by the requestor's signature.
+pub const NONCE_TIMESTAMP_SEPARATOR: char = '@';
+
+/// The `[replay_protection]` section of the relay config.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct ReplayProtection {
+    #[serde(default)]
+    pub enabled: bool,
+    // How long a nonce is remembered, and how far a nonce timestamp may be from the relay's clock.
+    #[serde(default = "default_window_secs")]
+    pub window_secs: u64,
+    // Upper bound on the number of nonces remembered per requesting network.
+    #[serde(default = "default_max_nonces")]
+    pub max_nonces_per_network: usize,
+    // Reject queries whose nonce does not carry a timestamp.
+    #[serde(default)]
+    pub require_timestamp: bool,
+}
+
+fn default_window_secs() -> u64 {
+    300
+}
+
+fn default_max_nonces() -> usize {
+    100000
+}
+
+impl Default for ReplayProtection {
+    fn default() -> Self {
+        ReplayProtection {
+            enabled: false,
+            window_secs: default_window_secs(),
+            max_nonces_per_network: default_max_nonces(),
+            require_timestamp: false,
+        }
+    }
+}
+
+impl ReplayProtection {
+    /// Reads the `[replay_protection]` table. A missing table disables replay protection.
+    pub fn from_config(conf: &config::Config) -> Result<ReplayProtection, String> {
+        match conf.get::<ReplayProtection>("replay_protection") {
+            Ok(settings) => Ok(settings),
+            Err(config::ConfigError::NotFound(_)) => Ok(ReplayProtection::default()),
+            Err(e) => Err(format!("replay_protection: {}", e)),
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+struct NetworkNonces {
+    seen: HashMap<String, Instant>,
+    // Nonces in the order they were first seen, used for expiry.
+    order: VecDeque<(String, Instant)>,
+}
+
+impl NetworkNonces {
+    fn expire(&mut self, window: Duration, now: Instant) {
+        while let Some((nonce, seen_at)) = self.order.front() {
+            if now.saturating_duration_since(*seen_at) < window {
+                break;
+            }
+            self.seen.remove(nonce);
+            self.order.pop_front();
+        }
+    }
+}
+
+/// NonceStore remembers the nonces of the queries a remote relay has served,
+/// per requesting network, for a bounded time window so that a captured query
+/// cannot be replayed.
+#[derive(Default, Debug)]
+pub struct NonceStore {
+    networks: Mutex<HashMap<String, NetworkNonces>>,
+}
+
+impl NonceStore {
+    pub fn new() -> NonceStore {
+        NonceStore::default()
+    }
+
+    /// Records `nonce` as used by `requesting_network`. Fails if the nonce was
+    /// already used within the window or carries a timestamp outside of it,
+    /// or if the network already used as many nonces as are remembered.
+    pub fn check_and_record(&self, requesting_network: &str, nonce: &str, settings: &ReplayProtection) -> Result<(), String> {
+        self.check_and_record_at(requesting_network, nonce, settings, Instant::now(), SystemTime::now())
+    }
+
+    fn check_and_record_at(
+        &self,
+        requesting_network: &str,
+        nonce: &str,
+        settings: &ReplayProtection,
+        now: Instant,
+        wall_clock: SystemTime,
+    ) -> Result<(), String> {
+        if nonce.is_empty() {
+            return Err("Query nonce is empty".to_string());
+        }
+        match parse_nonce_timestamp(nonce)? {
+            Some(timestamp) => {
+                let now_secs = wall_clock.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                if now_secs.abs_diff(timestamp) > settings.window_secs {
+                    return Err(format!(
+                        "Query nonce timestamp {} is outside the accepted window of {} seconds",
+                        timestamp, settings.window_secs
+                    ));
+                }
+            }
+            None if settings.require_timestamp => {
+                return Err("Query nonce does not carry a timestamp".to_string());
+            }
+            None => {}
+        }
+        let window = Duration::from_secs(settings.window_secs);
+        let mut networks = self.networks.lock().unwrap();
+        let nonces = networks.entry(requesting_network.to_string()).or_default();
+        nonces.expire(window, now);
+        if nonces.seen.contains_key(nonce) {
+            return Err(format!("Query nonce has already been used by network \"{}\"", requesting_network));
+        }
+        // Nonces are never forgotten before the window is over, otherwise a
+        // flood of fresh nonces would make room for replaying a captured query.
+        if nonces.order.len() >= settings.max_nonces_per_network.max(1) {
+            return Err(format!(
+                "Too many queries of network \"{}\" within {} seconds (replay_protection.max_nonces_per_network)",
+                requesting_network, settings.window_secs
+            ));
+        }
+        nonces.seen.insert(nonce.to_string(), now);
+        nonces.order.push_back((nonce.to_string(), now));
+        Ok(())
+    }
+}
+
+// Returns the timestamp of a nonce of the form `<random>@<unix-seconds>`.
+fn parse_nonce_timestamp(nonce: &str) -> Result<Option<u64>, String> {
+    match nonce.rsplit_once(NONCE_TIMESTAMP_SEPARATOR) {
+        Some((_, timestamp)) => timestamp
+            .parse::<u64>()
+            .map(Some)
+            .map_err(|_| format!("Query nonce timestamp \"{}\" is not a unix timestamp in seconds", timestamp)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> ReplayProtection {
+        ReplayProtection { enabled: true, window_secs: 60, max_nonces_per_network: 2, require_timestamp: false }
+    }
+
+    #[test]
+    fn reused_nonce_is_rejected_within_window() {
+        let store = NonceStore::new();
+        let (now, wall_clock) = (Instant::now(), SystemTime::now());
+        assert!(store.check_and_record_at("network1", "abc", &settings(), now, wall_clock).is_ok());
+        assert!(store.check_and_record_at("network1", "abc", &settings(), now, wall_clock).is_err());
+        assert!(store.check_and_record_at("network2", "abc", &settings(), now, wall_clock).is_ok());
+        let later = now + Duration::from_secs(61);
+        assert!(store.check_and_record_at("network1", "abc", &settings(), later, wall_clock).is_ok());
+    }
+
+    #[test]
+    fn nonce_timestamps_must_be_within_window() {
+        let store = NonceStore::new();
+        let (now, wall_clock) = (Instant::now(), UNIX_EPOCH + Duration::from_secs(1_000_000));
+        assert!(store.check_and_record_at("network1", "a@1000000", &settings(), now, wall_clock).is_ok());
+        assert!(store.check_and_record_at("network1", "b@999000", &settings(), now, wall_clock).is_err());
+        assert!(store.check_and_record_at("network1", "c@notatime", &settings(), now, wall_clock).is_err());
+        let strict = ReplayProtection { require_timestamp: true, ..settings() };
+        assert!(store.check_and_record_at("network1", "d", &strict, now, wall_clock).is_err());
+    }
+
+    #[test]
+    fn full_store_refuses_new_nonces_until_the_window_is_over() {
+        let store = NonceStore::new();
+        let (now, wall_clock) = (Instant::now(), SystemTime::now());
+        for nonce in ["a", "b"] {
+            assert!(store.check_and_record_at("network1", nonce, &settings(), now, wall_clock).is_ok());
+        }
+        // Live nonces are not evicted to make room, so "a" cannot be replayed.
+        assert!(store.check_and_record_at("network1", "c", &settings(), now, wall_clock).is_err());
+        assert!(store.check_and_record_at("network1", "a", &settings(), now, wall_clock).is_err());
+        assert!(store.check_and_record_at("network2", "c", &settings(), now, wall_clock).is_ok());
+        let later = now + Duration::from_secs(61);
+        assert!(store.check_and_record_at("network1", "c", &settings(), later, wall_clock).is_ok());
+    }
+}
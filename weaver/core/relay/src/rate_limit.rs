@@ -0,0 +1,532 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::common::query::Query;
+use weaverpb::common::events::{EventSubOperation, EventSubscription};
+
+use crate::db::Database;
+use crate::services::helpers::get_event_subscription_key;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// Limits applied to a single requester. Any limit left unset is not enforced.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug, Default)]
+pub struct RateLimitSpec {
+    // Sustained number of requests per second refilled into the token bucket.
+    pub requests_per_sec: Option<f64>,
+    // Size of the token bucket, i.e. the number of requests allowed in a burst.
+    pub burst: Option<u32>,
+    // Number of driver requests that may be outstanding at the same time.
+    pub max_concurrent: Option<u32>,
+    // Number of event subscriptions that may be active at the same time.
+    pub max_subscriptions: Option<u32>,
+}
+
+/// The `[rate_limits]` section of the relay config. `default` applies to every
+/// requester that has no specific entry in the `relays`, `networks` or `orgs` tables.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug, Default)]
+pub struct RateLimits {
+    // How long a request holds its concurrency slot when the driver never answers.
+    #[serde(default)]
+    pub request_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub default: RateLimitSpec,
+    #[serde(default)]
+    pub relays: HashMap<String, RateLimitSpec>,
+    #[serde(default)]
+    pub networks: HashMap<String, RateLimitSpec>,
+    #[serde(default)]
+    pub orgs: HashMap<String, RateLimitSpec>,
+}
+
+impl RateLimits {
+    /// Reads the `[rate_limits]` table. A missing table means no limits are enforced.
+    pub fn from_config(conf: &config::Config) -> Result<RateLimits, String> {
+        match conf.get::<RateLimits>("rate_limits") {
+            Ok(limits) => Ok(limits),
+            Err(config::ConfigError::NotFound(_)) => Ok(RateLimits::default()),
+            Err(e) => Err(format!("rate_limits: {}", e)),
+        }
+    }
+
+    fn spec_for(&self, key: &RequesterKey) -> &RateLimitSpec {
+        let table = match key.kind {
+            RequesterKind::Relay => &self.relays,
+            RequesterKind::Network => &self.networks,
+            RequesterKind::Org => &self.orgs,
+        };
+        table.get(&key.id).unwrap_or(&self.default)
+    }
+
+    // Requesters without their own entry are counted together, so that the
+    // counts stay bounded whatever identities remote relays claim.
+    fn counted_as(&self, key: &RequesterKey) -> RequesterKey {
+        let table = match key.kind {
+            RequesterKind::Relay => &self.relays,
+            RequesterKind::Network => &self.networks,
+            RequesterKind::Org => &self.orgs,
+        };
+        let id = if table.contains_key(&key.id) { key.id.to_string() } else { "default".to_string() };
+        RequesterKey { kind: key.kind, id }
+    }
+
+    fn request_timeout(&self) -> Duration {
+        Duration::from_secs(self.request_timeout_secs.unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS))
+    }
+}
+
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 300;
+
+// Idle requesters and timed out requests are swept at most this often.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum RequesterKind {
+    Relay,
+    Network,
+    Org,
+}
+
+impl fmt::Display for RequesterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequesterKind::Relay => write!(f, "requesting_relay"),
+            RequesterKind::Network => write!(f, "requesting_network"),
+            RequesterKind::Org => write!(f, "requesting_org"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct RequesterKey {
+    pub kind: RequesterKind,
+    pub id: String,
+}
+
+impl RequesterKey {
+    /// Keys a query is accounted against. Empty identities are not tracked.
+    pub fn from_query(query: &Query) -> Vec<RequesterKey> {
+        vec![
+            (RequesterKind::Relay, &query.requesting_relay),
+            (RequesterKind::Network, &query.requesting_network),
+            (RequesterKind::Org, &query.requesting_org),
+        ]
+        .into_iter()
+        .filter(|(_, id)| !id.is_empty())
+        .map(|(kind, id)| RequesterKey { kind, id: id.to_string() })
+        .collect()
+    }
+}
+
+/// Reason a request was refused by the rate limiter.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Throttled {
+    pub key: RequesterKey,
+    pub reason: String,
+}
+
+impl fmt::Display for Throttled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Throttled: {} \"{}\" {}", self.key.kind, self.key.id, self.reason)
+    }
+}
+
+/// Reason a data sharing query was not admitted.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Refused {
+    Throttled(Throttled),
+    // Another query with the same request id is still being served.
+    DuplicateRequest(String),
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refused::Throttled(throttled) => throttled.fmt(f),
+            Refused::DuplicateRequest(request_id) => write!(f, "Request {} is already being served", request_id),
+        }
+    }
+}
+
+impl From<Throttled> for Refused {
+    fn from(throttled: Throttled) -> Refused {
+        Refused::Throttled(throttled)
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64, now: Instant) -> TokenBucket {
+        TokenBucket { tokens: capacity, last_refill: now }
+    }
+
+    fn refill(&mut self, rate: f64, capacity: f64, now: Instant) {
+        self.tokens = self.tokens_at(rate, capacity, now);
+        self.last_refill = now;
+    }
+
+    fn tokens_at(&self, rate: f64, capacity: f64, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        (self.tokens + elapsed * rate).min(capacity)
+    }
+}
+
+// Concurrency slot held by a request until the driver answers or it times out.
+#[derive(Debug)]
+struct RequestSlot {
+    keys: Vec<RequesterKey>,
+    acquired: Instant,
+}
+
+#[derive(Default, Debug)]
+struct LimiterState {
+    buckets: HashMap<RequesterKey, TokenBucket>,
+    in_flight: HashMap<RequesterKey, u32>,
+    requests: HashMap<String, RequestSlot>,
+    active_subscriptions: HashMap<RequesterKey, u32>,
+    // request_id -> keys the subscription is accounted against
+    subscriptions: HashMap<String, Vec<RequesterKey>>,
+    throttled: HashMap<RequesterKey, u64>,
+    last_sweep: Option<Instant>,
+}
+
+/// RateLimiter enforces token-bucket rate limits, concurrency caps and
+/// subscription caps on the inbound requests a remote relay serves for
+/// other relays. State is kept in memory and shared by the relay services.
+#[derive(Default, Debug)]
+pub struct RateLimiter {
+    state: Mutex<LimiterState>,
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter::default()
+    }
+
+    /// Admits a data sharing query. On success a concurrency slot is held for
+    /// `request_id` until `release_request` is called, or until the request
+    /// times out. A request id can only hold one slot at a time.
+    pub fn try_acquire_request(&self, request_id: &str, query: &Query, limits: &RateLimits) -> Result<(), Refused> {
+        self.try_acquire_request_at(request_id, query, limits, Instant::now())
+    }
+
+    fn try_acquire_request_at(&self, request_id: &str, query: &Query, limits: &RateLimits, now: Instant) -> Result<(), Refused> {
+        let keys = RequesterKey::from_query(query);
+        let mut state = self.state.lock().unwrap();
+        state.sweep(limits, now);
+        if state.requests.contains_key(request_id) {
+            return Err(Refused::DuplicateRequest(request_id.to_string()));
+        }
+        for key in keys.iter() {
+            let spec = limits.spec_for(key);
+            if let Some(max_concurrent) = spec.max_concurrent {
+                let in_flight = *state.in_flight.get(key).unwrap_or(&0);
+                if in_flight >= max_concurrent {
+                    return Err(state.throttle(key, limits, format!("has reached the limit of {} concurrent requests", max_concurrent)).into());
+                }
+            }
+        }
+        state.take_tokens(&keys, limits, now)?;
+        for key in keys.iter() {
+            *state.in_flight.entry(key.clone()).or_insert(0) += 1;
+        }
+        state.requests.insert(request_id.to_string(), RequestSlot { keys, acquired: now });
+        Ok(())
+    }
+
+    /// Frees the concurrency slot held by `request_id`, if any.
+    pub fn release_request(&self, request_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.release_slot(request_id);
+    }
+
+    /// Admits an event subscription. The subscription counts against the
+    /// requester's cap until `release_subscription` is called.
+    pub fn try_acquire_subscription(&self, request_id: &str, query: &Query, limits: &RateLimits) -> Result<(), Throttled> {
+        self.try_acquire_subscription_at(request_id, query, limits, Instant::now())
+    }
+
+    fn try_acquire_subscription_at(&self, request_id: &str, query: &Query, limits: &RateLimits, now: Instant) -> Result<(), Throttled> {
+        let keys = RequesterKey::from_query(query);
+        let mut state = self.state.lock().unwrap();
+        state.sweep(limits, now);
+        if state.subscriptions.contains_key(request_id) {
+            // Already accounted for, e.g. a retried subscription request.
+            return Ok(());
+        }
+        for key in keys.iter() {
+            let spec = limits.spec_for(key);
+            if let Some(max_subscriptions) = spec.max_subscriptions {
+                let active = *state.active_subscriptions.get(key).unwrap_or(&0);
+                if active >= max_subscriptions {
+                    return Err(state.throttle(key, limits, format!("has reached the limit of {} active event subscriptions", max_subscriptions)));
+                }
+            }
+        }
+        state.take_tokens(&keys, limits, now)?;
+        state.add_subscription(request_id, keys);
+        Ok(())
+    }
+
+    /// Removes `request_id` from the active subscription counts, if present.
+    pub fn release_subscription(&self, request_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(keys) = state.subscriptions.remove(request_id) {
+            for key in keys.iter() {
+                decrement(&mut state.active_subscriptions, key);
+            }
+        }
+    }
+
+    /// Rebuilds the active subscription counts from the subscriptions stored in
+    /// the remote relay database, so that the caps survive a relay restart.
+    pub fn restore_subscriptions(&self, remote_db: &Database) {
+        let prefix = get_event_subscription_key("".to_string());
+        match remote_db.scan_prefix::<EventSubscription>(prefix) {
+            Ok(entries) => {
+                let mut state = self.state.lock().unwrap();
+                for (_key, event_sub) in entries {
+                    if event_sub.operation != EventSubOperation::Subscribe as i32 {
+                        continue;
+                    }
+                    if let Some(query) = event_sub.query {
+                        state.add_subscription(&query.request_id, RequesterKey::from_query(&query));
+                    }
+                }
+                info!("Restored {} active event subscriptions for rate limiting", state.subscriptions.len());
+            }
+            Err(e) => error!("Unable to restore event subscriptions for rate limiting: {:?}", e),
+        }
+    }
+
+    /// Number of event subscriptions currently accounted for.
+    pub fn active_subscriptions(&self) -> usize {
+        self.state.lock().unwrap().subscriptions.len()
+    }
+
+    /// Number of throttled calls per requester since the relay started.
+    /// Requesters without their own limits are counted under `default`.
+    pub fn throttle_counts(&self) -> Vec<(RequesterKey, u64)> {
+        let state = self.state.lock().unwrap();
+        state.throttled.iter().map(|(key, count)| (key.clone(), *count)).collect()
+    }
+}
+
+impl LimiterState {
+    // Consumes one token from the bucket of every key, or none at all if any bucket is empty.
+    fn take_tokens(&mut self, keys: &[RequesterKey], limits: &RateLimits, now: Instant) -> Result<(), Throttled> {
+        let mut limited = Vec::new();
+        for key in keys.iter() {
+            let spec = limits.spec_for(key);
+            if let Some(rate) = spec.requests_per_sec {
+                let capacity = spec.burst.map(|b| b as f64).unwrap_or(rate.ceil().max(1.0));
+                let bucket = self.buckets
+                    .entry(key.clone())
+                    .or_insert_with(|| TokenBucket::new(capacity, now));
+                bucket.refill(rate, capacity, now);
+                if bucket.tokens < 1.0 {
+                    return Err(self.throttle(key, limits, format!("exceeded the rate limit of {} requests/sec", rate)));
+                }
+                limited.push(key);
+            }
+        }
+        for key in limited {
+            if let Some(bucket) = self.buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_subscription(&mut self, request_id: &str, keys: Vec<RequesterKey>) {
+        for key in keys.iter() {
+            *self.active_subscriptions.entry(key.clone()).or_insert(0) += 1;
+        }
+        self.subscriptions.insert(request_id.to_string(), keys);
+    }
+
+    fn release_slot(&mut self, request_id: &str) {
+        if let Some(slot) = self.requests.remove(request_id) {
+            for key in slot.keys.iter() {
+                decrement(&mut self.in_flight, key);
+            }
+        }
+    }
+
+    // Frees the slots of requests the driver never answered, and forgets the
+    // buckets of requesters idle long enough for them to be full again.
+    fn sweep(&mut self, limits: &RateLimits, now: Instant) {
+        if matches!(self.last_sweep, Some(last) if now.saturating_duration_since(last) < SWEEP_INTERVAL) {
+            return;
+        }
+        self.last_sweep = Some(now);
+        let timeout = limits.request_timeout();
+        let timed_out: Vec<String> = self.requests
+            .iter()
+            .filter(|(_, slot)| now.saturating_duration_since(slot.acquired) >= timeout)
+            .map(|(request_id, _)| request_id.to_string())
+            .collect();
+        for request_id in timed_out {
+            warn!("Request {} timed out after {:?}, freeing its concurrency slot", request_id, timeout);
+            self.release_slot(&request_id);
+        }
+        self.buckets.retain(|key, bucket| {
+            let spec = limits.spec_for(key);
+            match spec.requests_per_sec {
+                Some(rate) => {
+                    let capacity = spec.burst.map(|b| b as f64).unwrap_or(rate.ceil().max(1.0));
+                    bucket.tokens_at(rate, capacity, now) < capacity
+                }
+                None => false,
+            }
+        });
+    }
+
+    fn throttle(&mut self, key: &RequesterKey, limits: &RateLimits, reason: String) -> Throttled {
+        *self.throttled.entry(limits.counted_as(key)).or_insert(0) += 1;
+        Throttled { key: key.clone(), reason }
+    }
+}
+
+// Counts that drop to zero are removed, so that idle requesters are not kept.
+fn decrement(counts: &mut HashMap<RequesterKey, u32>, key: &RequesterKey) {
+    if let Some(count) = counts.get_mut(key) {
+        *count = count.saturating_sub(1);
+        if *count == 0 {
+            counts.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn query(relay: &str, network: &str, org: &str) -> Query {
+        Query {
+            requesting_relay: relay.to_string(),
+            requesting_network: network.to_string(),
+            requesting_org: org.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn token_bucket_refills_over_time() {
+        let limits = RateLimits {
+            default: RateLimitSpec { requests_per_sec: Some(2.0), burst: Some(2), ..Default::default() },
+            ..Default::default()
+        };
+        let limiter = RateLimiter::new();
+        let q = query("Fabric_Relay", "network1", "");
+        let start = Instant::now();
+        assert!(limiter.try_acquire_request_at("1", &q, &limits, start).is_ok());
+        assert!(limiter.try_acquire_request_at("2", &q, &limits, start).is_ok());
+        let Err(Refused::Throttled(throttled)) = limiter.try_acquire_request_at("3", &q, &limits, start) else {
+            panic!("the third request should be throttled");
+        };
+        assert_eq!(throttled.key.kind, RequesterKind::Relay);
+        assert!(limiter.try_acquire_request_at("4", &q, &limits, start + Duration::from_millis(500)).is_ok());
+        assert_eq!(limiter.throttle_counts().len(), 1);
+    }
+
+    #[test]
+    fn specific_limits_override_default() {
+        let mut limits = RateLimits {
+            default: RateLimitSpec { max_concurrent: Some(1), ..Default::default() },
+            ..Default::default()
+        };
+        limits.networks.insert("network2".to_string(), RateLimitSpec { max_concurrent: Some(3), ..Default::default() });
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+        let q = query("", "network2", "");
+        for id in ["1", "2", "3"] {
+            assert!(limiter.try_acquire_request_at(id, &q, &limits, now).is_ok());
+        }
+        assert!(limiter.try_acquire_request_at("4", &q, &limits, now).is_err());
+        limiter.release_request("1");
+        assert!(limiter.try_acquire_request_at("5", &q, &limits, now).is_ok());
+    }
+
+    #[test]
+    fn subscription_cap_is_released_on_unsubscribe() {
+        let limits = RateLimits {
+            default: RateLimitSpec { max_subscriptions: Some(1), ..Default::default() },
+            ..Default::default()
+        };
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+        let q = query("Corda_Relay", "Corda_Network", "PartyA");
+        assert!(limiter.try_acquire_subscription_at("1", &q, &limits, now).is_ok());
+        assert!(limiter.try_acquire_subscription_at("1", &q, &limits, now).is_ok());
+        assert!(limiter.try_acquire_subscription_at("2", &q, &limits, now).is_err());
+        limiter.release_subscription("1");
+        assert!(limiter.try_acquire_subscription_at("2", &q, &limits, now).is_ok());
+    }
+
+    #[test]
+    fn duplicate_request_ids_are_refused() {
+        let limits = RateLimits {
+            default: RateLimitSpec { max_concurrent: Some(2), ..Default::default() },
+            ..Default::default()
+        };
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+        let q = query("Fabric_Relay", "network1", "");
+        assert!(limiter.try_acquire_request_at("1", &q, &limits, now).is_ok());
+        assert_eq!(limiter.try_acquire_request_at("1", &q, &limits, now), Err(Refused::DuplicateRequest("1".to_string())));
+        limiter.release_request("1");
+        assert!(limiter.state.lock().unwrap().in_flight.is_empty());
+    }
+
+    #[test]
+    fn slots_of_unanswered_requests_time_out() {
+        let limits = RateLimits {
+            request_timeout_secs: Some(60),
+            default: RateLimitSpec { max_concurrent: Some(1), ..Default::default() },
+            ..Default::default()
+        };
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+        let q = query("Fabric_Relay", "network1", "");
+        assert!(limiter.try_acquire_request_at("1", &q, &limits, now).is_ok());
+        assert!(limiter.try_acquire_request_at("2", &q, &limits, now + Duration::from_secs(30)).is_err());
+        assert!(limiter.try_acquire_request_at("2", &q, &limits, now + Duration::from_secs(61)).is_ok());
+        // The late answer of the timed out request frees nothing.
+        limiter.release_request("1");
+        assert!(limiter.try_acquire_request_at("3", &q, &limits, now + Duration::from_secs(61)).is_err());
+    }
+
+    #[test]
+    fn idle_requesters_are_forgotten() {
+        let limits = RateLimits {
+            default: RateLimitSpec { requests_per_sec: Some(1.0), ..Default::default() },
+            ..Default::default()
+        };
+        let limiter = RateLimiter::new();
+        let now = Instant::now();
+        for id in 0..100 {
+            let q = query(&format!("Relay_{}", id), "", "");
+            assert!(limiter.try_acquire_request_at(&id.to_string(), &q, &limits, now).is_ok());
+            assert!(limiter.try_acquire_request_at(&format!("again-{}", id), &q, &limits, now).is_err());
+            limiter.release_request(&id.to_string());
+        }
+        assert_eq!(limiter.throttle_counts(), vec![(RequesterKey { kind: RequesterKind::Relay, id: "default".to_string() }, 100)]);
+        let q = query("Relay_0", "", "");
+        assert!(limiter.try_acquire_request_at("last", &q, &limits, now + Duration::from_secs(10)).is_ok());
+        let state = limiter.state.lock().unwrap();
+        assert_eq!(state.buckets.len(), 1);
+        assert_eq!(state.in_flight.len(), 1);
+    }
+}
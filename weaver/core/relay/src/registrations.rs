@@ -0,0 +1,317 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::db::{decode_exact, Database};
+use crate::error::Error;
+use crate::relay_config::RelayConfig;
+use crate::relay_proto::LocationSegment;
+use crate::services::types::{default_ejection_msec, default_selection, Driver, Network};
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+const DRIVER_KEY_PREFIX: &str = "registration_driver_";
+const NETWORK_KEY_PREFIX: &str = "registration_network_";
+const RELAY_KEY_PREFIX: &str = "registration_relay_";
+
+// Registrations stored before they were versioned.
+const UNVERSIONED_DRIVER_KEY_PREFIX: &str = "registered_driver_";
+const UNVERSIONED_NETWORK_KEY_PREFIX: &str = "registered_network_";
+const UNVERSIONED_RELAY_KEY_PREFIX: &str = "registered_relay_";
+
+/// Layout version of the registrations the relay stores. It has to be bumped
+/// whenever the layout of Driver, Network or LocationSegment changes, with
+/// the records of the earlier version migrated in `load_entries`.
+const REGISTRATION_VERSION: u32 = 1;
+
+/// Registrations are the drivers, network bindings and remote relays added
+/// through the admin service at runtime. They are persisted in the relay db
+/// and applied on top of the relay config file, replacing entries of the same
+/// name.
+#[derive(Clone, Default, Debug)]
+pub struct Registrations {
+    pub drivers: HashMap<String, Driver>,
+    pub networks: HashMap<String, Network>,
+    pub relays: HashMap<String, LocationSegment>,
+}
+
+impl Registrations {
+    pub fn load(db: &Database) -> Result<Registrations, Error> {
+        migrate_unversioned(db, UNVERSIONED_DRIVER_KEY_PREFIX, DRIVER_KEY_PREFIX, decode_unversioned_driver)?;
+        migrate_unversioned(db, UNVERSIONED_NETWORK_KEY_PREFIX, NETWORK_KEY_PREFIX, decode_unversioned_network)?;
+        migrate_unversioned(db, UNVERSIONED_RELAY_KEY_PREFIX, RELAY_KEY_PREFIX, decode_unversioned_relay)?;
+        Ok(Registrations {
+            drivers: load_entries(db, DRIVER_KEY_PREFIX)?,
+            networks: load_entries(db, NETWORK_KEY_PREFIX)?,
+            relays: load_entries(db, RELAY_KEY_PREFIX)?,
+        })
+    }
+
+    pub fn apply(&self, conf: &mut RelayConfig) {
+        conf.drivers.extend(self.drivers.clone());
+        conf.networks.extend(self.networks.clone());
+        conf.relays.extend(self.relays.clone());
+    }
+}
+
+/// The database registrations are stored in, the relay's local request db.
+pub fn registrations_db(conf: &RelayConfig) -> Database {
+    Database {
+        db_path: conf.db_path.to_string(),
+        db_open_max_retries: conf.db_open_max_retries,
+        db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+    }
+}
+
+pub fn driver_key(name: &str) -> String {
+    format!("{}{}", DRIVER_KEY_PREFIX, name)
+}
+
+pub fn network_key(network_id: &str) -> String {
+    format!("{}{}", NETWORK_KEY_PREFIX, network_id)
+}
+
+pub fn relay_key(name: &str) -> String {
+    format!("{}{}", RELAY_KEY_PREFIX, name)
+}
+
+// A registration as it is stored: the entry encoded in the layout of its version.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredRegistration {
+    version: u32,
+    entry: Vec<u8>,
+}
+
+/// Stores a registration in the current layout version.
+pub fn store_registration<T: Serialize>(db: &Database, key: &str, entry: &T) -> Result<(), Error> {
+    let stored = StoredRegistration {
+        version: REGISTRATION_VERSION,
+        entry: bincode::serialize(entry)?,
+    };
+    db.set(&key.to_string(), &stored)?;
+    Ok(())
+}
+
+pub fn remove_registration(db: &Database, key: &str) -> Result<(), Error> {
+    db.remove(key.to_string())
+}
+
+fn load_entries<T: DeserializeOwned>(db: &Database, prefix: &str) -> Result<HashMap<String, T>, Error> {
+    let mut entries = HashMap::new();
+    for (key, stored) in db.scan_prefix::<StoredRegistration>(prefix.to_string())? {
+        if stored.version != REGISTRATION_VERSION {
+            return Err(Error::Simple(format!(
+                "Registration {} is stored in layout version {}, the relay reads version {}",
+                key, stored.version, REGISTRATION_VERSION
+            )));
+        }
+        entries.insert(key[prefix.len()..].to_string(), decode_exact(&stored.entry)?);
+    }
+    Ok(entries)
+}
+
+// Registrations stored before they were versioned are stored again with their
+// version, under the current keys. Each kind was stored in two layouts over
+// time with nothing telling them apart, so a record is taken for the layout
+// that decodes it exactly: decoding it as the other layout leaves bytes over
+// or runs out of them. Records that fit neither layout are left where they are.
+fn migrate_unversioned<T: Serialize>(
+    db: &Database,
+    unversioned_prefix: &str,
+    prefix: &str,
+    decode: fn(&[u8]) -> Option<T>,
+) -> Result<(), Error> {
+    for (key, bytes) in db.scan_prefix_bytes(unversioned_prefix.to_string())? {
+        let name = &key[unversioned_prefix.len()..];
+        match decode(&bytes) {
+            Some(entry) => {
+                store_registration(db, &format!("{}{}", prefix, name), &entry)?;
+                db.remove(key.to_string())?;
+                info!("Migrated registration {} to layout version {}", key, REGISTRATION_VERSION);
+            }
+            None => warn!("Registration {} is stored in an unknown layout and is not applied", key),
+        }
+    }
+    Ok(())
+}
+
+// Drivers registered before a driver could have several instances were
+// stored without the instance fields.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SingleInstanceDriver {
+    port: String,
+    hostname: String,
+    tls: bool,
+    tlsca_cert_path: String,
+}
+
+fn decode_unversioned_driver(bytes: &[u8]) -> Option<Driver> {
+    decode_exact::<Driver>(bytes).ok().or_else(|| {
+        decode_exact::<SingleInstanceDriver>(bytes).ok().map(|driver| Driver {
+            port: driver.port,
+            hostname: driver.hostname,
+            tls: driver.tls,
+            tlsca_cert_path: driver.tlsca_cert_path,
+            endpoints: vec![],
+            selection: default_selection(),
+            ejection_msec: default_ejection_msec(),
+        })
+    })
+}
+
+// Relays registered before a relay could have several endpoints were stored
+// without the endpoint fields. The fields keep the order of LocationSegment,
+// which differs from the order of Driver.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SingleEndpointRelay {
+    hostname: String,
+    port: String,
+    tls: bool,
+    tlsca_cert_path: String,
+}
+
+fn decode_unversioned_relay(bytes: &[u8]) -> Option<LocationSegment> {
+    decode_exact::<LocationSegment>(bytes).ok().or_else(|| {
+        decode_exact::<SingleEndpointRelay>(bytes).ok().map(|relay| LocationSegment {
+            hostname: relay.hostname,
+            port: relay.port,
+            tls: relay.tls,
+            tlsca_cert_path: relay.tlsca_cert_path,
+            priority: 0,
+            endpoints: vec![],
+        })
+    })
+}
+
+// Networks registered before they could name their driver were stored
+// without it, and served by the driver named after their type.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct TypeBoundNetwork {
+    network: String,
+    access_control_policies: Option<String>,
+    allowed_relays: Option<Vec<String>>,
+    denied_relays: Option<Vec<String>>,
+}
+
+fn decode_unversioned_network(bytes: &[u8]) -> Option<Network> {
+    decode_exact::<Network>(bytes).ok().or_else(|| {
+        decode_exact::<TypeBoundNetwork>(bytes).ok().map(|network| Network {
+            network: network.network,
+            driver: None,
+            access_control_policies: network.access_control_policies,
+            allowed_relays: network.allowed_relays,
+            denied_relays: network.denied_relays,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_db() -> Database {
+        Database {
+            db_path: std::env::temp_dir().join(format!("relay-registrations-{}", uuid::Uuid::new_v4())).to_string_lossy().to_string(),
+            db_open_max_retries: 10,
+            db_open_retry_backoff_msec: 10,
+        }
+    }
+
+    #[test]
+    fn relays_registered_with_a_single_endpoint_keep_their_address() {
+        let db = test_db();
+        let earlier = SingleEndpointRelay {
+            hostname: "relay.example.org".to_string(),
+            port: "9080".to_string(),
+            tls: true,
+            tlsca_cert_path: "ca.pem".to_string(),
+        };
+        db.set(&format!("{}Corda_Relay", UNVERSIONED_RELAY_KEY_PREFIX), &earlier).unwrap();
+
+        for _ in 0..2 {
+            let relay = Registrations::load(&db).unwrap().relays.remove("Corda_Relay").unwrap();
+            assert_eq!(relay.hostname, "relay.example.org");
+            assert_eq!(relay.port, "9080");
+            assert!(relay.tls);
+            assert_eq!(relay.tlsca_cert_path, "ca.pem");
+            assert!(relay.endpoints.is_empty());
+        }
+        let _ = std::fs::remove_dir_all(&db.db_path);
+    }
+
+    #[test]
+    fn registrations_round_trip_in_their_version() {
+        let db = test_db();
+        let driver = Driver {
+            port: "9090".to_string(),
+            hostname: "driver".to_string(),
+            tls: false,
+            tlsca_cert_path: "".to_string(),
+            endpoints: vec![],
+            selection: default_selection(),
+            ejection_msec: default_ejection_msec(),
+        };
+        let network = Network {
+            network: "Fabric".to_string(),
+            driver: Some("Fabric_Driver".to_string()),
+            access_control_policies: Some("config/policies.json".to_string()),
+            allowed_relays: Some(vec!["Corda_Relay".to_string()]),
+            denied_relays: None,
+        };
+        let relay = LocationSegment {
+            hostname: "relay".to_string(),
+            port: "9080".to_string(),
+            tls: true,
+            tlsca_cert_path: "ca.pem".to_string(),
+            priority: 1,
+            endpoints: vec![],
+        };
+        store_registration(&db, &driver_key("Fabric_Driver"), &driver).unwrap();
+        store_registration(&db, &network_key("network1"), &network).unwrap();
+        store_registration(&db, &relay_key("Corda_Relay"), &relay).unwrap();
+
+        let mut registrations = Registrations::load(&db).unwrap();
+        assert_eq!(registrations.drivers.remove("Fabric_Driver"), Some(driver));
+        assert_eq!(registrations.networks.remove("network1"), Some(network));
+        assert_eq!(registrations.relays.remove("Corda_Relay"), Some(relay));
+
+        remove_registration(&db, &network_key("network1")).unwrap();
+        assert!(Registrations::load(&db).unwrap().networks.is_empty());
+        let _ = std::fs::remove_dir_all(&db.db_path);
+    }
+
+    #[test]
+    fn unversioned_networks_keep_their_fields() {
+        let db = test_db();
+        // Stored before networks could name their driver.
+        let type_bound = TypeBoundNetwork {
+            network: "Fabric".to_string(),
+            access_control_policies: Some("config/policies.json".to_string()),
+            allowed_relays: Some(vec!["Corda_Relay".to_string()]),
+            denied_relays: None,
+        };
+        db.set(&format!("{}network1", UNVERSIONED_NETWORK_KEY_PREFIX), &type_bound).unwrap();
+        // Stored after, but before registrations were versioned.
+        let driver_bound = Network {
+            network: "Fabric".to_string(),
+            driver: Some("Fabric_Driver".to_string()),
+            access_control_policies: None,
+            allowed_relays: None,
+            denied_relays: Some(vec!["Other_Relay".to_string()]),
+        };
+        db.set(&format!("{}network2", UNVERSIONED_NETWORK_KEY_PREFIX), &driver_bound).unwrap();
+
+        for _ in 0..2 {
+            let networks = Registrations::load(&db).unwrap().networks;
+            let network1 = &networks["network1"];
+            assert_eq!(network1.driver, None);
+            assert_eq!(network1.access_control_policies.as_deref(), Some("config/policies.json"));
+            assert_eq!(network1.allowed_relays, Some(vec!["Corda_Relay".to_string()]));
+            assert_eq!(networks["network2"], driver_bound);
+        }
+        assert!(db.scan_prefix_bytes(UNVERSIONED_NETWORK_KEY_PREFIX.to_string()).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(&db.db_path);
+    }
+}
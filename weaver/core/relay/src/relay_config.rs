@@ -0,0 +1,444 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::access_control::load_policies;
+use crate::callbacks::CallbackSettings;
+use crate::discovery::Discovery;
+use crate::grpc_options::GrpcOptions;
+use crate::health::HealthChecks;
+use crate::logging::LoggingSettings;
+use crate::metrics::MetricsSettings;
+use crate::nonce_store::ReplayProtection;
+use crate::rate_limit::RateLimits;
+use crate::registrations::{registrations_db, Registrations};
+use crate::relay_proto::LocationSegment;
+use crate::result_cache::CacheSettings;
+use crate::routing::RoutingSettings;
+use crate::services::types::{Driver, Network, LEAST_LOADED, ROUND_ROBIN};
+use crate::shutdown::ShutdownSettings;
+use crate::trace_context::TraceSettings;
+
+use serde::de::DeserializeOwned;
+use weaverpb::common::access_control::AccessControlPolicy;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// RelayConfig is the relay config file parsed into typed settings. It is
+/// built and validated once when the config is loaded, so that request
+/// handling never runs into missing or malformed values.
+#[derive(Clone, serde::Serialize, Debug)]
+pub struct RelayConfig {
+    pub name: String,
+    pub hostname: String,
+    pub port: String,
+    pub tls: bool,
+    pub cert_path: String,
+    pub key_path: String,
+    pub db_path: String,
+    pub remote_db_path: String,
+    // max retries opening sled db if it is locked
+    pub db_open_max_retries: u32,
+    // retry back off time in ms if sled db is locked
+    pub db_open_retry_backoff_msec: u32,
+    pub config_reload_interval_msec: u64,
+    // Longest time a RequestStateSync call waits for the state of its query.
+    pub max_sync_wait_msec: u64,
+    // Token the admin service requires from callers, the service is disabled when empty.
+    pub admin_token: String,
+    // Address the admin service listens on, apart from the services other relays and networks
+    // call. The admin service is not served when no port is set.
+    pub admin_hostname: String,
+    pub admin_port: String,
+    pub networks: HashMap<String, Network>,
+    pub relays: HashMap<String, LocationSegment>,
+    pub drivers: HashMap<String, Driver>,
+    pub rate_limits: RateLimits,
+    pub replay_protection: ReplayProtection,
+    pub grpc: GrpcOptions,
+    pub discovery: Discovery,
+    pub health: HealthChecks,
+    pub metrics: MetricsSettings,
+    pub logging: LoggingSettings,
+    pub tracing: TraceSettings,
+    pub shutdown: ShutdownSettings,
+    pub routing: RoutingSettings,
+    pub cache: CacheSettings,
+    pub callbacks: CallbackSettings,
+    // Policies read from the access_control_policies file of each network that names one.
+    #[serde(skip)]
+    pub access_control_policies: HashMap<String, Vec<AccessControlPolicy>>,
+}
+
+impl RelayConfig {
+    /// Parses and validates the relay config, with the runtime registrations
+    /// applied on top of it. `None` reads the registrations persisted in the
+    /// relay db. All problems found are returned together, each naming the
+    /// setting it concerns.
+    pub fn from_config(conf: &config::Config, registrations: Option<&Registrations>) -> Result<RelayConfig, Vec<String>> {
+        let mut problems = Vec::new();
+        let mut relay_config = RelayConfig {
+            name: required(conf, "name", &mut problems),
+            hostname: optional(conf, "hostname", "localhost".to_string(), &mut problems),
+            port: required(conf, "port", &mut problems),
+            tls: optional(conf, "tls", false, &mut problems),
+            cert_path: optional(conf, "cert_path", "".to_string(), &mut problems),
+            key_path: optional(conf, "key_path", "".to_string(), &mut problems),
+            db_path: required(conf, "db_path", &mut problems),
+            remote_db_path: required(conf, "remote_db_path", &mut problems),
+            db_open_max_retries: optional(conf, "db_open_max_retries", 500, &mut problems),
+            db_open_retry_backoff_msec: optional(conf, "db_open_retry_backoff_msec", 10, &mut problems),
+            config_reload_interval_msec: optional(conf, "config_reload_interval_msec", 2000, &mut problems),
+            max_sync_wait_msec: optional(conf, "max_sync_wait_msec", 60000, &mut problems),
+            admin_token: optional(conf, "admin_token", "".to_string(), &mut problems),
+            admin_hostname: optional(conf, "admin_hostname", "127.0.0.1".to_string(), &mut problems),
+            admin_port: optional(conf, "admin_port", "".to_string(), &mut problems),
+            networks: table(conf, "networks", &mut problems),
+            relays: table(conf, "relays", &mut problems),
+            drivers: table(conf, "drivers", &mut problems),
+            rate_limits: RateLimits::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            replay_protection: ReplayProtection::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            grpc: GrpcOptions::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            discovery: Discovery::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            health: HealthChecks::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            metrics: MetricsSettings::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            logging: LoggingSettings::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            tracing: TraceSettings::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            shutdown: ShutdownSettings::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            routing: RoutingSettings::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            cache: CacheSettings::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            callbacks: CallbackSettings::from_config(conf).unwrap_or_else(|e| problem(e, &mut problems)),
+            access_control_policies: HashMap::new(),
+        };
+        if problems.is_empty() {
+            match registrations {
+                Some(registrations) => registrations.apply(&mut relay_config),
+                None => match Registrations::load(&registrations_db(&relay_config)) {
+                    Ok(registrations) => registrations.apply(&mut relay_config),
+                    Err(e) => problems.push(format!("Unable to read registrations from {}: {:?}", relay_config.db_path, e)),
+                },
+            }
+        }
+        relay_config.validate(&mut problems);
+        relay_config.load_access_control_policies(&mut problems);
+        if problems.is_empty() {
+            Ok(relay_config)
+        } else {
+            Err(problems)
+        }
+    }
+
+    fn validate(&self, problems: &mut Vec<String>) {
+        check_endpoint("hostname", &self.hostname, "port", &self.port, problems);
+        if self.tls {
+            check_file("cert_path", &self.cert_path, problems);
+            check_file("key_path", &self.key_path, problems);
+        }
+        let mut endpoints = vec![("relay server".to_string(), self.hostname.to_string(), self.port.to_string())];
+        if !self.admin_port.is_empty() {
+            check_endpoint("admin_hostname", &self.admin_hostname, "admin_port", &self.admin_port, problems);
+            endpoints.push(("admin server".to_string(), self.admin_hostname.to_string(), self.admin_port.to_string()));
+        }
+        for (relay_name, relay) in sorted(&self.relays) {
+            let prefix = format!("relays.{}", relay_name);
+            check_endpoint(&format!("{}.hostname", prefix), &relay.hostname, &format!("{}.port", prefix), &relay.port, problems);
+            if relay.tls {
+                check_file(&format!("{}.tlsca_cert_path", prefix), &relay.tlsca_cert_path, problems);
+            }
+            // A relay may list itself, so that its own networks can be queried through it.
+            if *relay_name != self.name {
+                endpoints.push((prefix.to_string(), relay.hostname.to_string(), relay.port.to_string()));
+            }
+            for (i, endpoint) in relay.endpoints.iter().enumerate() {
+                let endpoint_prefix = format!("{}.endpoints[{}]", prefix, i);
+                check_endpoint(
+                    &format!("{}.hostname", endpoint_prefix),
+                    &endpoint.hostname,
+                    &format!("{}.port", endpoint_prefix),
+                    &endpoint.port,
+                    problems,
+                );
+                if endpoint.tls {
+                    check_file(&format!("{}.tlsca_cert_path", endpoint_prefix), &endpoint.tlsca_cert_path, problems);
+                }
+                if !endpoint.endpoints.is_empty() {
+                    problems.push(format!("{}.endpoints: endpoints of a relay cannot list further endpoints", endpoint_prefix));
+                }
+                if *relay_name != self.name {
+                    endpoints.push((endpoint_prefix, endpoint.hostname.to_string(), endpoint.port.to_string()));
+                }
+            }
+        }
+        for (driver_name, driver) in sorted(&self.drivers) {
+            let prefix = format!("drivers.{}", driver_name);
+            check_endpoint(&format!("{}.hostname", prefix), &driver.hostname, &format!("{}.port", prefix), &driver.port, problems);
+            if driver.tls {
+                check_file(&format!("{}.tlsca_cert_path", prefix), &driver.tlsca_cert_path, problems);
+            }
+            endpoints.push((prefix.to_string(), driver.hostname.to_string(), driver.port.to_string()));
+            for (i, endpoint) in driver.endpoints.iter().enumerate() {
+                let endpoint_prefix = format!("{}.endpoints[{}]", prefix, i);
+                check_endpoint(
+                    &format!("{}.hostname", endpoint_prefix),
+                    &endpoint.hostname,
+                    &format!("{}.port", endpoint_prefix),
+                    &endpoint.port,
+                    problems,
+                );
+                if endpoint.tls {
+                    check_file(&format!("{}.tlsca_cert_path", endpoint_prefix), &endpoint.tlsca_cert_path, problems);
+                }
+                endpoints.push((endpoint_prefix, endpoint.hostname.to_string(), endpoint.port.to_string()));
+            }
+            if driver.selection != ROUND_ROBIN && driver.selection != LEAST_LOADED {
+                problems.push(format!(
+                    "{}.selection: \"{}\" is not a selection policy, expected \"{}\" or \"{}\"",
+                    prefix, driver.selection, ROUND_ROBIN, LEAST_LOADED
+                ));
+            }
+            if driver.ejection_msec == 0 {
+                problems.push(format!("{}.ejection_msec: must be greater than 0", prefix));
+            }
+        }
+        for (i, (name, hostname, port)) in endpoints.iter().enumerate() {
+            if let Some((other, _, _)) = endpoints[..i]
+                .iter()
+                .find(|(_, other_hostname, other_port)| other_hostname.eq_ignore_ascii_case(hostname) && other_port == port)
+            {
+                problems.push(format!("{} and {} both use {}:{}", other, name, hostname, port));
+            }
+        }
+        for (network_id, network) in sorted(&self.networks) {
+            if let Some(driver) = &network.driver {
+                if !self.drivers.contains_key(driver) {
+                    problems.push(format!(
+                        "networks.{}.driver: unknown driver \"{}\", expected one of the drivers in [drivers]",
+                        network_id, driver
+                    ));
+                }
+            } else if !self.drivers.contains_key(&network.network) {
+                problems.push(format!(
+                    "networks.{}.network: unknown network type \"{}\", expected one of the drivers in [drivers]",
+                    network_id, network.network
+                ));
+            }
+        }
+        if !self.discovery.registry_file.is_empty() {
+            check_file("discovery.registry_file", &self.discovery.registry_file, problems);
+            check_file("discovery.registry_signature_file", &self.discovery.registry_signature_file, problems);
+            check_file("discovery.registry_public_key", &self.discovery.registry_public_key, problems);
+        }
+        if !self.discovery.dns_srv_domain.is_empty() && !is_valid_hostname(self.discovery.dns_srv_domain.trim_end_matches('.')) {
+            problems.push(format!("discovery.dns_srv_domain: \"{}\" is not a valid domain", self.discovery.dns_srv_domain));
+        }
+        if self.discovery.dns_tls {
+            check_file("discovery.dns_tlsca_cert_path", &self.discovery.dns_tlsca_cert_path, problems);
+        }
+        for (network_id, next_hop) in sorted(&self.routing.routes) {
+            if self.networks.contains_key(network_id) {
+                problems.push(format!("routing.routes.{}: the network is served by this relay, it cannot be routed", network_id));
+            }
+            if *next_hop == self.name {
+                problems.push(format!("routing.routes.{}: the next hop cannot be this relay", network_id));
+            }
+        }
+    }
+
+    // Read after the registrations are applied, since they can name a policies file too.
+    fn load_access_control_policies(&mut self, problems: &mut Vec<String>) {
+        let mut policies = HashMap::new();
+        for (network_id, network) in sorted(&self.networks) {
+            if let Some(path) = &network.access_control_policies {
+                match load_policies(path) {
+                    Ok(network_policies) => {
+                        policies.insert(network_id.to_string(), network_policies);
+                    }
+                    Err(e) => problems.push(format!("networks.{}.access_control_policies: {}", network_id, e)),
+                }
+            }
+        }
+        self.access_control_policies = policies;
+    }
+}
+
+fn problem<T: Default>(message: String, problems: &mut Vec<String>) -> T {
+    problems.push(message);
+    T::default()
+}
+
+fn required<T: DeserializeOwned + Default>(conf: &config::Config, key: &str, problems: &mut Vec<String>) -> T {
+    conf.get::<T>(key).unwrap_or_else(|e| match e {
+        config::ConfigError::NotFound(_) => problem(format!("{}: missing", key), problems),
+        e => problem(format!("{}: {}", key, e), problems),
+    })
+}
+
+fn optional<T: DeserializeOwned>(conf: &config::Config, key: &str, default: T, problems: &mut Vec<String>) -> T {
+    match conf.get::<T>(key) {
+        Ok(value) => value,
+        Err(config::ConfigError::NotFound(_)) => default,
+        Err(e) => {
+            problems.push(format!("{}: {}", key, e));
+            default
+        }
+    }
+}
+
+// Reads every entry of a table separately, so that each malformed entry is reported.
+fn table<T: DeserializeOwned>(conf: &config::Config, key: &str, problems: &mut Vec<String>) -> HashMap<String, T> {
+    let entries = match conf.get_table(key) {
+        Ok(entries) => entries,
+        Err(config::ConfigError::NotFound(_)) => return HashMap::new(),
+        Err(e) => return problem(format!("{}: {}", key, e), problems),
+    };
+    let mut parsed = HashMap::new();
+    let mut names: Vec<&String> = entries.keys().collect();
+    names.sort();
+    for name in names {
+        match entries[name].clone().try_into::<T>() {
+            Ok(entry) => {
+                parsed.insert(name.to_string(), entry);
+            }
+            Err(e) => problems.push(format!("{}.{}: {}", key, name, e)),
+        }
+    }
+    parsed
+}
+
+// Table entries in name order, so that problems are reported in a stable order.
+fn sorted<T>(entries: &HashMap<String, T>) -> Vec<(&String, &T)> {
+    let mut sorted: Vec<(&String, &T)> = entries.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(b.0));
+    sorted
+}
+
+fn check_endpoint(hostname_key: &str, hostname: &str, port_key: &str, port: &str, problems: &mut Vec<String>) {
+    if !is_valid_hostname(hostname) {
+        problems.push(format!("{}: \"{}\" is not a valid hostname or IP address", hostname_key, hostname));
+    }
+    if port.parse::<u16>().map_or(true, |port| port == 0) {
+        problems.push(format!("{}: \"{}\" is not a valid port", port_key, port));
+    }
+}
+
+fn check_file(key: &str, path: &str, problems: &mut Vec<String>) {
+    if path.is_empty() {
+        problems.push(format!("{}: missing", key));
+    } else if !Path::new(path).is_file() {
+        problems.push(format!("{}: file \"{}\" does not exist", key, path));
+    }
+}
+
+fn is_valid_hostname(hostname: &str) -> bool {
+    if hostname.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    !hostname.is_empty()
+        && hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID_CONFIG: &str = r#"
+name="Fabric_Relay"
+port="9080"
+hostname="localhost"
+db_path="db/Fabric_Relay/requests"
+remote_db_path="db/Fabric_Relay/remote_request"
+[networks.network1]
+network="Fabric"
+[relays.Fabric_Relay]
+hostname="localhost"
+port="9080"
+tls=false
+tlsca_cert_path=""
+[relays.Corda_Relay]
+hostname="localhost"
+port="9081"
+tls=false
+tlsca_cert_path=""
+[drivers.Fabric]
+hostname="localhost"
+port="9090"
+tls=false
+tlsca_cert_path=""
+"#;
+
+    fn parse(toml: &str) -> Result<RelayConfig, Vec<String>> {
+        let mut conf = config::Config::default();
+        conf.merge(config::File::from_str(toml, config::FileFormat::Toml)).unwrap();
+        RelayConfig::from_config(&conf, Some(&Registrations::default()))
+    }
+
+    #[test]
+    fn valid_config_is_parsed() {
+        let relay_config = parse(VALID_CONFIG).unwrap();
+        assert_eq!(relay_config.db_open_max_retries, 500);
+        assert_eq!(relay_config.relays["Corda_Relay"].port, "9081");
+        assert_eq!(relay_config.drivers["Fabric"].port, "9090");
+        assert_eq!(relay_config.admin_hostname, "127.0.0.1");
+        assert!(relay_config.admin_port.is_empty(), "the admin service is only served when configured");
+    }
+
+    #[test]
+    fn admin_address_is_checked_against_other_endpoints() {
+        let problems = parse(&format!("admin_hostname=\"localhost\"\nadmin_port=\"9090\"\n{}", VALID_CONFIG)).unwrap_err();
+        assert_eq!(problems, vec!["admin server and drivers.Fabric both use localhost:9090".to_string()]);
+    }
+
+    #[test]
+    fn all_problems_are_reported_together() {
+        let toml = VALID_CONFIG
+            .replace("remote_db_path=\"db/Fabric_Relay/remote_request\"\n", "")
+            .replace("network=\"Fabric\"", "network=\"Besu\"")
+            .replace("port=\"9081\"\ntls=false", "port=\"9090\"\ntls=true")
+            + "[drivers.Corda]\nhostname=\"bad host\"\nport=\"9099\"\ntls=false\ntlsca_cert_path=\"\"\n";
+        let problems = parse(&toml).unwrap_err();
+        assert_eq!(
+            problems,
+            vec![
+                "remote_db_path: missing".to_string(),
+                "relays.Corda_Relay.tlsca_cert_path: missing".to_string(),
+                "drivers.Corda.hostname: \"bad host\" is not a valid hostname or IP address".to_string(),
+                "relays.Corda_Relay and drivers.Fabric both use localhost:9090".to_string(),
+                "networks.network1.network: unknown network type \"Besu\", expected one of the drivers in [drivers]".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn networks_of_one_type_bind_their_own_drivers() {
+        let toml = VALID_CONFIG.to_string()
+            + "[networks.network2]\nnetwork=\"Fabric\"\ndriver=\"Fabric_2\"\n"
+            + "[drivers.Fabric_2]\nhostname=\"localhost\"\nport=\"9095\"\ntls=false\ntlsca_cert_path=\"\"\n";
+        let relay_config = parse(&toml).unwrap();
+        assert_eq!(relay_config.networks["network1"].driver_name(), "Fabric");
+        assert_eq!(relay_config.networks["network2"].driver_name(), "Fabric_2");
+
+        let problems = parse(&toml.replace("driver=\"Fabric_2\"", "driver=\"Fabric_3\"")).unwrap_err();
+        assert_eq!(
+            problems,
+            vec!["networks.network2.driver: unknown driver \"Fabric_3\", expected one of the drivers in [drivers]".to_string()]
+        );
+    }
+
+    #[test]
+    fn hostnames_are_checked() {
+        assert!(is_valid_hostname("relay-network1"));
+        assert!(is_valid_hostname("relay.example.com"));
+        assert!(is_valid_hostname("::1"));
+        assert!(!is_valid_hostname(""));
+        assert!(!is_valid_hostname("-relay"));
+        assert!(!is_valid_hostname("relay:9080"));
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::error::Error;
+use crate::relay_proto::LocationSegment;
+use crate::services::helpers::get_relay_channel;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+use tracing::{info, warn};
+
+/// How long a relay endpoint that could not be reached is passed over.
+const EJECTION: Duration = Duration::from_secs(30);
+
+/// Tracks which endpoints of remote relays could not be reached, so that
+/// calls go to a healthy endpoint of the relay.
+pub struct RelayPool {
+    ejected_until: Mutex<HashMap<String, Instant>>,
+}
+
+static RELAY_POOL: OnceLock<RelayPool> = OnceLock::new();
+
+/// The relay's pool of remote relay endpoints, created on first use.
+pub fn relay_pool() -> &'static RelayPool {
+    RELAY_POOL.get_or_init(RelayPool::new)
+}
+
+impl RelayPool {
+    fn new() -> RelayPool {
+        RelayPool {
+            ejected_until: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The endpoints of a relay in the order a call tries them: by priority,
+    /// with endpoints that were recently unreachable last, as a last resort.
+    pub fn candidates(&self, endpoints: &[LocationSegment]) -> Vec<LocationSegment> {
+        self.candidates_at(endpoints, Instant::now())
+    }
+
+    fn candidates_at(&self, endpoints: &[LocationSegment], now: Instant) -> Vec<LocationSegment> {
+        let ejected_until = self.ejected_until.lock().unwrap();
+        let until = |endpoint: &LocationSegment| ejected_until.get(&endpoint.address()).copied();
+        let (healthy, mut ejected): (Vec<_>, Vec<_>) = endpoints
+            .iter()
+            .cloned()
+            .partition(|endpoint| !matches!(until(endpoint), Some(until) if until > now));
+        ejected.sort_by_key(|endpoint| until(endpoint));
+        healthy.into_iter().chain(ejected).collect()
+    }
+
+    /// Passes over an endpoint for a while.
+    pub fn eject(&self, endpoint: &LocationSegment) {
+        let previous = self.ejected_until.lock().unwrap().insert(endpoint.address(), Instant::now() + EJECTION);
+        if previous.is_none() {
+            warn!("Relay endpoint {} is unreachable, passing over it", endpoint.address());
+        }
+    }
+
+    /// Takes an endpoint back once it has been reached.
+    pub fn restore(&self, endpoint: &LocationSegment) {
+        if self.ejected_until.lock().unwrap().remove(&endpoint.address()).is_some() {
+            info!("Relay endpoint {} is reachable again", endpoint.address());
+        }
+    }
+}
+
+/// Makes a call to a remote relay, failing over to its next endpoint while
+/// an endpoint cannot be reached. Returns the endpoint that took the call.
+pub async fn call_relay<T, F, Fut>(endpoints: &[LocationSegment], mut call: F) -> Result<(T, LocationSegment), Error>
+where
+    F: FnMut(Channel) -> Fut,
+    Fut: Future<Output = Result<T, tonic::Status>>,
+{
+    let mut last_error = None;
+    for endpoint in relay_pool().candidates(endpoints) {
+        let result = match get_relay_channel(&endpoint).await {
+            Ok(channel) => call(channel).await.map_err(Error::from),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(reply) => {
+                relay_pool().restore(&endpoint);
+                return Ok((reply, endpoint));
+            }
+            Err(e) if e.is_unreachable() => {
+                warn!("Relay endpoint {} could not be reached: {:?}", endpoint.address(), e);
+                relay_pool().eject(&endpoint);
+                last_error = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| Error::Simple("No relay endpoint to call".to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn endpoints_are_tried_by_priority_and_health() {
+        let endpoint = |port: &str, priority: u32| LocationSegment {
+            hostname: "localhost".to_string(),
+            port: port.to_string(),
+            tls: false,
+            tlsca_cert_path: "".to_string(),
+            priority,
+            endpoints: vec![],
+        };
+        let relay = LocationSegment {
+            endpoints: vec![endpoint("9082", 0), endpoint("9081", 1)],
+            ..endpoint("9080", 1)
+        };
+        let ports = |endpoints: Vec<LocationSegment>| endpoints.into_iter().map(|e| e.port).collect::<Vec<_>>();
+        let instances = relay.instances();
+        assert_eq!(ports(instances.clone()), vec!["9082", "9080", "9081"]);
+
+        let pool = RelayPool::new();
+        let now = Instant::now();
+        pool.eject(&instances[0]);
+        assert_eq!(ports(pool.candidates_at(&instances, now)), vec!["9080", "9081", "9082"]);
+        assert_eq!(ports(pool.candidates_at(&instances, now + EJECTION * 2)), vec!["9082", "9080", "9081"]);
+        pool.restore(&instances[0]);
+        assert_eq!(ports(pool.candidates_at(&instances, now)), vec!["9082", "9080", "9081"]);
+    }
+}
@@ -2,14 +2,48 @@
 //
 // SPDX-License-Identifier: Apache-2.0
 
-use std::io::{Error, ErrorKind};
+use std::fmt;
+use std::net::Ipv6Addr;
+use tracing::debug;
 
 #[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, PartialOrd, Debug)]
 pub struct LocationSegment {
     pub hostname: String,
     pub port: String,
+    #[serde(default)]
     pub tls: bool,
+    #[serde(default)]
     pub tlsca_cert_path: String,
+    // Endpoints of a relay with a lower priority are tried first.
+    #[serde(default)]
+    pub priority: u32,
+    // Further endpoints of the relay, which calls fail over to.
+    #[serde(default)]
+    pub endpoints: Vec<LocationSegment>,
+}
+
+impl LocationSegment {
+    /// Every endpoint of the relay, in the order they are tried: by priority,
+    /// and endpoints of the same priority in the order they are listed.
+    pub fn instances(&self) -> Vec<LocationSegment> {
+        let primary = LocationSegment {
+            endpoints: vec![],
+            ..self.clone()
+        };
+        let mut instances: Vec<LocationSegment> =
+            std::iter::once(primary).chain(self.endpoints.iter().flat_map(|endpoint| endpoint.instances())).collect();
+        instances.sort_by_key(|instance| instance.priority);
+        instances
+    }
+
+    /// `host:port` of the endpoint, with an IPv6 host in brackets.
+    pub fn address(&self) -> String {
+        if self.hostname.contains(':') {
+            format!("[{}]:{}", self.hostname, self.port)
+        } else {
+            format!("{}:{}", self.hostname, self.port)
+        }
+    }
 }
 
 #[derive(PartialEq, PartialOrd, Debug)]
@@ -19,40 +53,160 @@ pub struct Address {
     pub network_id: String,
 }
 
-#[allow(dead_code)]
-pub fn parse_address(address: String) -> Result<Address, Error> {
-    println!("Address to be parsed: {}", address);
-    let v: Vec<&str> = address.split('/').collect();
-    if v.len() != 3 {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            "Invalid Address Format".to_string(),
-        ));
+/// Why a view address could not be parsed. `position` is the byte offset in
+/// the address at which the problem was found.
+#[derive(Clone, PartialEq, Debug)]
+pub struct AddressError {
+    pub address: String,
+    pub position: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid address \"{}\" at position {}: {}", self.address, self.position, self.reason)
     }
-    Ok(Address {
-        location: parse_location(v[0].to_string())?,
-        network_id: v[1].to_string(),
-        view: v[2].to_string(),
-    })
 }
 
-#[allow(dead_code)]
-fn parse_location(location: String) -> Result<LocationSegment, Error> {
-    let v: Vec<&str> = location.split(":").collect();
-    if v.len() != 2 {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            "Invalid Location Format".to_string(),
-        ));
+impl std::error::Error for AddressError {}
+
+const PLAINTEXT_SCHEME: &str = "grpc";
+const TLS_SCHEME: &str = "grpcs";
+
+/// Parses a view address of the form
+/// `[grpc://|grpcs://]<location>/<network-id>/<view>`. The `grpcs` scheme
+/// reaches the relay over TLS. The view is the rest of the address, `/`
+/// included, and `%XX` escapes in it are decoded. A `%` that does not start
+/// such an escape is kept as it is.
+pub fn parse_address(address: String) -> Result<Address, AddressError> {
+    parse_address_and_view_start(&address).map(|(parsed, _)| parsed)
+}
+
+/// The address with its view decoded, which is what a driver is sent: the
+/// view the access control policies were checked against.
+pub fn decoded_address(address: &str) -> Result<String, AddressError> {
+    let (parsed, view_start) = parse_address_and_view_start(address)?;
+    Ok(format!("{}{}", &address[..view_start], parsed.view))
+}
+
+// Parses an address, and also returns the position of its view.
+fn parse_address_and_view_start(address: &str) -> Result<(Address, usize), AddressError> {
+    debug!("Address to be parsed: {}", address);
+    let error = |position: usize, reason: &str| AddressError {
+        address: address.to_string(),
+        position,
+        reason: reason.to_string(),
+    };
+    // A scheme is only looked for before the first `/`, views may contain `://`.
+    let first_slash = address.find('/').ok_or_else(|| error(address.len(), "expected '/' after the location"))?;
+    let (tls, location_start) = match address[..first_slash].strip_suffix(':') {
+        Some(scheme) if address[first_slash..].starts_with("//") => match scheme {
+            PLAINTEXT_SCHEME => (false, first_slash + 2),
+            TLS_SCHEME => (true, first_slash + 2),
+            _ => return Err(error(0, "unsupported scheme, expected grpc:// or grpcs://")),
+        },
+        _ => (false, 0),
+    };
+    let location_end = address[location_start..]
+        .find('/')
+        .map(|end| location_start + end)
+        .ok_or_else(|| error(address.len(), "expected '/' after the location"))?;
+    let network_start = location_end + 1;
+    let network_end = address[network_start..]
+        .find('/')
+        .map(|end| network_start + end)
+        .ok_or_else(|| error(address.len(), "expected '/' after the network id"))?;
+    if network_end == network_start {
+        return Err(error(network_start, "the network id is empty"));
+    }
+    let mut location = parse_location(&address[location_start..location_end], location_start, &error)?;
+    location.tls = tls;
+    let address = Address {
+        location,
+        network_id: address[network_start..network_end].to_string(),
+        view: decode_view(&address[network_end + 1..]),
+    };
+    Ok((address, network_end + 1))
+}
+
+// A location is either `host:port` or `[ipv6]:port`, or a relay name without
+// a port, or empty, in which case the relay is discovered by the network id
+// of the address. `offset` is the position of the location in the address.
+fn parse_location(
+    location: &str,
+    offset: usize,
+    error: &dyn Fn(usize, &str) -> AddressError,
+) -> Result<LocationSegment, AddressError> {
+    let (hostname, port, port_offset) = if let Some(bracketed) = location.strip_prefix('[') {
+        let end = bracketed.find(']').ok_or_else(|| error(offset, "'[' of an IPv6 address is not closed"))?;
+        let hostname = &bracketed[..end];
+        if hostname.parse::<Ipv6Addr>().is_err() {
+            return Err(error(offset + 1, "not an IPv6 address"));
+        }
+        let port = bracketed[end + 1..]
+            .strip_prefix(':')
+            .ok_or_else(|| error(offset + end + 2, "expected ':<port>' after the IPv6 address"))?;
+        (hostname, port, offset + end + 3)
+    } else {
+        match location.split_once(':') {
+            Some((hostname, port)) => {
+                if let Some(colon) = port.find(':') {
+                    return Err(error(
+                        offset + hostname.len() + 1 + colon,
+                        "unexpected ':', IPv6 addresses must be enclosed in '[' and ']'",
+                    ));
+                }
+                if hostname.is_empty() {
+                    return Err(error(offset, "the hostname is empty"));
+                }
+                (hostname, port, offset + hostname.len() + 1)
+            }
+            None => (location, "", offset),
+        }
+    };
+    if location.contains(':') && !matches!(port.parse::<u16>(), Ok(port) if port > 0) {
+        return Err(error(port_offset, "the port must be a number from 1 to 65535"));
     }
     Ok(LocationSegment {
-        hostname: v[0].to_string(),
-        port: v[1].to_string(),
+        hostname: hostname.to_string(),
+        port: port.to_string(),
         tls: false,
         tlsca_cert_path: "".to_string(),
+        priority: 0,
+        endpoints: vec![],
     })
 }
 
+// Decodes the `%XX` escapes of a view. Views written before escapes were
+// decoded may contain a `%` of their own, so a `%` that is not followed by two
+// hex digits is kept, and so is a view that does not decode to valid UTF-8.
+fn decode_view(view: &str) -> String {
+    let bytes = view.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = match bytes[i] {
+            b'%' => view
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.bytes().all(|digit| digit.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match escape {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).unwrap_or_else(|_| view.to_string())
+}
+
+// Used by the clients, not by the relay.
 #[allow(dead_code)]
 pub fn get_url(args: &[String]) -> String {
     let port = if args.len() >= 2 {
@@ -60,7 +214,7 @@ pub fn get_url(args: &[String]) -> String {
     } else {
         "50051".to_string()
     };
-    return format!("localhost:{}", port);
+    format!("localhost:{}", port)
 }
 
 #[cfg(test)]
@@ -83,6 +237,8 @@ mod tests {
                     port: "8080".to_string(),
                     tls: false,
                     tlsca_cert_path: "".to_string(),
+                    priority: 0,
+                    endpoints: vec![],
                 },
             }
         );
@@ -96,12 +252,62 @@ mod tests {
                     port: "9081".to_string(),
                     tls: false,
                     tlsca_cert_path: "".to_string(),
+                    priority: 0,
+                    endpoints: vec![],
                 },
             }
         );
 
-        let result = parse_address("Corda_Relay:Fabric".to_string()).map_err(|e| e.kind());
-        let expected = Err(ErrorKind::InvalidData);
-        assert_eq!(expected, result);
+        let relay_name_location = parse_address("Corda_Relay/Corda_Network/myChannel:read:TestState".to_string()).unwrap();
+        assert_eq!(relay_name_location.location.hostname, "Corda_Relay");
+        assert_eq!(relay_name_location.location.port, "");
+
+        let result = parse_address("Corda_Relay:Fabric".to_string()).map_err(|e| e.position);
+        assert_eq!(Err(18), result);
+    }
+
+    #[test]
+    fn address_grammar() {
+        let tls = parse_address("grpcs://relay.example.org:9443/network1/a%2Fb:read%3Ax/y".to_string()).unwrap();
+        assert!(tls.location.tls);
+        assert_eq!(tls.location.address(), "relay.example.org:9443");
+        assert_eq!(tls.network_id, "network1");
+        assert_eq!(tls.view, "a/b:read:x/y");
+        assert!(!parse_address("grpc://localhost:9080/network1/view".to_string()).unwrap().location.tls);
+
+        let ipv6 = parse_address("[::1]:9080/network1/view".to_string()).unwrap();
+        assert_eq!(ipv6.location.hostname, "::1");
+        assert_eq!(ipv6.location.address(), "[::1]:9080");
+        assert_eq!(parse_address("/network1/view".to_string()).unwrap().location.hostname, "");
+
+        let position = |address: &str| parse_address(address.to_string()).unwrap_err().position;
+        assert_eq!(position("http://localhost:9080/network1/view"), 0);
+        assert_eq!(position("::1:9080/network1/view"), 1);
+        assert_eq!(position("[::1/network1/view"), 0);
+        assert_eq!(position("[::g]:9080/network1/view"), 1);
+        assert_eq!(position("[::1]/network1/view"), 5);
+        assert_eq!(position("localhost:90a/network1/view"), 10);
+        assert_eq!(position("localhost:9080//view"), 15);
+        assert_eq!(position("localhost:9080/network1"), 23);
+    }
+
+    #[test]
+    fn percent_signs_that_do_not_escape_are_kept() {
+        let view = |address: &str| parse_address(address.to_string()).unwrap().view;
+        assert_eq!(view("localhost:9080/network1/50%zz"), "50%zz");
+        assert_eq!(view("localhost:9080/network1/100%"), "100%");
+        assert_eq!(view("localhost:9080/network1/a%2"), "a%2");
+        assert_eq!(view("localhost:9080/network1/a%+1"), "a%+1");
+        assert_eq!(view("localhost:9080/network1/%ff%2F"), "%ff%2F");
+        assert_eq!(view("localhost:9080/network1/%C3%A9%25"), "é%");
+    }
+
+    #[test]
+    fn decoded_address_keeps_the_location_and_network() {
+        assert_eq!(
+            decoded_address("grpcs://[::1]:9443/network1/a%2Fb:read%3Ax%").unwrap(),
+            "grpcs://[::1]:9443/network1/a/b:read:x%"
+        );
+        assert!(decoded_address("localhost:9080/network1").is_err());
     }
 }
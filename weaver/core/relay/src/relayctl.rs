@@ -0,0 +1,300 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::common::ack::ack;
+use weaverpb::networks::networks::network_client::NetworkClient;
+use weaverpb::networks::networks::CancelRequestMessage;
+use weaverpb::relay::admin::relay_admin_client::RelayAdminClient;
+use weaverpb::relay::admin::{
+    CheckConnectivityRequest, ExpireRequestMessage, GetConfigRequest, ListEventSubscriptionsRequest,
+    ListRegistrationsRequest, RequestLookup,
+};
+
+use clap::{Parser, Subcommand};
+use std::error::Error;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+use tonic::Request;
+
+/// Operator CLI for inspecting and managing a running relay through its admin service.
+#[derive(Parser)]
+#[command(name = "relayctl")]
+struct Cli {
+    /// Address of the relay's admin service, as host:port
+    #[arg(long, env = "RELAY_ADMIN_ADDRESS")]
+    admin: String,
+    /// Address of the relay, as host:port, to which cancellations are sent
+    #[arg(long, env = "RELAY_ADDRESS", default_value = "localhost:9080")]
+    relay: String,
+    /// Connect over TLS, verifying the relay's certificate with this CA certificate
+    #[arg(long)]
+    tls_ca_cert: Option<PathBuf>,
+    /// Name the relay's certificate is issued to, the host connected to when not set
+    #[arg(long, requires = "tls_ca_cert")]
+    tls_domain: Option<String>,
+    /// The relay's admin token
+    #[arg(long, env = "RELAY_ADMIN_TOKEN", hide_env_values = true)]
+    token: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Show the state of a request
+    State { request_id: String },
+    /// List event subscriptions and their publication specs
+    Subscriptions,
+    /// Show an event subscription and its publication specs
+    Subscription { request_id: String },
+    /// Print the events received for a subscription
+    Events {
+        request_id: String,
+        /// Keep printing events as they are received
+        #[arg(long)]
+        follow: bool,
+        /// How often to check for new events when following
+        #[arg(long, default_value_t = 1000)]
+        interval_msec: u64,
+    },
+    /// End a pending request with an error
+    Expire {
+        request_id: String,
+        #[arg(long, default_value = "expired with relayctl")]
+        reason: String,
+    },
+    /// Cancel a pending request, also at the remote relay
+    Cancel { request_id: String },
+    /// Show the config the relay is running with
+    Config,
+    /// List the drivers, networks and remote relays in use
+    Peers,
+    /// Test connectivity from the relay to each driver and remote relay
+    Check {
+        #[arg(long, default_value_t = 2000)]
+        timeout_msec: u64,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = run(cli).await {
+        match e.downcast_ref::<tonic::Status>() {
+            Some(status) => eprintln!("relayctl: {:?}: {}", status.code(), status.message()),
+            None => eprintln!("relayctl: {}", e),
+        }
+        process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let mut client = RelayAdminClient::new(connect(&cli, &cli.admin).await?);
+    let mut network_client = NetworkClient::new(connect(&cli, &cli.relay).await?);
+    let token = cli.token.to_string();
+    match cli.command {
+        Command::State { request_id } => {
+            let state = client.get_request_state(admin_request(RequestLookup { request_id }, &token)?).await?;
+            println!("{:#?}", state.into_inner());
+        }
+        Command::Subscriptions => {
+            let list = client
+                .list_event_subscriptions(admin_request(ListEventSubscriptionsRequest {}, &token)?)
+                .await?
+                .into_inner();
+            println!("Subscriptions of this relay's networks:");
+            for subscription in list.subscriptions {
+                println!("{:#?}", subscription);
+            }
+            println!("Subscriptions served to remote relays:");
+            for subscription in list.served {
+                println!("{:#?}", subscription);
+            }
+        }
+        Command::Subscription { request_id } => {
+            let list = client
+                .list_event_subscriptions(admin_request(ListEventSubscriptionsRequest {}, &token)?)
+                .await?
+                .into_inner();
+            let subscription = list
+                .subscriptions
+                .into_iter()
+                .find(|subscription| subscription.request_id == request_id)
+                .ok_or(format!("No event subscription with request id {}", request_id))?;
+            println!("{:#?}", subscription);
+        }
+        Command::Events { request_id, follow, interval_msec } => {
+            let mut printed = 0;
+            loop {
+                let lookup = RequestLookup { request_id: request_id.to_string() };
+                let event_states = client.get_event_states(admin_request(lookup, &token)?).await?.into_inner();
+                for event_state in event_states.states.iter().skip(printed) {
+                    println!("{:#?}", event_state);
+                }
+                printed = printed.max(event_states.states.len());
+                if !follow {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(interval_msec)).await;
+            }
+        }
+        Command::Expire { request_id, reason } => {
+            let ack = client
+                .expire_request(admin_request(ExpireRequestMessage { request_id, reason }, &token)?)
+                .await?
+                .into_inner();
+            if ack.status != ack::Status::Ok as i32 {
+                return Err(ack.message.into());
+            }
+            println!("{}", ack.message);
+        }
+        Command::Cancel { request_id } => {
+            let message = CancelRequestMessage { request_id: request_id.to_string() };
+            let ack = network_client.cancel_request(Request::new(message)).await?.into_inner();
+            if ack.status != ack::Status::Ok as i32 {
+                return Err(ack.message.into());
+            }
+            println!("Cancelled request {}", request_id);
+        }
+        Command::Config => {
+            let view = client.get_config(admin_request(GetConfigRequest {}, &token)?).await?.into_inner();
+            let config: serde_json::Value = serde_json::from_str(&view.config_json)?;
+            println!("{}", serde_json::to_string_pretty(&config)?);
+        }
+        Command::Peers => {
+            let registrations = client
+                .list_registrations(admin_request(ListRegistrationsRequest {}, &token)?)
+                .await?
+                .into_inner();
+            for driver in registrations.drivers {
+                println!("driver  {}  {}:{}{}", driver.name, driver.hostname, driver.port, flags(driver.tls, driver.runtime));
+                for endpoint in driver.endpoints.iter() {
+                    println!("driver  {}  {}:{}{}", driver.name, endpoint.hostname, endpoint.port, flags(endpoint.tls, driver.runtime));
+                }
+            }
+            for network in registrations.networks {
+                println!("network  {}  {}  driver {}{}", network.network_id, network.network_type, network.driver, flags(false, network.runtime));
+            }
+            for relay in registrations.relays {
+                println!("relay  {}  {}:{}  priority {}{}", relay.name, relay.hostname, relay.port, relay.priority, flags(relay.tls, relay.runtime));
+                for endpoint in relay.endpoints.iter() {
+                    println!(
+                        "relay  {}  {}:{}  priority {}{}",
+                        relay.name,
+                        endpoint.hostname,
+                        endpoint.port,
+                        endpoint.priority,
+                        flags(endpoint.tls, relay.runtime)
+                    );
+                }
+            }
+        }
+        Command::Check { timeout_msec } => {
+            let report = client
+                .check_connectivity(admin_request(CheckConnectivityRequest { timeout_msec }, &token)?)
+                .await?
+                .into_inner();
+            let unreachable = report.peers.iter().filter(|peer| !peer.reachable).count();
+            for peer in report.peers {
+                if peer.reachable {
+                    println!("{}  {}  {}  ok  {} ms", peer.kind, peer.name, peer.endpoint, peer.latency_msec);
+                } else {
+                    println!("{}  {}  {}  unreachable  {}", peer.kind, peer.name, peer.endpoint, peer.message);
+                }
+            }
+            if unreachable > 0 {
+                return Err(format!("{} peers are unreachable", unreachable).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+// Connects lazily, so that only the address a command calls has to be reachable.
+async fn connect(cli: &Cli, address: &str) -> Result<Channel, Box<dyn Error>> {
+    let channel = match &cli.tls_ca_cert {
+        Some(tls_ca_cert) => {
+            let pem = tokio::fs::read(tls_ca_cert).await?;
+            let domain = match &cli.tls_domain {
+                Some(domain) => domain.to_string(),
+                None => address.rsplit_once(':').map_or(address, |(host, _port)| host).to_string(),
+            };
+            let tls = ClientTlsConfig::new()
+                .ca_certificate(Certificate::from_pem(pem))
+                .domain_name(domain);
+            Channel::from_shared(format!("https://{}", address))?.tls_config(tls)?.connect_lazy()
+        }
+        None => Channel::from_shared(format!("http://{}", address))?.connect_lazy(),
+    };
+    Ok(channel)
+}
+
+// Admin calls carry the admin token as `authorization: Bearer <token>`.
+fn admin_request<T>(message: T, token: &str) -> Result<Request<T>, Box<dyn Error>> {
+    let mut request = Request::new(message);
+    request.metadata_mut().insert("authorization", format!("Bearer {}", token).parse()?);
+    Ok(request)
+}
+
+fn flags(tls: bool, runtime: bool) -> String {
+    let mut flags = String::new();
+    if tls {
+        flags.push_str("  tls");
+    }
+    if runtime {
+        flags.push_str("  runtime");
+    }
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(["relayctl", "--admin", "relay1:9180", "--token", "secret"].iter().chain(args))
+    }
+
+    #[test]
+    fn subcommands_are_parsed_with_their_defaults() {
+        let cli = parse(&["state", "r1"]).unwrap();
+        assert_eq!(cli.admin, "relay1:9180");
+        assert_eq!(cli.relay, "localhost:9080");
+        assert_eq!(cli.token, "secret");
+        assert!(matches!(cli.command, Command::State { request_id } if request_id == "r1"));
+        let cli = parse(&["events", "r1"]).unwrap();
+        assert!(matches!(cli.command, Command::Events { follow: false, interval_msec: 1000, .. }));
+        let cli = parse(&["events", "r1", "--follow", "--interval-msec", "250"]).unwrap();
+        assert!(matches!(cli.command, Command::Events { follow: true, interval_msec: 250, .. }));
+        let cli = parse(&["expire", "r1"]).unwrap();
+        assert!(matches!(cli.command, Command::Expire { reason, .. } if reason == "expired with relayctl"));
+        let cli = parse(&["check"]).unwrap();
+        assert!(matches!(cli.command, Command::Check { timeout_msec: 2000 }));
+        for command in ["subscriptions", "config", "peers"] {
+            assert!(parse(&[command]).is_ok(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn invalid_invocations_are_rejected() {
+        assert!(parse(&[]).is_err());
+        assert!(Cli::try_parse_from(["relayctl", "--token", "secret", "config"]).is_err(), "the admin address is required");
+        assert!(parse(&["state"]).is_err());
+        assert!(parse(&["unknown"]).is_err());
+        assert!(parse(&["check", "--timeout-msec", "soon"]).is_err());
+        assert!(parse(&["--tls-domain", "relay1", "config"]).is_err());
+        let cli = parse(&["--tls-ca-cert", "ca.pem", "--tls-domain", "relay1", "config"]).unwrap();
+        assert_eq!(cli.tls_ca_cert, Some(PathBuf::from("ca.pem")));
+        assert_eq!(cli.tls_domain.as_deref(), Some("relay1"));
+    }
+
+    #[test]
+    fn admin_calls_carry_the_token() {
+        let request = admin_request(GetConfigRequest {}, "secret").unwrap();
+        assert_eq!(request.metadata().get("authorization").unwrap(), "Bearer secret");
+        assert!(admin_request(GetConfigRequest {}, "bad\ntoken").is_err());
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::common::state::{view_payload, View};
+use weaverpb::networks::networks::NetworkQuery;
+
+use crate::metrics::metrics;
+use crate::relay_proto::parse_address;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// The `[cache]` section of the relay config.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct CacheSettings {
+    // Which queries are cached and for how long, the first matching rule applies.
+    #[serde(default)]
+    pub rules: Vec<CacheRule>,
+    // Upper bound on the number of cached results.
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+/// Caches the results of queries of a network, or of addresses matching a
+/// pattern where `*` stands for any text, for `ttl_secs`.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct CacheRule {
+    #[serde(default)]
+    pub network: String,
+    #[serde(default)]
+    pub address: String,
+    pub ttl_secs: u64,
+}
+
+fn default_max_entries() -> usize {
+    10000
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            rules: vec![],
+            max_entries: default_max_entries(),
+        }
+    }
+}
+
+impl CacheSettings {
+    /// Reads the `[cache]` table, no result is cached when it is missing.
+    pub fn from_config(conf: &config::Config) -> Result<CacheSettings, String> {
+        let settings = match conf.get::<CacheSettings>("cache") {
+            Ok(settings) => settings,
+            Err(config::ConfigError::NotFound(_)) => CacheSettings::default(),
+            Err(e) => return Err(format!("cache: {}", e)),
+        };
+        if settings.max_entries == 0 {
+            return Err("cache.max_entries: must be at least 1".to_string());
+        }
+        if let Some(i) = settings.rules.iter().position(|rule| rule.network.is_empty() && rule.address.is_empty()) {
+            return Err(format!("cache.rules[{}]: a network or an address pattern is required", i));
+        }
+        Ok(settings)
+    }
+
+    // How long the result of a query is cached, if it is.
+    fn ttl(&self, query: &NetworkQuery) -> Option<Duration> {
+        let network_id = parse_address(query.address.to_string()).ok()?.network_id;
+        self.rules
+            .iter()
+            .find(|rule| {
+                (rule.network.is_empty() || rule.network == network_id)
+                    && (rule.address.is_empty() || matches_pattern(&rule.address, &query.address))
+            })
+            .map(|rule| Duration::from_secs(rule.ttl_secs))
+            .filter(|ttl| !ttl.is_zero())
+    }
+}
+
+// Matches `text` against `pattern`, where `*` stands for any text.
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+// Results are shared by queries of the same view, under the same policy and
+// by the same requestor. The requestor is told by its certificate, which is
+// public: a hit is served without the requestor's signature being verified
+// by the remote network, so the cache is only for views every client of the
+// relay may read.
+fn cache_key(query: &NetworkQuery) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        query.address,
+        query.policy.join(","),
+        query.requesting_network,
+        query.requesting_org,
+        query.certificate
+    )
+}
+
+/// Results of queries the requesting relay received from remote relays,
+/// which answer repeated queries until they expire.
+pub struct ResultCache {
+    entries: Mutex<HashMap<String, (View, Instant)>>,
+    // Cache key, time to live and start of each query waiting for its result.
+    pending: Mutex<HashMap<String, (String, Duration, Instant)>>,
+}
+
+static RESULT_CACHE: OnceLock<ResultCache> = OnceLock::new();
+
+/// The relay's result cache, created on first use.
+pub fn result_cache() -> &'static ResultCache {
+    RESULT_CACHE.get_or_init(ResultCache::new)
+}
+
+impl ResultCache {
+    fn new() -> ResultCache {
+        ResultCache {
+            entries: Mutex::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The cached view answering a query, marked as coming from the cache.
+    /// Confidential queries and queries that bypass the cache are never
+    /// answered from it.
+    pub fn lookup(&self, query: &NetworkQuery, settings: &CacheSettings) -> Option<View> {
+        self.lookup_at(query, settings, Instant::now())
+    }
+
+    fn lookup_at(&self, query: &NetworkQuery, settings: &CacheSettings, now: Instant) -> Option<View> {
+        if query.bypass_cache || query.confidential || settings.ttl(query).is_none() {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let key = cache_key(query);
+        let hit = match entries.get(&key) {
+            Some((view, expires_at)) if *expires_at > now => {
+                let mut view = view.clone();
+                if let Some(meta) = view.meta.as_mut() {
+                    meta.from_cache = true;
+                }
+                Some(view)
+            }
+            Some(_) => {
+                entries.remove(&key);
+                None
+            }
+            None => None,
+        };
+        metrics().record_cache_lookup(hit.is_some());
+        hit
+    }
+
+    /// Remembers a query sent to the remote relay, so that its result is
+    /// cached when it arrives. Queries that bypass the cache refresh it.
+    pub fn expect(&self, request_id: &str, query: &NetworkQuery, settings: &CacheSettings) {
+        self.expect_at(request_id, query, settings, Instant::now())
+    }
+
+    fn expect_at(&self, request_id: &str, query: &NetworkQuery, settings: &CacheSettings, now: Instant) {
+        if query.confidential {
+            return;
+        }
+        if let Some(ttl) = settings.ttl(query) {
+            let mut pending = self.pending.lock().unwrap();
+            // Queries whose result never arrives make room, the one waiting longest first.
+            while pending.len() >= settings.max_entries {
+                let oldest = pending.iter().min_by_key(|(_, (_, _, since))| *since).map(|(request_id, _)| request_id.to_string());
+                match oldest {
+                    Some(oldest) => pending.remove(&oldest),
+                    None => break,
+                };
+            }
+            pending.insert(request_id.to_string(), (cache_key(query), ttl, now));
+        }
+    }
+
+    /// Stops expecting the result of a query, once it is cancelled or failed.
+    pub fn forget(&self, request_id: &str) {
+        self.pending.lock().unwrap().remove(request_id);
+    }
+
+    /// Caches the result of a query that was expected, if it is a view.
+    pub fn complete(&self, request_id: &str, state: Option<&view_payload::State>, settings: &CacheSettings) {
+        self.complete_at(request_id, state, settings, Instant::now())
+    }
+
+    fn complete_at(&self, request_id: &str, state: Option<&view_payload::State>, settings: &CacheSettings, now: Instant) {
+        let Some((key, ttl, _)) = self.pending.lock().unwrap().remove(request_id) else {
+            return;
+        };
+        if let Some(view_payload::State::View(view)) = state {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.len() >= settings.max_entries && !entries.contains_key(&key) {
+                entries.retain(|_, (_, expires_at)| *expires_at > now);
+                // Still full, the result closest to expiring makes room.
+                while entries.len() >= settings.max_entries {
+                    let soonest = entries.iter().min_by_key(|(_, (_, expires_at))| *expires_at).map(|(key, _)| key.to_string());
+                    match soonest {
+                        Some(soonest) => entries.remove(&soonest),
+                        None => break,
+                    };
+                }
+            }
+            entries.insert(key, (view.clone(), now + ttl));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use weaverpb::common::state::Meta;
+
+    fn query(address: &str) -> NetworkQuery {
+        NetworkQuery {
+            address: address.to_string(),
+            requesting_network: "network1".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn results_are_cached_by_rule_until_they_expire() {
+        let settings = CacheSettings {
+            rules: vec![
+                CacheRule { network: "".to_string(), address: "*:Read:Live*".to_string(), ttl_secs: 0 },
+                CacheRule { network: "network2".to_string(), address: "".to_string(), ttl_secs: 10 },
+            ],
+            max_entries: 1,
+        };
+        let cache = ResultCache::new();
+        let now = Instant::now();
+        let view = View {
+            meta: Some(Meta::default()),
+            data: b"state".to_vec(),
+        };
+        let state = view_payload::State::View(view.clone());
+        let cached = query("localhost:9081/network2/mychannel:simplestate:Read:a");
+        cache.expect("r1", &cached, &settings);
+        cache.complete_at("r1", Some(&state), &settings, now);
+        let hit = cache.lookup_at(&cached, &settings, now).unwrap();
+        assert!(hit.meta.unwrap().from_cache);
+        assert_eq!(hit.data, view.data);
+        assert!(cache.lookup_at(&cached, &settings, now + Duration::from_secs(11)).is_none());
+
+        // Other requestors, excluded addresses and queries that bypass the cache miss it.
+        let excluded = query("localhost:9081/network2/mychannel:simplestate:Read:Live1");
+        cache.expect("r2", &excluded, &settings);
+        cache.complete_at("r2", Some(&state), &settings, now);
+        assert!(cache.lookup_at(&excluded, &settings, now).is_none());
+        cache.expect("r3", &cached, &settings);
+        cache.complete_at("r3", Some(&state), &settings, now);
+        let other_requestor = NetworkQuery { requesting_network: "network3".to_string(), ..cached.clone() };
+        assert!(cache.lookup_at(&other_requestor, &settings, now).is_none());
+        let bypass = NetworkQuery { bypass_cache: true, ..cached.clone() };
+        assert!(cache.lookup_at(&bypass, &settings, now).is_none());
+        assert!(cache.lookup_at(&cached, &settings, now).is_some());
+    }
+
+    #[test]
+    fn queries_without_result_do_not_stop_caching() {
+        let settings = CacheSettings {
+            rules: vec![CacheRule { network: "network2".to_string(), address: "".to_string(), ttl_secs: 10 }],
+            max_entries: 2,
+        };
+        let cache = ResultCache::new();
+        let now = Instant::now();
+        let state = view_payload::State::View(View { meta: Some(Meta::default()), data: b"state".to_vec() });
+        let failed = query("localhost:9081/network2/mychannel:simplestate:Read:a");
+        cache.expect_at("failed", &failed, &settings, now);
+        cache.forget("failed");
+        cache.expect_at("lost1", &failed, &settings, now);
+        cache.expect_at("lost2", &failed, &settings, now + Duration::from_secs(1));
+        // Full of queries whose result never came, the oldest makes room.
+        let cached = query("localhost:9081/network2/mychannel:simplestate:Read:b");
+        cache.expect_at("r1", &cached, &settings, now + Duration::from_secs(2));
+        assert_eq!(cache.pending.lock().unwrap().len(), 2);
+        cache.complete_at("r1", Some(&state), &settings, now + Duration::from_secs(2));
+        assert!(cache.lookup_at(&cached, &settings, now + Duration::from_secs(2)).is_some());
+    }
+
+    #[test]
+    fn patterns_match_any_text_for_stars() {
+        assert!(matches_pattern("localhost:9081/network1/*", "localhost:9081/network1/mychannel:cc:Read:a"));
+        assert!(matches_pattern("*:Read:*", "localhost:9081/network1/mychannel:cc:Read:a"));
+        assert!(matches_pattern("*network1*", "localhost:9081/network1/view"));
+        assert!(!matches_pattern("localhost:9081/network1/*", "localhost:9081/network2/view"));
+        assert!(!matches_pattern("*:Read", "localhost:9081/network1/mychannel:cc:Read:a"));
+        assert!(!matches_pattern("a*a", "a"));
+    }
+}
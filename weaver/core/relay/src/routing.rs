@@ -0,0 +1,144 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::common::query::Query;
+
+use crate::error::Error;
+use crate::relay_config::RelayConfig;
+
+use std::collections::HashMap;
+
+/// The `[routing]` section of the relay config.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct RoutingSettings {
+    // Next-hop relay, by name, of each network that is reached through intermediary relays.
+    #[serde(default)]
+    pub routes: HashMap<String, String>,
+    // Most relay-to-relay hops a query may take. A query sent straight to the relay serving the network takes one.
+    #[serde(default = "default_max_hops")]
+    pub max_hops: usize,
+}
+
+fn default_max_hops() -> usize {
+    8
+}
+
+impl Default for RoutingSettings {
+    fn default() -> Self {
+        RoutingSettings {
+            routes: HashMap::new(),
+            max_hops: default_max_hops(),
+        }
+    }
+}
+
+impl RoutingSettings {
+    /// Reads the `[routing]` table, no network is routed when it is missing.
+    pub fn from_config(conf: &config::Config) -> Result<RoutingSettings, String> {
+        let settings = match conf.get::<RoutingSettings>("routing") {
+            Ok(settings) => settings,
+            Err(config::ConfigError::NotFound(_)) => RoutingSettings::default(),
+            Err(e) => return Err(format!("routing: {}", e)),
+        };
+        if settings.max_hops == 0 {
+            return Err("routing.max_hops: must be at least 1".to_string());
+        }
+        if let Some((network_id, _)) = settings.routes.iter().find(|(_, next_hop)| next_hop.is_empty()) {
+            return Err(format!("routing.routes.{}: a next-hop relay name is required", network_id));
+        }
+        Ok(settings)
+    }
+}
+
+/// The relay a query for `network_id` is forwarded to, when the network is
+/// not served by this relay but is routed through another one.
+pub fn next_hop<'a>(network_id: &str, conf: &'a RelayConfig) -> Option<&'a String> {
+    if conf.networks.contains_key(network_id) {
+        return None;
+    }
+    conf.routing.routes.get(network_id)
+}
+
+/// Relays a query has passed through, starting with the requesting relay.
+/// Queries from relays that do not record the path have come straight from
+/// the requesting relay.
+pub fn relay_path(query: &Query) -> Vec<String> {
+    if query.relay_path.is_empty() {
+        vec![query.requesting_relay.to_string()]
+    } else {
+        query.relay_path.clone()
+    }
+}
+
+/// The relay a query was received from, which its result is sent back to.
+pub fn previous_hop(query: &Query) -> String {
+    relay_path(query).pop().unwrap_or_default()
+}
+
+/// Checks that a query received by `relay_name` has not passed through it
+/// before and has not taken more than `max_hops` hops.
+pub fn check_route(query: &Query, relay_name: &str, max_hops: usize) -> Result<(), Error> {
+    let path = relay_path(query);
+    // A relay may query its own networks through itself, which is not a loop.
+    if path.len() > 1 && path.iter().any(|relay| relay == relay_name) {
+        return Err(Error::Routing(format!(
+            "Routing loop: the query already passed through relay \"{}\" ({})",
+            relay_name,
+            path.join(" -> ")
+        )));
+    }
+    check_hops(&path, max_hops)
+}
+
+/// The relay path of a query `relay_name` forwards to the next hop.
+pub fn forwarded_relay_path(query: &Query, relay_name: &str, max_hops: usize) -> Result<Vec<String>, Error> {
+    let mut path = relay_path(query);
+    path.push(relay_name.to_string());
+    check_hops(&path, max_hops)?;
+    Ok(path)
+}
+
+// Each relay on the path has sent the query one hop further.
+fn check_hops(path: &[String], max_hops: usize) -> Result<(), Error> {
+    if path.len() > max_hops {
+        return Err(Error::Routing(format!(
+            "Hop limit exceeded: the query would take {} hops, at most {} are allowed ({})",
+            path.len(),
+            max_hops,
+            path.join(" -> ")
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(requesting_relay: &str, relay_path: &[&str]) -> Query {
+        Query {
+            requesting_relay: requesting_relay.to_string(),
+            relay_path: relay_path.iter().map(|relay| relay.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn queries_are_checked_for_loops_and_hop_limits() {
+        // Direct queries, including a relay querying itself, are fine.
+        assert!(check_route(&query("A", &[]), "B", 1).is_ok());
+        assert!(check_route(&query("A", &["A"]), "A", 1).is_ok());
+        assert_eq!(previous_hop(&query("A", &[])), "A");
+
+        let forwarded = query("A", &["A", "B"]);
+        assert_eq!(previous_hop(&forwarded), "B");
+        assert!(check_route(&forwarded, "C", 2).is_ok());
+        assert!(matches!(check_route(&forwarded, "C", 1), Err(Error::Routing(_))));
+        assert!(matches!(check_route(&forwarded, "A", 8), Err(Error::Routing(_))));
+        assert!(matches!(check_route(&forwarded, "B", 8), Err(Error::Routing(_))));
+
+        assert_eq!(forwarded_relay_path(&forwarded, "C", 3).unwrap(), vec!["A", "B", "C"]);
+        assert!(forwarded_relay_path(&forwarded, "C", 2).is_err());
+    }
+}
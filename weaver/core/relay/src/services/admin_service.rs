@@ -0,0 +1,589 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+// Internal generated modules
+use weaverpb::common::ack::{ack, Ack};
+use weaverpb::common::events::{EventStates, EventSubscription, EventSubscriptionState};
+use weaverpb::common::state::{request_state, RequestState};
+use weaverpb::relay::admin::relay_admin_server::RelayAdmin;
+use weaverpb::relay::admin::{
+    CheckConnectivityRequest, ConnectivityReport, DriverEndpoint as DriverEndpointRegistration, DriverRegistration, EventSubscriptionList, ExpireRequestMessage,
+    GetConfigRequest, ListEventSubscriptionsRequest, ListRegistrationsRequest, NetworkRegistration, PeerConnectivity,
+    Registrations as RegistrationList, RelayConfigView, RelayEndpoint, RelayRegistration, RemoveRegistration, RequestLookup,
+};
+
+// Internal modules
+use crate::config_reload::load_relay_config_with;
+use crate::callbacks::deliver_final_state;
+use crate::db::Database;
+use crate::error::Error;
+use crate::metrics::metrics;
+use crate::registrations::{driver_key, network_key, registrations_db, relay_key, remove_registration, store_registration, Registrations};
+use crate::relay_config::RelayConfig;
+use crate::relay_proto::LocationSegment;
+use crate::result_cache::result_cache;
+use crate::services::helpers::{get_event_publication_key, get_event_subscription_key};
+use crate::services::types::{default_ejection_msec, default_selection, Driver, DriverEndpoint, Network};
+use crate::state_watch::state_watch;
+
+// external modules
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint};
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+pub struct AdminService {
+    pub config_lock: Arc<RwLock<RelayConfig>>,
+    pub config_file_name: String,
+}
+
+// Selects the registrations of one kind, e.g. the drivers.
+type Entries<T> = fn(&mut Registrations) -> &mut HashMap<String, T>;
+
+/// AdminService is the gRPC server implementation that lets an operator
+/// register drivers, network bindings and remote relays at runtime. Every
+/// change is validated together with the relay config before it is persisted
+/// and put into effect. It also lets the operator inspect requests, event
+/// subscriptions and the relay's connectivity, which is what `relayctl` uses.
+#[tonic::async_trait]
+impl RelayAdmin for AdminService {
+    async fn register_driver(&self, request: Request<DriverRegistration>) -> Result<Response<Ack>, Status> {
+        self.authorize(&request).await?;
+        let registration = request.into_inner();
+        info!("Admin: Received driver registration: {:?}", registration);
+        let driver = Driver {
+            hostname: registration.hostname,
+            port: registration.port,
+            tls: registration.tls,
+            tlsca_cert_path: registration.tlsca_cert_path,
+            endpoints: registration
+                .endpoints
+                .into_iter()
+                .map(|endpoint| DriverEndpoint {
+                    hostname: endpoint.hostname,
+                    port: endpoint.port,
+                    tls: endpoint.tls,
+                    tlsca_cert_path: endpoint.tlsca_cert_path,
+                })
+                .collect(),
+            selection: match registration.selection.as_str() {
+                "" => default_selection(),
+                selection => selection.to_string(),
+            },
+            ejection_msec: match registration.ejection_msec {
+                0 => default_ejection_msec(),
+                ejection_msec => ejection_msec,
+            },
+        };
+        let name = registration.name;
+        let result = self.register("driver", &name, driver_key(&name), |r| &mut r.drivers, driver).await;
+        Ok(ack_reply(format!("Registered driver {}", name), result))
+    }
+
+    async fn remove_driver(&self, request: Request<RemoveRegistration>) -> Result<Response<Ack>, Status> {
+        self.authorize(&request).await?;
+        let name = request.into_inner().name;
+        info!("Admin: Received driver removal: {}", name);
+        let result = self.remove("driver", &name, driver_key(&name), |r| &mut r.drivers).await;
+        Ok(ack_reply(format!("Removed driver {}", name), result))
+    }
+
+    async fn register_network(&self, request: Request<NetworkRegistration>) -> Result<Response<Ack>, Status> {
+        self.authorize(&request).await?;
+        let registration = request.into_inner();
+        info!("Admin: Received network registration: {:?}", registration);
+        let network = Network {
+            network: Some(registration.network_type).filter(|network_type| !network_type.is_empty()).unwrap_or(registration.driver.to_string()),
+            driver: Some(registration.driver),
+            access_control_policies: Some(registration.access_control_policies).filter(|path| !path.is_empty()),
+            allowed_relays: Some(registration.allowed_relays).filter(|relays| !relays.is_empty()),
+            denied_relays: Some(registration.denied_relays).filter(|relays| !relays.is_empty()),
+        };
+        let network_id = registration.network_id;
+        let result = self.register("network", &network_id, network_key(&network_id), |r| &mut r.networks, network).await;
+        Ok(ack_reply(format!("Registered network {}", network_id), result))
+    }
+
+    async fn remove_network(&self, request: Request<RemoveRegistration>) -> Result<Response<Ack>, Status> {
+        self.authorize(&request).await?;
+        let network_id = request.into_inner().name;
+        info!("Admin: Received network removal: {}", network_id);
+        let result = self.remove("network", &network_id, network_key(&network_id), |r| &mut r.networks).await;
+        Ok(ack_reply(format!("Removed network {}", network_id), result))
+    }
+
+    async fn register_relay(&self, request: Request<RelayRegistration>) -> Result<Response<Ack>, Status> {
+        self.authorize(&request).await?;
+        let registration = request.into_inner();
+        info!("Admin: Received relay registration: {:?}", registration);
+        let relay = LocationSegment {
+            hostname: registration.hostname,
+            port: registration.port,
+            tls: registration.tls,
+            tlsca_cert_path: registration.tlsca_cert_path,
+            priority: registration.priority,
+            endpoints: registration
+                .endpoints
+                .into_iter()
+                .map(|endpoint| LocationSegment {
+                    hostname: endpoint.hostname,
+                    port: endpoint.port,
+                    tls: endpoint.tls,
+                    tlsca_cert_path: endpoint.tlsca_cert_path,
+                    priority: endpoint.priority,
+                    endpoints: vec![],
+                })
+                .collect(),
+        };
+        let name = registration.name;
+        let result = self.register("relay", &name, relay_key(&name), |r| &mut r.relays, relay).await;
+        Ok(ack_reply(format!("Registered relay {}", name), result))
+    }
+
+    async fn remove_relay(&self, request: Request<RemoveRegistration>) -> Result<Response<Ack>, Status> {
+        self.authorize(&request).await?;
+        let name = request.into_inner().name;
+        info!("Admin: Received relay removal: {}", name);
+        let result = self.remove("relay", &name, relay_key(&name), |r| &mut r.relays).await;
+        Ok(ack_reply(format!("Removed relay {}", name), result))
+    }
+
+    async fn list_registrations(&self, request: Request<ListRegistrationsRequest>) -> Result<Response<RegistrationList>, Status> {
+        self.authorize(&request).await?;
+        let conf = self.config_lock.read().await.clone();
+        let registrations = Registrations::load(&registrations_db(&conf))
+            .map_err(|e| Status::internal(format!("Unable to read registrations: {:?}", e)))?;
+        let mut list = RegistrationList {
+            drivers: conf
+                .drivers
+                .iter()
+                .map(|(name, driver)| DriverRegistration {
+                    name: name.to_string(),
+                    hostname: driver.hostname.to_string(),
+                    port: driver.port.to_string(),
+                    tls: driver.tls,
+                    tlsca_cert_path: driver.tlsca_cert_path.to_string(),
+                    runtime: registrations.drivers.contains_key(name),
+                    endpoints: driver
+                        .endpoints
+                        .iter()
+                        .map(|endpoint| DriverEndpointRegistration {
+                            hostname: endpoint.hostname.to_string(),
+                            port: endpoint.port.to_string(),
+                            tls: endpoint.tls,
+                            tlsca_cert_path: endpoint.tlsca_cert_path.to_string(),
+                        })
+                        .collect(),
+                    selection: driver.selection.to_string(),
+                    ejection_msec: driver.ejection_msec,
+                })
+                .collect(),
+            networks: conf
+                .networks
+                .iter()
+                .map(|(network_id, network)| NetworkRegistration {
+                    network_id: network_id.to_string(),
+                    driver: network.driver_name().to_string(),
+                    network_type: network.network.to_string(),
+                    access_control_policies: network.access_control_policies.clone().unwrap_or_default(),
+                    allowed_relays: network.allowed_relays.clone().unwrap_or_default(),
+                    denied_relays: network.denied_relays.clone().unwrap_or_default(),
+                    runtime: registrations.networks.contains_key(network_id),
+                })
+                .collect(),
+            relays: conf
+                .relays
+                .iter()
+                .map(|(name, relay)| RelayRegistration {
+                    name: name.to_string(),
+                    hostname: relay.hostname.to_string(),
+                    port: relay.port.to_string(),
+                    tls: relay.tls,
+                    tlsca_cert_path: relay.tlsca_cert_path.to_string(),
+                    runtime: registrations.relays.contains_key(name),
+                    priority: relay.priority,
+                    endpoints: relay
+                        .endpoints
+                        .iter()
+                        .map(|endpoint| RelayEndpoint {
+                            hostname: endpoint.hostname.to_string(),
+                            port: endpoint.port.to_string(),
+                            tls: endpoint.tls,
+                            tlsca_cert_path: endpoint.tlsca_cert_path.to_string(),
+                            priority: endpoint.priority,
+                        })
+                        .collect(),
+                })
+                .collect(),
+        };
+        list.drivers.sort_by(|a, b| a.name.cmp(&b.name));
+        list.networks.sort_by(|a, b| a.network_id.cmp(&b.network_id));
+        list.relays.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(Response::new(list))
+    }
+
+    async fn get_request_state(&self, request: Request<RequestLookup>) -> Result<Response<RequestState>, Status> {
+        self.authorize(&request).await?;
+        let conf = self.config_lock.read().await.clone();
+        let request_id = request.into_inner().request_id;
+        let request_state = local_db(&conf)
+            .get::<RequestState>(request_id.to_string())
+            .map_err(|e| Status::not_found(format!("Request {} not found. Error: {:?}", request_id, e)))?;
+        Ok(Response::new(request_state))
+    }
+
+    async fn list_event_subscriptions(
+        &self,
+        request: Request<ListEventSubscriptionsRequest>,
+    ) -> Result<Response<EventSubscriptionList>, Status> {
+        self.authorize(&request).await?;
+        let conf = self.config_lock.read().await.clone();
+        let prefix = get_event_subscription_key("".to_string());
+        let subscriptions = local_db(&conf)
+            .scan_prefix::<EventSubscriptionState>(prefix.to_string())
+            .map_err(|e| Status::internal(format!("Unable to read event subscriptions: {:?}", e)))?;
+        let served = remote_db(&conf)
+            .scan_prefix::<EventSubscription>(prefix)
+            .map_err(|e| Status::internal(format!("Unable to read served event subscriptions: {:?}", e)))?;
+        Ok(Response::new(EventSubscriptionList {
+            subscriptions: subscriptions.into_iter().map(|(_key, subscription)| subscription).collect(),
+            served: served.into_iter().map(|(_key, subscription)| subscription).collect(),
+        }))
+    }
+
+    async fn get_event_states(&self, request: Request<RequestLookup>) -> Result<Response<EventStates>, Status> {
+        self.authorize(&request).await?;
+        let conf = self.config_lock.read().await.clone();
+        let request_id = request.into_inner().request_id;
+        // No events have been received yet when the subscription exists without them.
+        let db = local_db(&conf);
+        match db.get::<EventStates>(get_event_publication_key(request_id.to_string())) {
+            Ok(event_states) => Ok(Response::new(event_states)),
+            Err(_) if db.has_key(get_event_subscription_key(request_id.to_string())).unwrap_or(false) => {
+                Ok(Response::new(EventStates { states: vec![] }))
+            }
+            Err(e) => Err(Status::not_found(format!("Event subscription {} not found. Error: {:?}", request_id, e))),
+        }
+    }
+
+    async fn expire_request(&self, request: Request<ExpireRequestMessage>) -> Result<Response<Ack>, Status> {
+        self.authorize(&request).await?;
+        let message = request.into_inner();
+        info!("Admin: Received request expiry: {:?}", message);
+        let conf = self.config_lock.read().await.clone();
+        let result = expire_request(&local_db(&conf), &message.request_id, &message.reason);
+        if result.is_ok() {
+            result_cache().forget(&message.request_id);
+            state_watch().notify(&message.request_id);
+            deliver_final_state(&conf, &message.request_id);
+        }
+        Ok(ack_reply(format!("Expired request {}", message.request_id), result))
+    }
+
+    async fn get_config(&self, request: Request<GetConfigRequest>) -> Result<Response<RelayConfigView>, Status> {
+        self.authorize(&request).await?;
+        let conf = self.config_lock.read().await.clone();
+        let mut config = serde_json::to_value(&conf).map_err(|e| Status::internal(format!("Unable to encode the config: {}", e)))?;
+        if let Some(config) = config.as_object_mut() {
+            config.remove("admin_token");
+        }
+        Ok(Response::new(RelayConfigView {
+            config_json: config.to_string(),
+        }))
+    }
+
+    async fn check_connectivity(
+        &self,
+        request: Request<CheckConnectivityRequest>,
+    ) -> Result<Response<ConnectivityReport>, Status> {
+        self.authorize(&request).await?;
+        let timeout_msec = match request.into_inner().timeout_msec {
+            0 => 2000,
+            timeout_msec => timeout_msec,
+        };
+        let timeout = Duration::from_millis(timeout_msec);
+        let conf = self.config_lock.read().await.clone();
+        // Each instance of a driver and each endpoint of a relay is checked.
+        let drivers = conf.drivers.iter().flat_map(|(name, driver)| {
+            driver.instances().into_iter().map(move |instance| async move {
+                check_peer("driver", name, &instance.hostname, &instance.port, instance.tls, &instance.tlsca_cert_path, timeout).await
+            })
+        });
+        let relays = conf.relays.iter().flat_map(|(name, relay)| {
+            relay.instances().into_iter().map(move |endpoint| async move {
+                check_peer("relay", name, &endpoint.hostname, &endpoint.port, endpoint.tls, &endpoint.tlsca_cert_path, timeout).await
+            })
+        });
+        let mut peers = futures::future::join_all(drivers).await;
+        peers.extend(futures::future::join_all(relays).await);
+        peers.sort_by(|a, b| (&a.kind, &a.name).cmp(&(&b.kind, &b.name)));
+        Ok(Response::new(ConnectivityReport { peers }))
+    }
+}
+
+impl AdminService {
+    // Admin calls carry the admin token as `authorization: Bearer <token>`.
+    async fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let admin_token = self.config_lock.read().await.admin_token.to_string();
+        if admin_token.is_empty() {
+            return Err(Status::permission_denied("Admin service is disabled, no admin_token is configured"));
+        }
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or("");
+        if !tokens_match(token, &admin_token) {
+            warn!("Admin: Rejected request with a missing or invalid admin token");
+            return Err(Status::unauthenticated("Missing or invalid admin token"));
+        }
+        Ok(())
+    }
+
+    // Registrations are changed with the config lock held, so that concurrent
+    // changes and config reloads are applied one after the other. A change is
+    // validated with the relay config before it is persisted and put into effect.
+    async fn register<T: Serialize + Clone>(&self, kind: &str, name: &str, key: String, entries: Entries<T>, value: T) -> Result<(), Error> {
+        if name.is_empty() {
+            return Err(Error::Simple(format!("A {} name is required", kind)));
+        }
+        let mut conf = self.config_lock.write().await;
+        let db = registrations_db(&conf);
+        let mut registrations = Registrations::load(&db)?;
+        entries(&mut registrations).insert(name.to_string(), value.clone());
+        let updated = self.updated_config(&registrations)?;
+        store_registration(&db, &key, &value)?;
+        *conf = updated;
+        info!("Admin: Registered {} {}", kind, name);
+        Ok(())
+    }
+
+    async fn remove<T>(&self, kind: &str, name: &str, key: String, entries: Entries<T>) -> Result<(), Error> {
+        let mut conf = self.config_lock.write().await;
+        let db = registrations_db(&conf);
+        let mut registrations = Registrations::load(&db)?;
+        if entries(&mut registrations).remove(name).is_none() {
+            return Err(Error::Simple(format!(
+                "{} {} was not registered at runtime, entries of the relay config file can only be removed from the file",
+                kind, name
+            )));
+        }
+        let updated = self.updated_config(&registrations)?;
+        remove_registration(&db, &key)?;
+        *conf = updated;
+        info!("Admin: Removed {} {}", kind, name);
+        Ok(())
+    }
+
+    fn updated_config(&self, registrations: &Registrations) -> Result<RelayConfig, Error> {
+        load_relay_config_with(&self.config_file_name, Some(registrations))
+            .map_err(|problems| Error::Simple(format!("Rejected, the relay config would be invalid: {}", problems.join("; "))))
+    }
+}
+
+fn local_db(conf: &RelayConfig) -> Database {
+    Database {
+        db_path: conf.db_path.to_string(),
+        db_open_max_retries: conf.db_open_max_retries,
+        db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+    }
+}
+
+fn remote_db(conf: &RelayConfig) -> Database {
+    Database {
+        db_path: conf.remote_db_path.to_string(),
+        db_open_max_retries: conf.db_open_max_retries,
+        db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+    }
+}
+
+// Only requests still waiting for their result can be expired.
+fn expire_request(db: &Database, request_id: &str, reason: &str) -> Result<(), Error> {
+    let request_state = db.get::<RequestState>(request_id.to_string())?;
+    let status = request_state::Status::try_from(request_state.status).ok();
+    if !matches!(status, Some(request_state::Status::PendingAck) | Some(request_state::Status::Pending)) {
+        return Err(Error::Simple(format!("Request {} is not pending, its status is {:?}", request_id, status)));
+    }
+    let reason = if reason.is_empty() { "no reason given" } else { reason };
+    let expired_state = RequestState {
+        status: request_state::Status::Error as i32,
+        request_id: request_id.to_string(),
+        state: Some(request_state::State::Error(format!("Request expired by the relay operator: {}", reason))),
+        relay_path: vec![],
+        callback: None,
+    };
+    db.set(&request_id.to_string(), &expired_state)?;
+    metrics().record_request_state(expired_state.status);
+    info!("Admin: Expired request {}", request_id);
+    Ok(())
+}
+
+// Connects to a driver or relay the way the relay's own clients do, TLS included.
+async fn check_peer(
+    kind: &str,
+    name: &str,
+    hostname: &str,
+    port: &str,
+    tls: bool,
+    tlsca_cert_path: &str,
+    timeout: Duration,
+) -> PeerConnectivity {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(timeout, connect(hostname, port, tls, tlsca_cert_path)).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("No connection within {} ms", timeout.as_millis())),
+    };
+    PeerConnectivity {
+        kind: kind.to_string(),
+        name: name.to_string(),
+        endpoint: format!("{}:{}", hostname, port),
+        reachable: result.is_ok(),
+        message: result.err().unwrap_or_default(),
+        latency_msec: start.elapsed().as_millis() as u64,
+    }
+}
+
+async fn connect(hostname: &str, port: &str, tls: bool, tlsca_cert_path: &str) -> Result<(), String> {
+    let endpoint = if tls {
+        let pem = tokio::fs::read(tlsca_cert_path)
+            .await
+            .map_err(|e| format!("Unable to read {}: {}", tlsca_cert_path, e))?;
+        let tls_config = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(pem))
+            .domain_name(hostname);
+        Endpoint::from_shared(format!("https://{}:{}", hostname, port)).and_then(|endpoint| endpoint.tls_config(tls_config))
+    } else {
+        Endpoint::from_shared(format!("http://{}:{}", hostname, port))
+    };
+    let endpoint = endpoint.map_err(|e| format!("Invalid endpoint: {}", error_chain(&e)))?;
+    endpoint.connect().await.map(|_| ()).map_err(|e| format!("Unable to connect: {}", error_chain(&e)))
+}
+
+// Transport errors only say what failed in their sources, e.g. "connection refused".
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        let cause = e.to_string();
+        if !message.contains(&cause) {
+            message.push_str(&format!(": {}", cause));
+        }
+        source = e.source();
+    }
+    message
+}
+
+// Compares in time independent of where the tokens differ.
+fn tokens_match(token: &str, admin_token: &str) -> bool {
+    token.len() == admin_token.len()
+        && token.bytes().zip(admin_token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn ack_reply(message: String, result: Result<(), Error>) -> Response<Ack> {
+    let reply = match result {
+        Ok(()) => Ack {
+            status: ack::Status::Ok as i32,
+            request_id: "".to_string(),
+            message,
+        },
+        Err(e) => Ack {
+            status: ack::Status::Error as i32,
+            request_id: "".to_string(),
+            message: format!("Error: {:?}", e),
+        },
+    };
+    info!("Admin: Sending back Ack: {:?}", reply);
+    Response::new(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_config::TestConfig;
+
+    // An admin service whose config file is written to a temporary directory.
+    fn admin_service(admin_token: &str) -> (AdminService, TestConfig) {
+        let dir = TestConfig::default();
+        let toml = dir.toml(
+            "Fabric_Relay",
+            "9080",
+            &format!(
+                "admin_token=\"{}\"\n[drivers.Fabric]\nhostname=\"localhost\"\nport=\"9090\"\ntls=false\ntlsca_cert_path=\"\"\n",
+                admin_token
+            ),
+        );
+        let config_file_name = dir.write(&toml);
+        let conf = crate::config_reload::load_relay_config(&config_file_name).unwrap();
+        let service = AdminService {
+            config_lock: Arc::new(RwLock::new(conf)),
+            config_file_name,
+        };
+        (service, dir)
+    }
+
+    fn with_token<T>(message: T, token: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        request.metadata_mut().insert("authorization", format!("Bearer {}", token).parse().unwrap());
+        request
+    }
+
+    fn network(network_id: &str, driver: &str) -> NetworkRegistration {
+        NetworkRegistration {
+            network_id: network_id.to_string(),
+            driver: driver.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_service_is_disabled_without_a_token() {
+        let (service, _dir) = admin_service("");
+        for token in ["", "anything"] {
+            let refused = service.register_network(with_token(network("network1", "Fabric"), token)).await.unwrap_err();
+            assert_eq!(refused.code(), tonic::Code::PermissionDenied);
+        }
+        assert!(service.config_lock.read().await.networks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn calls_without_the_admin_token_are_rejected() {
+        let (service, _dir) = admin_service("secret");
+        let refused = service.register_network(with_token(network("network1", "Fabric"), "wrong")).await.unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::Unauthenticated);
+        let refused = service.register_network(Request::new(network("network1", "Fabric"))).await.unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::Unauthenticated);
+        let refused = service.remove_driver(with_token(RemoveRegistration { name: "Fabric".to_string() }, "secre")).await.unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::Unauthenticated);
+        assert!(service.config_lock.read().await.networks.is_empty());
+
+        let ack = service.register_network(with_token(network("network1", "Fabric"), "secret")).await.unwrap().into_inner();
+        assert_eq!(ack.status, ack::Status::Ok as i32);
+        assert!(service.config_lock.read().await.networks.contains_key("network1"));
+    }
+
+    #[tokio::test]
+    async fn invalid_registrations_are_not_persisted() {
+        let (service, _dir) = admin_service("secret");
+        let ack = service.register_network(with_token(network("network1", "Unknown_Driver"), "secret")).await.unwrap().into_inner();
+        assert_eq!(ack.status, ack::Status::Error as i32);
+        let ack = service.register_network(with_token(network("", "Fabric"), "secret")).await.unwrap().into_inner();
+        assert_eq!(ack.status, ack::Status::Error as i32);
+        // Drivers of the config file cannot be removed at runtime.
+        let removal = RemoveRegistration { name: "Fabric".to_string() };
+        let ack = service.remove_driver(with_token(removal, "secret")).await.unwrap().into_inner();
+        assert_eq!(ack.status, ack::Status::Error as i32);
+
+        let conf = service.config_lock.read().await.clone();
+        assert!(conf.networks.is_empty());
+        assert!(conf.drivers.contains_key("Fabric"));
+        let registrations = Registrations::load(&registrations_db(&conf)).unwrap();
+        assert!(registrations.networks.is_empty());
+        assert!(registrations.drivers.is_empty());
+    }
+}
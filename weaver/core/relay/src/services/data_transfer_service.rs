@@ -8,21 +8,44 @@ use weaverpb::common::query::Query;
 use weaverpb::common::state::{request_state, view_payload, RequestState, ViewPayload};
 use weaverpb::relay::datatransfer::data_transfer_client::DataTransferClient;
 use weaverpb::relay::datatransfer::data_transfer_server::DataTransfer;
+use weaverpb::relay::datatransfer::{AckBatch, CancelQuery, QueryBatch};
 // Internal modules
+use crate::access_control::{check_access, check_relay_admission};
+use crate::callbacks::deliver_final_state;
 use crate::db::Database;
+use crate::discovery::RelayDiscovery;
+use crate::driver_pool::{call_driver_for_request, driver_pool};
 use crate::error::Error;
-use crate::relay_proto::{parse_address, LocationSegment};
-use crate::services::helpers::{get_driver, get_driver_client};
+use crate::relay_config::RelayConfig;
+use crate::grpc_options::{limit_view_payload, message_too_large_payload, GrpcOptions, WithGrpcOptions};
+use crate::logging::{record_request_id, Redacted, ViewData};
+use crate::metrics::metrics;
+use crate::nonce_store::NonceStore;
+use crate::rate_limit::RateLimiter;
+use crate::relay_pool::call_relay;
+use crate::relay_proto::{decoded_address, parse_address, LocationSegment};
+use crate::result_cache::result_cache;
+use crate::routing::{check_route, forwarded_relay_path, next_hop, previous_hop, relay_path};
+use crate::services::helpers::{get_cancelled_query_key, get_driver, get_driver_name, get_forwarded_query_key, get_sent_query_key};
+use crate::services::network_service::spawn_send_cancellation;
 use crate::services::types::{Driver};
+use crate::trace_context::traced;
+use crate::shutdown::{spawn_tracked, PendingWork};
+use crate::state_watch::state_watch;
 // external modules
-use config;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::sync::RwLock;
-use tonic::{Request, Response, Status};
+use tonic::{Code, Request, Response, Status};
+use tracing::{debug, error, info, warn, Instrument};
 
-use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+use tonic::transport::Channel;
 
 pub struct DataTransferService {
-    pub config_lock: RwLock<config::Config>,
+    pub config_lock: Arc<RwLock<RelayConfig>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub nonce_store: NonceStore,
+    pub discovery: Arc<RelayDiscovery>,
 }
 
 /// DataTransferService is the gRPC server implementation that handles the logic for
@@ -32,37 +55,70 @@ impl DataTransfer for DataTransferService {
     /// request_state is run on the remote relay to retrieve the state that was
     /// requested from the requesting relay
     async fn request_state(&self, request: Request<Query>) -> Result<Response<Ack>, Status> {
-        println!(
+        info!(
             "Got a Query request from {:?} - {:?}",
             request.remote_addr(),
-            request
+            Redacted(request.get_ref())
         );
-        let query = request.into_inner().clone();
-        let request_id = query.request_id.to_string();
-        let conf = self.config_lock.read().await;
+        let remote_addr = request.remote_addr();
+        let query = request.into_inner();
+        Ok(Response::new(self.serve_query(remote_addr, query).await))
+    }
+
+    /// request_state_batch is run on the remote relay to serve several queries
+    /// of the requesting relay at once. Each query is served as if it came
+    /// with request_state.
+    async fn request_state_batch(&self, request: Request<QueryBatch>) -> Result<Response<AckBatch>, Status> {
+        info!(
+            "Got a QueryBatch request from {:?} with {} queries",
+            request.remote_addr(),
+            request.get_ref().queries.len()
+        );
+        let remote_addr = request.remote_addr();
+        let mut acks = Vec::new();
+        for query in request.into_inner().queries {
+            debug!("Batched query: {:?}", Redacted(&query));
+            let span = tracing::info_span!("query", request_id = tracing::field::Empty);
+            acks.push(self.serve_query(remote_addr, query).instrument(span).await);
+        }
+        Ok(Response::new(AckBatch { acks }))
+    }
+
+    /// cancel_request is run on the remote relay when the requesting relay
+    /// cancels a query. Work left for the query is dropped, and its result is
+    /// discarded when the driver or the next relay sends it.
+    async fn cancel_request(&self, request: Request<CancelQuery>) -> Result<Response<Ack>, Status> {
+        info!("Got a CancelQuery request from {:?} - {:?}", request.remote_addr(), request.get_ref());
+        let cancel = request.into_inner();
+        let request_id = cancel.request_id.to_string();
+        record_request_id(&request_id);
+        let conf = self.config_lock.read().await.clone();
         // Database access/storage
         let remote_db = Database {
-            db_path: conf.get_str("remote_db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.remote_db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
-        match request_state_helper(remote_db, request_id.to_string(), query, conf.clone()) {
-            Ok(ack) => {
-                let reply = Ok(Response::new(ack));
-                println!("Sending back Ack: {:?}\n", reply);
-                reply
-            }
+        let reply = match cancel_query(&cancel, &remote_db, &conf, &self.rate_limiter, self.discovery.clone()) {
+            Ok(()) => Ack {
+                status: ack::Status::Ok as i32,
+                request_id,
+                message: "".to_string(),
+            },
             Err(e) => {
-                println!("Requesting State from Driver failed.");
-                let reply = Ok(Response::new(Ack {
+                warn!("Unable to cancel query {}: {:?}", request_id, e);
+                Ack {
                     status: ack::Status::Error as i32,
                     request_id,
-                    message: format!("Error: Requesting State from Driver failed. {:?}", e),
-                }));
-                println!("Sending back Ack: {:?}\n", reply);
-                reply
+                    message: match e {
+                        Error::AccessDenied(reason) => format!("Error: {}", reason),
+                        _ => format!("Error: {:?}", e),
+                    },
+                }
             }
-        }
+        };
+        info!("Sending back Ack: {:?}", reply);
+        Ok(Response::new(reply))
     }
 
     /// send_driver_state is run on the remote relay. Run when the driver sends the state back to the remote relay
@@ -72,26 +128,27 @@ impl DataTransfer for DataTransferService {
     ) -> Result<Response<Ack>, Status> {
         let state = request.into_inner().clone();
         let state_clone = state.clone();
-        println!("Received State from driver: {:?}", state_clone.request_id);
+        record_request_id(&state.request_id);
+        info!("Received State from driver: {:?}", state_clone.request_id);
         match state_clone.state.as_ref().unwrap() {
-            view_payload::State::View(v) => println!("View Meta: {:?}, View Data: {:?}", v.meta, base64::encode(&v.data)),
-            view_payload::State::Error(e) => println!("Error: {:?}", e),
+            view_payload::State::View(v) => debug!("View Meta: {:?}, View Data: {}", v.meta, ViewData(&v.data)),
+            view_payload::State::Error(e) => info!("Error: {:?}", e),
         }
         let request_id = state.request_id.to_string();
-        let conf = self.config_lock.read().await;
+        let conf = self.config_lock.read().await.clone();
         // Database access/storage
         let remote_db = Database {
-            db_path: conf.get_str("remote_db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.remote_db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
 
         let result =
-            send_driver_state_helper(request_id.to_string(), remote_db, conf.clone(), state);
+            send_driver_state_helper(request_id.to_string(), remote_db, conf, state, &self.rate_limiter, &self.discovery).await;
         match result {
             Ok(resp) => {
                 let reply = Ok(resp);
-                println!("Sending back Ack to driver: {:?}\n", reply);
+                info!("Sending back Ack to driver: {:?}", reply);
                 return reply;
             }
             Err(e) => {
@@ -101,7 +158,7 @@ impl DataTransfer for DataTransferService {
                     request_id: request_id.to_string(),
                     message: format!("Error: {:?}", e),
                 }));
-                println!("Sending back Ack to driver: {:?}\n", reply);
+                info!("Sending back Ack to driver: {:?}", reply);
                 return reply;
             }
         }
@@ -109,41 +166,226 @@ impl DataTransfer for DataTransferService {
     /// send_state is run on the requesting relay when a remote relay sends a result back to the requesting relay
     async fn send_state(&self, request: Request<ViewPayload>) -> Result<Response<Ack>, Status> {
         let request_view_payload = request.into_inner().clone();
-        println!(
+        record_request_id(&request_view_payload.request_id);
+        info!(
             "Received state from remote relay: Request ID = {:?}",
             request_view_payload.request_id
         );
         match request_view_payload.state.as_ref().unwrap() {
-            view_payload::State::View(v) => println!("View Meta: {:?}, View Data: {:?}", v.meta, base64::encode(&v.data)),
-            view_payload::State::Error(e) => println!("Error: {:?}", e),
+            view_payload::State::View(v) => debug!("View Meta: {:?}, View Data: {}", v.meta, ViewData(&v.data)),
+            view_payload::State::Error(e) => info!("Error: {:?}", e),
         }
         let request_id = &request_view_payload.request_id.to_string();
         let conf = self.config_lock.read().await.clone();
+        // Results of queries this relay forwarded go back to the relay the query came from.
+        let remote_db = Database {
+            db_path: conf.remote_db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        if let Ok(query) = remote_db.unset::<Query>(get_cancelled_query_key(request_id.to_string())) {
+            info!("Discarding state of query {} cancelled by relay {}", request_id, previous_hop(&query));
+            let reply = Ack {
+                status: ack::Status::Ok as i32,
+                request_id: request_id.to_string(),
+                message: "".to_string(),
+            };
+            info!("Sending back Ack to remote relay: {:?}", reply);
+            return Ok(Response::new(reply));
+        }
+        if let Ok(query) = remote_db.unset::<Query>(get_forwarded_query_key(request_id.to_string())) {
+            self.rate_limiter.release_request(request_id);
+            info!("Passing state of forwarded query back to relay {}", previous_hop(&query));
+            let reply = match send_state_back(request_view_payload, &query, &conf, &self.discovery).await {
+                Ok(()) => Ack {
+                    status: ack::Status::Ok as i32,
+                    request_id: request_id.to_string(),
+                    message: "".to_string(),
+                },
+                Err(e) => Ack {
+                    status: ack::Status::Error as i32,
+                    request_id: request_id.to_string(),
+                    message: format!("Error: {:?}", e),
+                },
+            };
+            info!("Sending back Ack to remote relay: {:?}", reply);
+            return Ok(Response::new(reply));
+        }
         // Database access/storage
         let db = Database {
-            db_path: conf.get_str("db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
-        let result = send_state_helper(request_view_payload.state, request_id.to_string(), db);
+        let _ = db.unset::<(LocationSegment, String)>(get_sent_query_key(request_id.to_string()));
+        // The network is no longer waiting for the result of a cancelled request.
+        if let Ok(request_state) = db.get::<RequestState>(request_id.to_string()) {
+            if request_state.status == request_state::Status::Cancelled as i32 {
+                info!("Discarding state of cancelled request {}", request_id);
+                let reply = Ack {
+                    status: ack::Status::Ok as i32,
+                    request_id: request_id.to_string(),
+                    message: "".to_string(),
+                };
+                info!("Sending back Ack to remote relay: {:?}", reply);
+                return Ok(Response::new(reply));
+            }
+        }
+        result_cache().complete(request_id, request_view_payload.state.as_ref(), &conf.cache);
+        let result = send_state_helper(request_view_payload.state, request_view_payload.relay_path, request_id.to_string(), db);
 
         match result {
-            Ok(_) => println!("Successfully set state in DB."),
-            Err(e) => println!("Setting value in DB failed: {:?}", e),
+            Ok(_) => {
+                info!("Successfully set state in DB.");
+                state_watch().notify(request_id);
+                deliver_final_state(&conf, request_id);
+            }
+            Err(e) => error!("Setting value in DB failed: {:?}", e),
         }
         let reply = Ok(Response::new(Ack {
             status: ack::Status::Ok as i32,
             request_id: request_id.to_string(),
             message: "".to_string(),
         }));
-        println!("Sending back Ack to remote relay: {:?}", reply);
+        info!("Sending back Ack to remote relay: {:?}", reply);
         reply
     }
 }
 
+impl DataTransferService {
+    // Serves a query of a remote relay, answering with the Ack sent back to it.
+    async fn serve_query(&self, remote_addr: Option<SocketAddr>, query: Query) -> Ack {
+        let request_id = query.request_id.to_string();
+        record_request_id(&request_id);
+        let conf = self.config_lock.read().await.clone();
+        // Requests from unknown or disallowed relays, or with an address that cannot be
+        // parsed, are turned away before any work is done for them.
+        let relay_known = self.discovery.resolve_relay(&previous_hop(&query), &conf).await.is_ok();
+        let address = match check_relay_admission(&query, &conf, relay_known) {
+            Ok(address) => address,
+            Err(e) => {
+                warn!("Rejected Query request {} from relay {:?} at {:?}: {:?}", request_id, query.requesting_relay, remote_addr, e);
+                let reply = Ack {
+                    status: ack::Status::Error as i32,
+                    request_id,
+                    message: match e {
+                        Error::AccessDenied(_) | Error::Address(_) => format!("Error: {}", e),
+                        _ => format!("Error: {:?}", e),
+                    },
+                };
+                info!("Sending back Ack: {:?}", reply);
+                return reply;
+            }
+        };
+        // Queries that went around in a loop or took too many hops are neither served nor forwarded.
+        if let Err(Error::Routing(reason)) = check_route(&query, &conf.name, conf.routing.max_hops) {
+            warn!("Rejected Query request {} from relay {:?}: {}", request_id, previous_hop(&query), reason);
+            let reply = Ack {
+                status: ack::Status::Error as i32,
+                request_id,
+                message: format!("Error: {}", reason),
+            };
+            info!("Sending back Ack: {:?}", reply);
+            return reply;
+        }
+        // Database access/storage
+        let remote_db = Database {
+            db_path: conf.remote_db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        let rate_limits = conf.rate_limits.clone();
+        if let Err(refused) = self.rate_limiter.try_acquire_request(&request_id, &query, &rate_limits) {
+            warn!("Rejecting Query request {}: {}", request_id, refused);
+            let reply = Ack {
+                status: ack::Status::Error as i32,
+                request_id,
+                message: format!("Error: {}", refused),
+            };
+            info!("Sending back Ack: {:?}", reply);
+            return reply;
+        }
+        // Queries of networks reached through another relay are passed on to it.
+        let route = next_hop(&address.network_id, &conf).cloned();
+        // Reject queries the local network's access control policies deny before any work is done.
+        if route.is_none() {
+            if let Err(e) = check_access(&query, &address, &conf) {
+                warn!("Rejected Query request {} from relay {:?}: {:?}", request_id, query.requesting_relay, e);
+                self.rate_limiter.release_request(&request_id);
+                let reply = Ack {
+                    status: ack::Status::Error as i32,
+                    request_id,
+                    message: match e {
+                        Error::AccessDenied(reason) => format!("Error: {}", reason),
+                        _ => format!("Error: Requesting State from Driver failed. {:?}", e),
+                    },
+                };
+                info!("Sending back Ack: {:?}", reply);
+                return reply;
+            }
+        }
+        // A query can only be served once, so that a captured query cannot be replayed. Its
+        // nonce is only recorded once the query is admitted, so that throttled or denied
+        // queries do not use up nonces.
+        let replay_protection = conf.replay_protection.clone();
+        if replay_protection.enabled {
+            if let Err(reason) = self.nonce_store.check_and_record(&query.requesting_network, &query.nonce, &replay_protection) {
+                warn!("Rejected Query request {} from relay {:?} at {:?}: {}", request_id, query.requesting_relay, remote_addr, reason);
+                self.rate_limiter.release_request(&request_id);
+                let reply = Ack {
+                    status: ack::Status::Error as i32,
+                    request_id,
+                    message: format!("Error: Replay rejected: {}", reason),
+                };
+                info!("Sending back Ack: {:?}", reply);
+                return reply;
+            }
+        }
+        if let Some(next_hop) = route {
+            let ack = forward_query(remote_db, request_id.to_string(), query, &next_hop, &conf, &self.discovery)
+                .await
+                .unwrap_or_else(|e| Ack {
+                    status: ack::Status::Error as i32,
+                    request_id: request_id.to_string(),
+                    message: match e {
+                        Error::Routing(reason) => format!("Error: {}", reason),
+                        _ => format!("Error: Forwarding the query to relay {} failed. {:?}", next_hop, e),
+                    },
+                });
+            if ack.status != ack::Status::Ok as i32 {
+                self.rate_limiter.release_request(&request_id);
+            }
+            info!("Sending back Ack: {:?}", ack);
+            return ack;
+        }
+        match request_state_helper(remote_db, request_id.to_string(), query, conf.clone(), self.rate_limiter.clone(), self.discovery.clone()) {
+            Ok(ack) => {
+                let reply = ack;
+                info!("Sending back Ack: {:?}", reply);
+                reply
+            }
+            Err(e) => {
+                warn!("Requesting State from Driver failed.");
+                self.rate_limiter.release_request(&request_id);
+                let reply = Ack {
+                    status: ack::Status::Error as i32,
+                    request_id,
+                    message: match e {
+                        Error::AccessDenied(reason) => format!("Error: {}", reason),
+                        _ => format!("Error: Requesting State from Driver failed. {:?}", e),
+                    },
+                };
+                info!("Sending back Ack: {:?}", reply);
+                reply
+            }
+        }
+    }
+}
+
 /// send_state is run on the requesting relay when a remote relay sends a result back to the requesting relay
 fn send_state_helper(
     state: Option<view_payload::State>,
+    relay_path: Vec<String>,
     request_id: String,
     db: Database,
 ) -> Result<(), Error> {
@@ -154,9 +396,12 @@ fn send_state_helper(
                     status: request_state::Status::Completed as i32,
                     request_id: request_id.to_string(),
                     state: Some(request_state::State::View(payload)),
+                    relay_path: relay_path.clone(),
+                    callback: None,
                 };
                 let _ = db.set(&request_id.to_string(), &target)?;
-                println!(
+                metrics().record_request_state(target.status);
+                info!(
                     "Stored ViewPayload into db with request_id: {}",
                     request_id.to_string()
                 );
@@ -166,9 +411,12 @@ fn send_state_helper(
                     status: request_state::Status::Error as i32,
                     request_id: request_id.to_string(),
                     state: Some(request_state::State::Error(error)),
+                    relay_path: relay_path.clone(),
+                    callback: None,
                 };
                 let _ = db.set(&request_id.to_string(), &target)?;
-                println!(
+                metrics().record_request_state(target.status);
+                info!(
                     "Stored Error into db with request_id: {}",
                     request_id.to_string()
                 );
@@ -179,9 +427,12 @@ fn send_state_helper(
                 status: request_state::Status::Error as i32,
                 request_id: request_id.to_string(),
                 state: Some(request_state::State::Error("Missing state".to_string())),
+                relay_path,
+                callback: None,
             };
             let _ = db.set(&request_id.to_string(), &target)?;
-            println!(
+            metrics().record_request_state(target.status);
+            info!(
                 "Stored Error into db with request_id: {}",
                 request_id.to_string()
             );
@@ -197,16 +448,24 @@ fn request_state_helper(
     remote_db: Database,
     request_id: String,
     query: Query,
-    conf: config::Config,
+    conf: RelayConfig,
+    rate_limiter: Arc<RateLimiter>,
+    discovery: Arc<RelayDiscovery>,
 ) -> Result<Ack, Error> {
+    let parsed_address = parse_address(query.address.to_string())?;
+    // The driver is sent the view that the access control policies were checked against.
+    let query = Query {
+        address: decoded_address(&query.address)?,
+        ..query
+    };
     let _set_query = remote_db
         .set(&request_id.to_string(), &query)
         .map_err(|e| Error::Simple(format!("DB Failure: {:?}", e)))?;
-    let parsed_address = parse_address(query.address.to_string())?;
     let result = get_driver(parsed_address.network_id.to_string(), conf.clone());
     match result {
         Ok(driver_info) => {
-            spawn_request_driver_state(query, driver_info, conf.clone());
+            let driver_name = get_driver_name(&parsed_address.network_id, &conf);
+            spawn_request_driver_state(query, driver_info, driver_name, conf.clone(), rate_limiter, discovery);
             return Ok(Ack {
                 status: ack::Status::Ok as i32,
                 request_id,
@@ -218,51 +477,155 @@ fn request_state_helper(
     
 }
 
+// Cancels a query this relay serves or forwards for the relay it was received
+// from, which is told apart from other relays by name. The query is kept under
+// its cancelled key until its result comes back, to be discarded.
+fn cancel_query(
+    cancel: &CancelQuery,
+    remote_db: &Database,
+    conf: &RelayConfig,
+    rate_limiter: &RateLimiter,
+    discovery: Arc<RelayDiscovery>,
+) -> Result<(), Error> {
+    let request_id = cancel.request_id.to_string();
+    let forwarded_query_key = get_forwarded_query_key(request_id.to_string());
+    let forwarded = remote_db.has_key(forwarded_query_key.to_string())?;
+    let query_key = if forwarded { forwarded_query_key } else { request_id.to_string() };
+    let query = remote_db
+        .get::<Query>(query_key.to_string())
+        .map_err(|_| Error::Simple(format!("Query {} is not known to relay {}", request_id, conf.name)))?;
+    if previous_hop(&query) != cancel.requesting_relay {
+        return Err(Error::AccessDenied(format!(
+            "Query {} was not received from relay {}",
+            request_id, cancel.requesting_relay
+        )));
+    }
+    remote_db.set(&get_cancelled_query_key(request_id.to_string()), &query)?;
+    remote_db.unset::<Query>(query_key)?;
+    rate_limiter.release_request(&request_id);
+    info!("Cancelled query {} of relay {}", request_id, cancel.requesting_relay);
+    // The next relay is told in turn.
+    if forwarded {
+        if let Ok(address) = parse_address(query.address.to_string()) {
+            spawn_send_cancellation(conf.clone(), discovery, request_id, address.location, address.network_id);
+        }
+    }
+    Ok(())
+}
+
 /// send_driver_state is run on the remote relay. Runs when the driver sends the
 /// state back to the remote relay or if there was an error making the
 /// request_driver_state gRPC call.
-fn send_driver_state_helper(
+async fn send_driver_state_helper(
     request_id: String,
     remote_db: Database,
-    conf: config::Config,
+    conf: RelayConfig,
     state: ViewPayload,
+    rate_limiter: &RateLimiter,
+    discovery: &RelayDiscovery,
 ) -> Result<Response<Ack>, Error> {
+    // The driver is done with this request, so it no longer counts towards the concurrency caps.
+    rate_limiter.release_request(&request_id);
+    driver_pool().release(&request_id);
+    if let Ok(query) = remote_db.unset::<Query>(get_cancelled_query_key(request_id.to_string())) {
+        info!("Discarding state of query {} cancelled by relay {}", request_id, previous_hop(&query));
+        return Ok(Response::new(Ack {
+            status: ack::Status::Ok as i32,
+            request_id,
+            message: "".to_string(),
+        }));
+    }
     let query: Query = remote_db
         .get::<Query>(request_id.to_string())
         .map_err(|e| Error::GetQuery(format!("Failed to get query from db. Error: {:?}", e)))?;
-    let relays_table = conf.get_table("relays")?;
-    let relay_uri = relays_table
-        .get(&query.requesting_relay.to_string())
-        .ok_or(Error::Simple("Relay name not found".to_string()))?;
-    let uri = relay_uri.clone().try_into::<LocationSegment>()?;
-    spawn_send_state(
-        state,
-        uri.hostname.to_string(),
-        uri.port.to_string(),
-        uri.tls,
-        uri.tlsca_cert_path.to_string(),
-    );
+    // The path the query took is recorded with its result.
+    let mut relay_path = relay_path(&query);
+    relay_path.push(conf.name.to_string());
+    let state = ViewPayload { relay_path, ..state };
+    send_state_back(state, &query, &conf, discovery).await?;
     let reply = Ack {
         status: ack::Status::Ok as i32,
         request_id,
         message: "".to_string(),
     };
 
-    return Ok(Response::new(reply));
+    Ok(Response::new(reply))
+}
+
+// Sends the state of a query back to the relay the query was received from,
+// which is the requesting relay unless the query was forwarded.
+async fn send_state_back(state: ViewPayload, query: &Query, conf: &RelayConfig, discovery: &RelayDiscovery) -> Result<(), Error> {
+    let relay_name = previous_hop(query);
+    let endpoints = discovery.resolve_relay(&relay_name, conf).await.inspect_err(|_| {
+        metrics().record_callback_failure(&relay_name);
+    })?;
+    spawn_send_state(state, relay_name.to_string(), endpoints, conf.grpc.clone());
+    Ok(())
+}
+
+/// Forwards a query of a network that is reached through the `next_hop`
+/// relay. The query is kept until its result comes back, so that the result
+/// can be passed on to the relay the query came from. The next hop's Ack is
+/// passed on as well.
+async fn forward_query(
+    remote_db: Database,
+    request_id: String,
+    query: Query,
+    next_hop: &str,
+    conf: &RelayConfig,
+    discovery: &RelayDiscovery,
+) -> Result<Ack, Error> {
+    let forwarded = Query {
+        relay_path: forwarded_relay_path(&query, &conf.name, conf.routing.max_hops)?,
+        ..query.clone()
+    };
+    let forwarded_query_key = get_forwarded_query_key(request_id.to_string());
+    remote_db.set(&forwarded_query_key, &query)?;
+    info!("Forwarding Query {} to relay {}: {}", request_id, next_hop, forwarded.relay_path.join(" -> "));
+    let result = async {
+        let endpoints = discovery.resolve_relay(next_hop, conf).await?;
+        let (ack, _) = call_relay(&endpoints, |channel| {
+            let mut client = DataTransferClient::new(channel).with_grpc_options(&conf.grpc);
+            let forwarded = forwarded.clone();
+            async move { client.request_state(traced(forwarded)).await }
+        })
+        .await?;
+        Ok::<Ack, Error>(ack.into_inner())
+    }
+    .await;
+    match result {
+        Ok(ack) if ack.status == ack::Status::Ok as i32 => Ok(ack),
+        Ok(ack) => {
+            let _ = remote_db.unset::<Query>(forwarded_query_key);
+            Ok(Ack {
+                message: format!("Error: Relay {} rejected the query. {}", next_hop, ack.message),
+                ..ack
+            })
+        }
+        Err(e) => {
+            let _ = remote_db.unset::<Query>(forwarded_query_key);
+            Err(e)
+        }
+    }
 }
 
 async fn spawn_request_driver_state_helper(
     query: Query,
     driver_info: Driver,
+    driver_name: &str,
+    grpc_options: &GrpcOptions,
 ) -> Result<(), Error> {
-    let client = get_driver_client(driver_info).await?;
-    println!("Sending request to driver with query {:?}", query.clone());
-    let ack = client
-        .clone()
-        .request_driver_state(query)
-        .await?
-        .into_inner();
-    println!("Response ACK from driver={:?}\n", ack);
+    info!("Sending request to driver with query {:?}", Redacted(&query));
+    let request_id = query.request_id.to_string();
+    // The instance works on the request until it sends the result back.
+    let (ack, instance) = call_driver_for_request(&request_id, driver_name, &driver_info, grpc_options, |mut client| {
+        let query = query.clone();
+        async move { client.request_driver_state(traced(query)).await }
+    })
+    .await?;
+    let ack = ack.into_inner();
+    info!("Query {} taken by driver {} instance {}", request_id, driver_name, instance.address());
+    info!("Response ACK from driver={:?}", ack);
     let status = ack::Status::from_i32(ack.status)
         .ok_or(Error::Simple("Status from Driver error".to_string()))?;
     match status {
@@ -275,94 +638,363 @@ async fn spawn_request_driver_state_helper(
 }
 
 // Function that starts a thread which sends the query information to the driver
-fn spawn_request_driver_state(query: Query, driver_info: Driver, conf: config::Config) {
-    tokio::spawn(async move {
-        let result = spawn_request_driver_state_helper(query.clone(), driver_info).await;
+pub fn spawn_request_driver_state(
+    query: Query,
+    driver_info: Driver,
+    driver_name: String,
+    conf: RelayConfig,
+    rate_limiter: Arc<RateLimiter>,
+    discovery: Arc<RelayDiscovery>,
+) {
+    let work = PendingWork::RequestDriverState {
+        query: query.clone(),
+        driver_name: driver_name.to_string(),
+    };
+    spawn_tracked(work, async move {
+        let remote_db = Database {
+            db_path: conf.remote_db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        // Queries cancelled before they were sent to the driver are dropped.
+        if let Ok(true) = remote_db.has_key(get_cancelled_query_key(query.request_id.to_string())) {
+            info!("Dropping query {}, it was cancelled", query.request_id);
+            let _ = remote_db.unset::<Query>(get_cancelled_query_key(query.request_id.to_string()));
+            rate_limiter.release_request(&query.request_id);
+            return;
+        }
+        let result = spawn_request_driver_state_helper(query.clone(), driver_info, &driver_name, &conf.grpc).await;
         match result {
             Ok(_) => {
                 // Do nothing
-                println!("Ack Ok from driver\n")
+                info!("Ack Ok from driver")
             }
             Err(e) => {
-                println!("Error sending query to driver: {:?}\n", e);
+                warn!("Error sending query to driver: {:?}", e);
+                metrics().record_driver_error(&driver_name);
                 // In Error case we send an error_state to requesting relay.
                 let request_id = query.request_id.to_string();
-                // Database access/storage
-                let remote_db = Database {
-                    db_path: conf.get_str("remote_db_path").unwrap(),
-                    db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-                    db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
-                };
                 let error_state = ViewPayload {
                     request_id: query.request_id.to_string(),
                     state: Some(view_payload::State::Error(format!("Driver Error: {:?}", e))),
+                    relay_path: vec![],
                 };
                 let result = send_driver_state_helper(
                     request_id.to_string(),
                     remote_db,
                     conf.clone(),
                     error_state,
-                );
+                    &rate_limiter,
+                    &discovery,
+                )
+                .await;
                 match result {
                     Ok(_) => {
-                        println!(
+                        info!(
                             "Send_Driver_Data error successfully sent back to requesting relay"
                         );
                     }
-                    Err(e) => println!("Error sending state: {:?}", e),
+                    Err(e) => error!("Error sending state: {:?}", e),
                 }
             }
         }
-    });
+    }.in_current_span());
 }
 
 // spawn_send_state sends data from the remote relay back to the requesting relay
 // When it errors it currently logs to console. Needs improving
-fn spawn_send_state(state: ViewPayload, requestor_host: String, requester_port: String, use_tls: bool, tlsca_cert_path: String) {
-    tokio::spawn(async move {
-        println!("Sending state back to requesting relay: Request ID = {:?}", state.request_id);
+pub fn spawn_send_state(
+    state: ViewPayload,
+    requesting_relay: String,
+    endpoints: Vec<LocationSegment>,
+    grpc_options: GrpcOptions,
+) {
+    let work = PendingWork::SendState {
+        state: state.clone(),
+        requesting_relay: requesting_relay.to_string(),
+        endpoints: endpoints.clone(),
+    };
+    spawn_tracked(work, async move {
+        let state = limit_view_payload(state, &grpc_options);
+        info!("Sending state back to requesting relay: Request ID = {:?}", state.request_id);
         match state.state.as_ref().unwrap() {
-            view_payload::State::View(v) => println!("View Meta: {:?}, View Data: {:?}", v.meta, base64::encode(&v.data)),
-            view_payload::State::Error(e) => println!("Error: {:?}", e),
+            view_payload::State::View(v) => debug!("View Meta: {:?}, View Data: {}", v.meta, ViewData(&v.data)),
+            view_payload::State::Error(e) => info!("Error: {:?}", e),
+        }
+        // The state goes to whichever endpoint of the requesting relay can be reached.
+        let result = call_relay(&endpoints, |channel| {
+            let client = DataTransferClient::new(channel).with_grpc_options(&grpc_options);
+            send_state_to_relay(client, state.clone())
+        })
+        .await;
+        match result {
+            Ok((response, endpoint)) => {
+                info!("Response ACK from requesting relay {}={:?}", endpoint.address(), response);
+                metrics().record_callback_result(&requesting_relay, &Ok(response));
+            }
+            Err(e) => {
+                warn!("Failed to send state to requesting relay {}: {:?}", requesting_relay, e);
+                metrics().record_callback_failure(&requesting_relay);
+            }
+        }
+    }.in_current_span());
+}
+
+// Sends the state to the requesting relay. When the requesting relay refuses it
+// for exceeding its maximum message size, the reason is sent in its place so
+// that the request does not stay pending.
+async fn send_state_to_relay(mut client: DataTransferClient<Channel>, state: ViewPayload) -> Result<Response<Ack>, Status> {
+    let request_id = state.request_id.to_string();
+    let relay_path = state.relay_path.clone();
+    match client.send_state(traced(state)).await {
+        Err(status) if status.code() == Code::OutOfRange => {
+            warn!("Requesting relay refused the view payload: {}", status.message());
+            let refused = ViewPayload {
+                relay_path,
+                ..message_too_large_payload(request_id, status.message().to_string())
+            };
+            client.send_state(traced(refused)).await
         }
-        if use_tls {
-            let client_addr = format!("https://{}:{}", requestor_host, requester_port);
-            let pem = tokio::fs::read(tlsca_cert_path).await.unwrap();
-            let ca = Certificate::from_pem(pem);
+        response => response,
+    }
+}
 
-            let tls = ClientTlsConfig::new()
-                .ca_certificate(ca)
-                .domain_name(requestor_host);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::{RateLimitSpec, RateLimits};
+    use crate::test_config::TestConfig;
+    use std::time::Duration;
+    use weaverpb::common::events::EventSubscription;
+    use weaverpb::driver::driver::driver_communication_server::{DriverCommunication, DriverCommunicationServer};
+    use weaverpb::driver::driver::WriteExternalStateMessage;
+    use weaverpb::relay::datatransfer::data_transfer_server::DataTransferServer;
 
-            let channel = Channel::from_shared(client_addr.to_string()).unwrap()
-                .tls_config(tls).expect(&format!("Error in TLS configuration for client: {}", client_addr.to_string()))
-                .connect()
-                .await
-                .unwrap();
-
-            let mut client_result = DataTransferClient::new(channel);
-            let response = client_result.send_state(state).await;
-            println!("Response ACK from requesting relay={:?}\n", response);
-        } else {
-            let client_addr = format!("http://{}:{}", requestor_host, requester_port);
-            let client_result = DataTransferClient::connect(client_addr).await;
-            match client_result {
-                Ok(client) => {
-                    let response = client.clone().send_state(state).await;
-                    println!("Response ACK from requesting relay={:?}\n", response);
-                    // Not returning anything here
-                }
-                Err(e) => {
-                    // TODO: Add better error handling (Attempt a few times?)
-                    println!(
-                        "Failed to connect to client: ${:?}. Error: {}\n",
-                        requester_port,
-                        e.to_string()
-                    );
-                    // TODO: Handle this error thorugh join handle after thread completes.
-                    // Not actually returning anything here yet
-                }
+    fn remote_db(conf: &RelayConfig) -> Database {
+        Database {
+            db_path: conf.remote_db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        }
+    }
+
+    fn service(conf: &RelayConfig) -> DataTransferService {
+        DataTransferService {
+            config_lock: Arc::new(RwLock::new(conf.clone())),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            nonce_store: NonceStore::new(),
+            discovery: Arc::new(RelayDiscovery::new()),
+        }
+    }
+
+    fn query(request_id: &str, requesting_relay: &str, address: &str) -> Query {
+        Query {
+            request_id: request_id.to_string(),
+            requesting_relay: requesting_relay.to_string(),
+            address: address.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn cancel(request_id: &str, requesting_relay: &str) -> CancelQuery {
+        CancelQuery {
+            request_id: request_id.to_string(),
+            requesting_relay: requesting_relay.to_string(),
+        }
+    }
+
+    fn view_payload(request_id: &str) -> ViewPayload {
+        ViewPayload {
+            request_id: request_id.to_string(),
+            relay_path: vec![],
+            state: Some(view_payload::State::Error("late".to_string())),
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_are_admitted_before_their_address_is_parsed() {
+        let dir = TestConfig::default();
+        let mut conf = dir.relay_config(
+            "Fabric_Relay",
+            "9080",
+            "[relays.Corda_Relay]\nhostname=\"localhost\"\nport=\"9081\"\ntls=false\ntlsca_cert_path=\"\"\n",
+        );
+        let limits = RateLimits {
+            default: RateLimitSpec { max_concurrent: Some(1), ..Default::default() },
+            ..Default::default()
+        };
+        conf.rate_limits = limits.clone();
+        conf.replay_protection.enabled = true;
+        let service = service(&conf);
+        let malformed = |requesting_relay: &str| Query {
+            requesting_network: "network2".to_string(),
+            nonce: "n1".to_string(),
+            ..query("r1", requesting_relay, "localhost:9080/network1")
+        };
+
+        let ack = service.serve_query(None, malformed("Unknown_Relay")).await;
+        assert_eq!(ack.status, ack::Status::Error as i32);
+        assert!(ack.message.contains("is not a known relay"), "{}", ack.message);
+        let ack = service.serve_query(None, malformed("Corda_Relay")).await;
+        assert_eq!(ack.status, ack::Status::Error as i32);
+        assert!(ack.message.contains("Invalid address"), "{}", ack.message);
+
+        // Neither query took a concurrency slot or used up its nonce.
+        assert!(service.rate_limiter.try_acquire_request("r2", &malformed("Corda_Relay"), &limits).is_ok());
+        assert!(service.nonce_store.check_and_record("network2", "n1", &conf.replay_protection).is_ok());
+    }
+
+    // A driver that only records the queries it is sent.
+    struct RecordingDriver(tokio::sync::mpsc::UnboundedSender<Query>);
+
+    #[tonic::async_trait]
+    impl DriverCommunication for RecordingDriver {
+        async fn request_driver_state(&self, request: Request<Query>) -> Result<Response<Ack>, Status> {
+            let _ = self.0.send(request.into_inner());
+            Ok(Response::new(Ack { status: ack::Status::Ok as i32, ..Default::default() }))
+        }
+        async fn subscribe_event(&self, _: Request<EventSubscription>) -> Result<Response<Ack>, Status> {
+            Err(Status::unimplemented("subscribe_event"))
+        }
+        async fn request_signed_event_subscription_query(&self, _: Request<EventSubscription>) -> Result<Response<Query>, Status> {
+            Err(Status::unimplemented("request_signed_event_subscription_query"))
+        }
+        async fn write_external_state(&self, _: Request<WriteExternalStateMessage>) -> Result<Response<Ack>, Status> {
+            Err(Status::unimplemented("write_external_state"))
+        }
+    }
+
+    #[tokio::test]
+    async fn drivers_are_sent_the_view_access_was_checked_against() {
+        let driver_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let (sent, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let driver = DriverCommunicationServer::new(RecordingDriver(sent));
+        let addr: SocketAddr = format!("127.0.0.1:{}", driver_port).parse().unwrap();
+        tokio::spawn(tonic::transport::Server::builder().add_service(driver).serve(addr));
+
+        let dir = TestConfig::default();
+        let policies_file = dir.path().join("policies.json");
+        std::fs::write(
+            &policies_file,
+            "[{\"securityDomain\":\"network2\",\"rules\":[{\"principal\":\"Org2MSP\",\"principalType\":\"ca\",\"resource\":\"mychannel:simplestate:Read:a/b\",\"read\":true}]}]",
+        )
+        .unwrap();
+        let tables = format!(
+            "[drivers.Fabric]\nhostname=\"localhost\"\nport=\"{}\"\ntls=false\ntlsca_cert_path=\"\"\n\
+             [relays.Corda_Relay]\nhostname=\"localhost\"\nport=\"9081\"\ntls=false\ntlsca_cert_path=\"\"\n\
+             [networks.network1]\nnetwork=\"Fabric\"\naccess_control_policies=\"{}\"\n",
+            driver_port,
+            policies_file.display()
+        );
+        let conf = dir.relay_config("Fabric_Relay", "9080", &tables);
+        let service = service(&conf);
+        let escaped = |request_id: &str, view: &str| Query {
+            requesting_network: "network2".to_string(),
+            requesting_org: "Org2MSP".to_string(),
+            ..query(request_id, "Corda_Relay", &format!("localhost:9080/network1/{}", view))
+        };
+
+        let ack = service.serve_query(None, escaped("r1", "mychannel:simplestate:Read:a%2Fb")).await;
+        assert_eq!(ack.status, ack::Status::Ok as i32, "{}", ack.message);
+        let sent = tokio::time::timeout(Duration::from_secs(5), received.recv()).await.unwrap().unwrap();
+        assert_eq!(sent.address, "localhost:9080/network1/mychannel:simplestate:Read:a/b");
+
+        // The escaped view is not a different view as far as the policies are concerned.
+        let ack = service.serve_query(None, escaped("r2", "mychannel:simplestate:Read:a%2Fc")).await;
+        assert_eq!(ack.status, ack::Status::Error as i32);
+    }
+
+    #[tokio::test]
+    async fn only_the_previous_hop_may_cancel_a_query() {
+        let dir = TestConfig::default();
+        let conf = dir.relay_config("Fabric_Relay", "9080", "");
+        let db = remote_db(&conf);
+        let discovery = Arc::new(RelayDiscovery::new());
+        let limits = RateLimits {
+            default: RateLimitSpec { max_concurrent: Some(1), ..Default::default() },
+            ..Default::default()
+        };
+        let rate_limiter = RateLimiter::new();
+        let served = query("r1", "Corda_Relay", "localhost:9080/network1/mychannel:simplestate:Read:a");
+        rate_limiter.try_acquire_request("r1", &served, &limits).unwrap();
+        db.set(&"r1".to_string(), &served).unwrap();
+
+        let denied = cancel_query(&cancel("r1", "Fabric_Relay2"), &db, &conf, &rate_limiter, discovery.clone());
+        assert!(matches!(denied, Err(Error::AccessDenied(_))));
+        assert!(db.has_key("r1".to_string()).unwrap());
+        assert!(!db.has_key(get_cancelled_query_key("r1".to_string())).unwrap());
+        assert!(rate_limiter.try_acquire_request("r2", &served, &limits).is_err());
+
+        cancel_query(&cancel("r1", "Corda_Relay"), &db, &conf, &rate_limiter, discovery.clone()).unwrap();
+        assert!(!db.has_key("r1".to_string()).unwrap());
+        assert_eq!(db.get::<Query>(get_cancelled_query_key("r1".to_string())).unwrap(), served);
+        assert!(rate_limiter.try_acquire_request("r2", &served, &limits).is_ok());
+
+        assert!(cancel_query(&cancel("unknown", "Corda_Relay"), &db, &conf, &rate_limiter, discovery).is_err());
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_forwarded_query_cancels_it_at_the_next_relay() {
+        // The next relay, serving the query for this relay.
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port().to_string();
+        let next_dir = TestConfig::default();
+        let next_conf = next_dir.relay_config("Fabric_Relay2", &port, "");
+        let next_db = remote_db(&next_conf);
+        let address = format!("localhost:{}/network2/mychannel:simplestate:Read:a", port);
+        next_db.set(&"r1".to_string(), &query("r1", "Fabric_Relay", &address)).unwrap();
+        let next_relay = DataTransferServer::new(service(&next_conf));
+        let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+        tokio::spawn(tonic::transport::Server::builder().add_service(next_relay).serve(addr));
+
+        let dir = TestConfig::default();
+        let conf = dir.relay_config("Fabric_Relay", "9080", "");
+        let db = remote_db(&conf);
+        let forwarded = Query {
+            relay_path: vec!["Corda_Relay".to_string(), "Fabric_Relay".to_string()],
+            ..query("r1", "Corda_Relay", &address)
+        };
+        db.set(&get_forwarded_query_key("r1".to_string()), &forwarded).unwrap();
+        let denied = cancel_query(&cancel("r1", "Corda_Relay"), &db, &conf, &RateLimiter::new(), Arc::new(RelayDiscovery::new()));
+        assert!(matches!(denied, Err(Error::AccessDenied(_))), "the previous hop is the last relay of the path");
+
+        let forwarded = Query { relay_path: vec!["Corda_Relay".to_string()], ..forwarded };
+        db.set(&get_forwarded_query_key("r1".to_string()), &forwarded).unwrap();
+        cancel_query(&cancel("r1", "Corda_Relay"), &db, &conf, &RateLimiter::new(), Arc::new(RelayDiscovery::new())).unwrap();
+        assert!(!db.has_key(get_forwarded_query_key("r1".to_string())).unwrap());
+        assert!(db.has_key(get_cancelled_query_key("r1".to_string())).unwrap());
+
+        let cancelled_key = get_cancelled_query_key("r1".to_string());
+        let mut cancelled = false;
+        for _ in 0..100 {
+            if next_db.has_key(cancelled_key.to_string()).unwrap() {
+                cancelled = true;
+                break;
             }
+            tokio::time::sleep(Duration::from_millis(50)).await;
         }
-    });
+        assert!(cancelled, "the next relay was not told about the cancellation");
+        assert!(!next_db.has_key("r1".to_string()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn late_results_of_cancelled_queries_are_discarded() {
+        let dir = TestConfig::default();
+        let conf = dir.relay_config("Fabric_Relay", "9080", "");
+        let db = remote_db(&conf);
+        let service = service(&conf);
+
+        // The driver's result for a query cancelled while it was served.
+        db.set(&get_cancelled_query_key("r1".to_string()), &query("r1", "Corda_Relay", "")).unwrap();
+        let ack = service.send_driver_state(Request::new(view_payload("r1"))).await.unwrap().into_inner();
+        assert_eq!(ack.status, ack::Status::Ok as i32);
+        assert!(!db.has_key(get_cancelled_query_key("r1".to_string())).unwrap());
+
+        // The next relay's result for a forwarded query cancelled meanwhile.
+        db.set(&get_cancelled_query_key("r2".to_string()), &query("r2", "Corda_Relay", "")).unwrap();
+        let ack = service.send_state(Request::new(view_payload("r2"))).await.unwrap().into_inner();
+        assert_eq!(ack.status, ack::Status::Ok as i32);
+        assert!(!db.has_key(get_cancelled_query_key("r2".to_string())).unwrap());
+        // Nothing was kept for the relay the query came from.
+        assert!(!db.has_key("r2".to_string()).unwrap());
+    }
 }
@@ -6,27 +6,38 @@
 use weaverpb::common::ack::{ack, Ack};
 use weaverpb::common::query::Query;
 use weaverpb::common::state::{request_state, view_payload, RequestState, ViewPayload};
-use weaverpb::common::events::{EventSubscription, EventSubscriptionState, event_publication, EventPublication, EventState, EventStates};
+use weaverpb::common::events::{ContractTransaction, EventSubscription, EventSubscriptionState, event_publication, EventPublication, EventState, EventStates};
 use weaverpb::relay::events::event_publish_client::EventPublishClient;
 use weaverpb::relay::events::event_publish_server::EventPublish;
 use weaverpb::driver::driver::WriteExternalStateMessage;
 
 // Internal modules
 use crate::db::Database;
-use crate::error::Error;
+use crate::discovery::RelayDiscovery;
+use crate::relay_pool::call_relay;
 use crate::relay_proto::LocationSegment;
-use crate::services::helpers::{get_driver, get_driver_client, get_event_subscription_key, get_event_publication_key, update_event_state};
+use crate::error::Error;
+use crate::relay_config::RelayConfig;
+use crate::grpc_options::{limit_view_payload, message_too_large_payload, GrpcOptions, WithGrpcOptions};
+use crate::logging::{record_request_id, ViewData};
+use crate::metrics::metrics;
+use crate::driver_pool::call_driver;
+use crate::services::helpers::{get_driver, get_driver_name, get_event_subscription_key, get_event_publication_key, update_event_state};
+use crate::trace_context::traced;
+use crate::shutdown::{spawn_tracked, PendingWork};
 
 // external modules
-use config;
+use std::sync::Arc;
 use tokio::sync::RwLock;
-use tonic::{Request, Response, Status};
+use tonic::{Code, Request, Response, Status};
+use tracing::{debug, info, warn, Instrument};
 use uuid::Uuid;
-use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+use tonic::transport::Channel;
 use reqwest;
 
 pub struct EventPublishService {
-    pub config_lock: RwLock<config::Config>,
+    pub config_lock: Arc<RwLock<RelayConfig>>,
+    pub discovery: Arc<RelayDiscovery>,
 }
 
 /// EventPublishService is the gRPC server implementation that handles the logic for
@@ -40,26 +51,27 @@ impl EventPublish for EventPublishService {
     ) -> Result<Response<Ack>, Status> {
         let state = request.into_inner().clone();
         let state_clone = state.clone();
-        println!("Received State from driver: {:?}", state_clone.request_id);
+        record_request_id(&state.request_id);
+        info!("Received State from driver: {:?}", state_clone.request_id);
         match state_clone.state.as_ref().unwrap() {
-            view_payload::State::View(v) => println!("View Meta: {:?}, View Data: {:?}", v.meta, base64::encode(&v.data)),
-            view_payload::State::Error(e) => println!("Error: {:?}", e),
+            view_payload::State::View(v) => debug!("View Meta: {:?}, View Data: {}", v.meta, ViewData(&v.data)),
+            view_payload::State::Error(e) => info!("Error: {:?}", e),
         }
         let request_id = state.request_id.to_string();
-        let conf = self.config_lock.read().await;
+        let conf = self.config_lock.read().await.clone();
         // Database access/storage
         let remote_db = Database {
-            db_path: conf.get_str("remote_db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.remote_db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
 
         let result =
-            send_driver_state_helper(request_id.to_string(), remote_db, conf.clone(), state);
+            send_driver_state_helper(request_id.to_string(), remote_db, conf, state, &self.discovery).await;
         match result {
             Ok(resp) => {
                 let reply = Ok(resp);
-                println!("Sending back Ack to driver: {:?}\n", reply);
+                info!("Sending back Ack to driver: {:?}", reply);
                 return reply;
             }
             Err(e) => {
@@ -69,7 +81,7 @@ impl EventPublish for EventPublishService {
                     request_id: request_id.to_string(),
                     message: format!("Error: {:?}", e),
                 }));
-                println!("Sending back Ack to driver: {:?}\n", reply);
+                info!("Sending back Ack to driver: {:?}", reply);
                 return reply;
             }
         }
@@ -77,21 +89,22 @@ impl EventPublish for EventPublishService {
     // src-relay will forward the state as part of event subscription to dest-relay
     async fn send_state(&self, request: Request<ViewPayload>) -> Result<Response<Ack>, Status> {
         let request_view_payload = request.into_inner().clone();
-        println!(
+        record_request_id(&request_view_payload.request_id);
+        info!(
             "Event: Received state from remote relay: Request ID = {:?}",
             request_view_payload.request_id
         );
         match request_view_payload.state.as_ref().unwrap() {
-            view_payload::State::View(v) => println!("View Meta: {:?}, View Data: {:?}", v.meta, base64::encode(&v.data)),
-            view_payload::State::Error(e) => println!("Error: {:?}", e),
+            view_payload::State::View(v) => debug!("View Meta: {:?}, View Data: {}", v.meta, ViewData(&v.data)),
+            view_payload::State::Error(e) => info!("Error: {:?}", e),
         }
         let request_id = &request_view_payload.request_id.to_string();
         let conf = self.config_lock.read().await.clone();
         // Database access/storage
         let db = Database {
-            db_path: conf.get_str("db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
         let result = send_state_helper(request_view_payload, request_id.to_string(), db, conf);
 
@@ -102,7 +115,7 @@ impl EventPublish for EventPublishService {
                     request_id: request_id.to_string(),
                     message: "".to_string(),
                 }));
-                println!("Event: Sending back Ack to remote relay: {:?}", reply);
+                info!("Event: Sending back Ack to remote relay: {:?}", reply);
                 reply
             },
             Err(e) => {
@@ -111,7 +124,7 @@ impl EventPublish for EventPublishService {
                     request_id: request_id.to_string(),
                     message: format!("{:?}", e),
                 }));
-                println!("Event: Sending back Ack to remote relay: {:?}", reply);
+                info!("Event: Sending back Ack to remote relay: {:?}", reply);
                 reply
             }
         }
@@ -122,29 +135,22 @@ impl EventPublish for EventPublishService {
 
 /// send_driver_state is run on the remote relay. Runs when the driver sends the
 /// state or error back to the remote relay.
-fn send_driver_state_helper(
+async fn send_driver_state_helper(
     request_id: String,
     remote_db: Database,
-    conf: config::Config,
+    conf: RelayConfig,
     state: ViewPayload,
+    discovery: &RelayDiscovery,
 ) -> Result<Response<Ack>, Error> {
     let event_sub_key = get_event_subscription_key(request_id.to_string());
     let event_sub: EventSubscription = remote_db
         .get::<EventSubscription>(event_sub_key.to_string())
         .map_err(|e| Error::GetQuery(format!("Failed to get event subscription from db. Error: {:?}", e)))?;
     let query: &Query = &event_sub.clone().query.expect("Unexpected Error: Query not found in event subscription");
-    let relays_table = conf.get_table("relays")?;
-    let relay_uri = relays_table
-        .get(&query.requesting_relay.to_string())
-        .ok_or(Error::Simple("Relay name not found".to_string()))?;
-    let uri = relay_uri.clone().try_into::<LocationSegment>()?;
-    spawn_send_state(
-        state,
-        uri.hostname.to_string(),
-        uri.port.to_string(),
-        uri.tls,
-        uri.tlsca_cert_path.to_string(),
-    );
+    let endpoints = discovery.resolve_relay(&query.requesting_relay, &conf).await.inspect_err(|_| {
+        metrics().record_callback_failure(&query.requesting_relay);
+    })?;
+    spawn_send_state(state, query.requesting_relay.to_string(), endpoints, conf.grpc.clone());
     let reply = Ack {
         status: ack::Status::Ok as i32,
         request_id,
@@ -156,53 +162,41 @@ fn send_driver_state_helper(
 
 // spawn_send_state sends data from the remote relay back to the requesting relay
 // When it errors it currently logs to console. Needs improving
-fn spawn_send_state(state: ViewPayload, requestor_host: String, requester_port: String, use_tls: bool, tlsca_cert_path: String) {
-    tokio::spawn(async move {
-        println!("Event Publish: Sending state back to requesting relay: Request ID = {:?}", state.request_id);
+pub fn spawn_send_state(
+    state: ViewPayload,
+    requesting_relay: String,
+    endpoints: Vec<LocationSegment>,
+    grpc_options: GrpcOptions,
+) {
+    let work = PendingWork::PublishState {
+        state: state.clone(),
+        requesting_relay: requesting_relay.to_string(),
+        endpoints: endpoints.clone(),
+    };
+    spawn_tracked(work, async move {
+        let state = limit_view_payload(state, &grpc_options);
+        info!("Event Publish: Sending state back to requesting relay: Request ID = {:?}", state.request_id);
         match state.state.as_ref().unwrap() {
-            view_payload::State::View(v) => println!("View Meta: {:?}, View Data: {:?}", v.meta, base64::encode(&v.data)),
-            view_payload::State::Error(e) => println!("Error: {:?}", e),
+            view_payload::State::View(v) => debug!("View Meta: {:?}, View Data: {}", v.meta, ViewData(&v.data)),
+            view_payload::State::Error(e) => info!("Error: {:?}", e),
         }
-        if use_tls {
-            let client_addr = format!("https://{}:{}", requestor_host, requester_port);
-            let pem = tokio::fs::read(tlsca_cert_path).await.unwrap();
-            let ca = Certificate::from_pem(pem);
-
-            let tls = ClientTlsConfig::new()
-                .ca_certificate(ca)
-                .domain_name(requestor_host);
-
-            let channel = Channel::from_shared(client_addr.to_string()).unwrap()
-                .tls_config(tls).expect(&format!("Error in TLS configuration for client: {}", client_addr.to_string()))
-                .connect()
-                .await
-                .unwrap();
-
-            let mut client_result = EventPublishClient::new(channel);
-            let response = client_result.send_state(state).await;
-            println!("Event Publish: Response ACK from requesting relay={:?}\n", response);
-        } else {
-            let client_addr = format!("http://{}:{}", requestor_host, requester_port);
-            let client_result = EventPublishClient::connect(client_addr).await;
-            match client_result {
-                Ok(client) => {
-                    let response = client.clone().send_state(state).await;
-                    println!("Event Publish: Response ACK from requesting relay={:?}\n", response);
-                    // Not returning anything here
-                }
-                Err(e) => {
-                    // TODO: Add better error handling (Attempt a few times?)
-                    println!(
-                        "Event Publish: Failed to connect to client: ${:?}. Error: {}\n",
-                        requester_port,
-                        e.to_string()
-                    );
-                    // TODO: Handle this error thorugh join handle after thread completes.
-                    // Not actually returning anything here yet
-                }
+        // The event goes to whichever endpoint of the subscribing relay can be reached.
+        let result = call_relay(&endpoints, |channel| {
+            let client = EventPublishClient::new(channel).with_grpc_options(&grpc_options);
+            send_state_to_relay(client, state.clone())
+        })
+        .await;
+        match result {
+            Ok((response, endpoint)) => {
+                info!("Event Publish: Response ACK from requesting relay {}={:?}", endpoint.address(), response);
+                metrics().record_callback_result(&requesting_relay, &Ok(response));
+            }
+            Err(e) => {
+                warn!("Event Publish: Failed to send state to requesting relay {}: {:?}", requesting_relay, e);
+                metrics().record_callback_failure(&requesting_relay);
             }
         }
-    });
+    }.in_current_span());
 }
 
 /// send_state is run on the requesting relay when a remote relay sends a result back to the requesting relay
@@ -210,7 +204,7 @@ fn send_state_helper(
     state: ViewPayload,
     request_id: String,
     db: Database,
-    conf: config::Config
+    conf: RelayConfig
 ) -> Result<(), Error> {
     let event_sub_key = get_event_subscription_key(request_id.to_string());
     let event_sub_state: EventSubscriptionState = db.get::<EventSubscriptionState>(event_sub_key.to_string())
@@ -226,6 +220,8 @@ fn send_state_helper(
                     status: request_state::Status::EventReceived as i32,
                     request_id: request_id.to_string(),
                     state: Some(request_state::State::View(payload)),
+                    relay_path: vec![],
+                    callback: None,
                 };
                 target = EventState {
                     state: Some(request_state),
@@ -238,6 +234,8 @@ fn send_state_helper(
                     status: request_state::Status::Error as i32,
                     request_id: request_id.to_string(),
                     state: Some(request_state::State::Error(error)),
+                    relay_path: vec![],
+                    callback: None,
                 };
                 target = EventState {
                     state: Some(request_state),
@@ -251,6 +249,8 @@ fn send_state_helper(
                 status: request_state::Status::Error as i32,
                 request_id: request_id.to_string(),
                 state: Some(request_state::State::Error("Missing state".to_string())),
+                relay_path: vec![],
+                callback: None,
             };
             target = EventState {
                 state: Some(request_state),
@@ -272,6 +272,7 @@ fn send_state_helper(
     
     db.set(&event_publish_key, &event_states)
         .expect("Failed to insert into DB");
+    metrics().record_event("received");
     
     for event_pub_spec in event_sub_state.event_publication_specs.iter() {
         spawn_handle_event(
@@ -287,92 +288,66 @@ fn send_state_helper(
 }
 
 // Calls handle_event and updates status of event_state depending upon the success or failure.
-fn spawn_handle_event(state: ViewPayload, publication_spec: EventPublication, request_id: String, event_id: String, conf: config::Config) {
-    tokio::spawn(async move {
-        println!("Event Publish: Sending state to subscriber: Request ID = {:?}", request_id.to_string());
+pub fn spawn_handle_event(state: ViewPayload, publication_spec: EventPublication, request_id: String, event_id: String, conf: RelayConfig) {
+    let work = PendingWork::HandleEvent {
+        state: state.clone(),
+        publication_spec: publication_spec.clone(),
+        request_id: request_id.to_string(),
+        event_id: event_id.to_string(),
+    };
+    spawn_tracked(work, async move {
+        info!("Event Publish: Sending state to subscriber: Request ID = {:?}", request_id.to_string());
         match state.state.as_ref().unwrap() {
-            view_payload::State::View(v) => println!("View Meta: {:?}, View Data: {:?}", v.meta, base64::encode(&v.data)),
-            view_payload::State::Error(e) => println!("Error: {:?}", e),
+            view_payload::State::View(v) => debug!("View Meta: {:?}, View Data: {}", v.meta, ViewData(&v.data)),
+            view_payload::State::Error(e) => info!("Error: {:?}", e),
         }
         let result = handle_event(state, publication_spec, conf.clone()).await;
         match result {
             Ok(message) => {
                 if message.contains("written") {
+                    metrics().record_event("written");
                     // Update published event status to written to request_id, event_id in db
                     update_event_state(
                         request_id,
                         event_id,
                         request_state::Status::EventWritten,
-                        conf.get_str("db_path").unwrap(),
-                        conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-                        conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+                        conf.db_path.to_string(),
+                        conf.db_open_max_retries,
+                        conf.db_open_retry_backoff_msec,
                         message.to_string(),
                     )
                 }
-                println!("Success: {}", message.to_string());
+                info!("Success: {}", message.to_string());
             }
             Err(e) => {
                 // Update published event status to error and error message to request_id, event_id in db
-                println!("Write Error: {:?}", e);
+                warn!("Write Error: {:?}", e);
+                metrics().record_event("failed");
                 update_event_state(
                     request_id,
                     event_id,
                     request_state::Status::EventWriteError,
-                    conf.get_str("db_path").unwrap(),
-                    conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-                    conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+                    conf.db_path.to_string(),
+                    conf.db_open_max_retries,
+                    conf.db_open_retry_backoff_msec,
                     format!("Write Error: {:?}", e),
                 )
             }
         };
-    });
+    }.in_current_span());
 }
 
 // Sends event payload to either driver or app_url depending upon publication spec during subscription
 async fn handle_event(
     state: ViewPayload, 
     publication_spec: EventPublication,
-    conf: config::Config
+    conf: RelayConfig
 ) -> Result<String, Error>  {
     return match publication_spec.publication_target {
         Some(data) => match data {
-            event_publication::PublicationTarget::Ctx(ctx) => {
-                let driver_id = ctx.clone().driver_id.to_string();
-                let result = get_driver(driver_id.to_string(), conf.clone());
-                match result {
-                    Ok(driver_info) => {
-                        let client = get_driver_client(driver_info).await?;
-                        println!("Sending Received Event to driver: {:?}", state.clone().request_id.to_string());
-                        let write_external_state_message: WriteExternalStateMessage = WriteExternalStateMessage {
-                            view_payload: Some(state),
-                            ctx: Some(ctx),
-                        };
-                        let ack = client
-                            .clone()
-                            .write_external_state(write_external_state_message)
-                            .await?
-                            .into_inner();
-                        println!("Response ACK from driver={:?}\n", ack);
-                        let status = ack::Status::from_i32(ack.status)
-                            .ok_or(Error::Simple("Status from Driver error".to_string()))?;
-                        match status {
-                            ack::Status::Ok => Ok(format!("Successfully written to ledger based on ContractTransaction")),
-                            ack::Status::Error => Err(Error::Simple(format!("Error from driver: {}", ack.message))),
-                        }
-                    },
-                    Err(e) => Err(e),
-                }
-            }
+            event_publication::PublicationTarget::Ctx(ctx) => write_to_ledger(state, ctx, &conf).await,
             event_publication::PublicationTarget::AppUrl(app_url) => {
-                let app_url_clone = app_url.clone();
-                tokio::spawn(async move {
-                    let client = reqwest::Client::new();
-                    let res = client.post(&app_url_clone)
-                        .json(&state)
-                        .send()
-                        .await;
-                    println!("Result from app: {:?}", res);
-                });
+                spawn_post_to_app_url(app_url.clone(), state);
                 Ok(format!("Published to app url: {}", app_url.to_string()))
             }
         },
@@ -380,4 +355,65 @@ async fn handle_event(
             Ok(format!("Cannot publish event received: No publication target found."))
         }
     };
-}
\ No newline at end of file
+}
+// Writes a state to the ledger with the contract transaction of a publication
+// spec, through the driver it names.
+pub async fn write_to_ledger(state: ViewPayload, ctx: ContractTransaction, conf: &RelayConfig) -> Result<String, Error> {
+    let driver_id = ctx.driver_id.to_string();
+    let driver_info = get_driver(driver_id.to_string(), conf.clone())?;
+    let driver_name = get_driver_name(&driver_id, conf);
+    let result = async {
+        info!("Sending state to driver to write to the ledger: {:?}", state.request_id.to_string());
+        let write_external_state_message: WriteExternalStateMessage = WriteExternalStateMessage {
+            view_payload: Some(state),
+            ctx: Some(ctx),
+        };
+        let (ack, _) = call_driver(&driver_name, &driver_info, &conf.grpc, |mut client| {
+            let message = write_external_state_message.clone();
+            async move { client.write_external_state(traced(message)).await }
+        })
+        .await?;
+        let ack = ack.into_inner();
+        info!("Response ACK from driver={:?}", ack);
+        let status = ack::Status::try_from(ack.status)
+            .map_err(|_| Error::Simple("Status from Driver error".to_string()))?;
+        match status {
+            ack::Status::Ok => Ok("Successfully written to ledger based on ContractTransaction".to_string()),
+            ack::Status::Error => Err(Error::Simple(format!("Error from driver: {}", ack.message))),
+        }
+    }.await;
+    if result.is_err() {
+        metrics().record_driver_error(&driver_name);
+    }
+    result
+}
+
+// Posts the event payload to the app_url of the publication spec
+pub fn spawn_post_to_app_url(app_url: String, state: ViewPayload) {
+    let work = PendingWork::PostToAppUrl {
+        app_url: app_url.to_string(),
+        state: state.clone(),
+    };
+    spawn_tracked(work, async move {
+        let client = reqwest::Client::new();
+        let res = client.post(&app_url)
+            .json(&state)
+            .send()
+            .await;
+        info!("Result from app: {:?}", res);
+    }.in_current_span());
+}
+
+// Sends the state to the requesting relay. When the requesting relay refuses it
+// for exceeding its maximum message size, the reason is sent in its place so
+// that the request does not stay pending.
+async fn send_state_to_relay(mut client: EventPublishClient<Channel>, state: ViewPayload) -> Result<Response<Ack>, Status> {
+    let request_id = state.request_id.to_string();
+    match client.send_state(traced(state)).await {
+        Err(status) if status.code() == Code::OutOfRange => {
+            warn!("Event Publish: Requesting relay refused the view payload: {}", status.message());
+            client.send_state(traced(message_too_large_payload(request_id, status.message().to_string()))).await
+        }
+        response => response,
+    }
+}
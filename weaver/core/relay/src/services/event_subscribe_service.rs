@@ -9,20 +9,33 @@ use weaverpb::common::events::{EventSubOperation, EventSubscription};
 use weaverpb::relay::events::event_subscribe_client::EventSubscribeClient;
 use weaverpb::relay::events::event_subscribe_server::EventSubscribe;
 // Internal modules
+use crate::access_control::{check_access, check_relay_admission};
 use crate::db::Database;
+use crate::discovery::RelayDiscovery;
+use crate::relay_pool::call_relay;
+use crate::driver_pool::call_driver;
 use crate::error::Error;
-use crate::relay_proto::{parse_address, LocationSegment};
-use crate::services::helpers::{update_event_subscription_status, get_driver, get_driver_client, get_event_subscription_key};
+use crate::relay_config::RelayConfig;
+use crate::grpc_options::{GrpcOptions, WithGrpcOptions};
+use crate::logging::{record_request_id, Redacted};
+use crate::metrics::metrics;
+use crate::rate_limit::RateLimiter;
+use crate::relay_proto::{decoded_address, parse_address, LocationSegment};
+use crate::services::helpers::{update_event_subscription_status, get_driver, get_driver_name, get_event_subscription_key};
 use crate::services::types::{Driver};
+use crate::trace_context::traced;
+use crate::shutdown::{spawn_tracked, PendingWork};
 // external modules
-use config;
+use std::sync::Arc;
 use tokio::sync::RwLock;
 use tonic::{Request, Response, Status};
+use tracing::{error, info, warn, Instrument};
 
-use tonic::transport::{Certificate, Channel, ClientTlsConfig};
 
 pub struct EventSubscribeService {
-    pub config_lock: RwLock<config::Config>,
+    pub config_lock: Arc<RwLock<RelayConfig>>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub discovery: Arc<RelayDiscovery>,
 }
 
 /// EventSubscribeService is the gRPC server implementation that handles the logic for
@@ -31,36 +44,75 @@ pub struct EventSubscribeService {
 impl EventSubscribe for EventSubscribeService {
     // Dest-relay will call this endpoint of src-relay to request for event Subscription/Unsubscription
     async fn subscribe_event(&self, request: Request<EventSubscription>) -> Result<Response<Ack>, Status> {
-        println!(
+        info!(
             "Got a Event Subscription request from {:?} - {:?}",
             request.remote_addr(),
-            request
+            Redacted(request.get_ref())
         );
+        let remote_addr = request.remote_addr();
         let event_subscription = request.into_inner().clone();
         let query = event_subscription.query.clone().expect("No query passed with EventSubscription request");
         let request_id = query.request_id.to_string();
-        
-        let conf = self.config_lock.read().await;
+        record_request_id(&request_id);
+
+        let conf = self.config_lock.read().await.clone();
+        // Requests from unknown or disallowed relays, or with an address that cannot be
+        // parsed, are turned away before any work is done for them.
+        let relay_known = self.discovery.resolve_relay(&query.requesting_relay, &conf).await.is_ok();
+        if let Err(e) = check_relay_admission(&query, &conf, relay_known) {
+            warn!("Rejected EventSubscription request {} from relay {:?} at {:?}: {:?}", request_id, query.requesting_relay, remote_addr, e);
+            let reply = Ok(Response::new(Ack {
+                status: ack::Status::Error as i32,
+                request_id,
+                message: match e {
+                    Error::AccessDenied(_) | Error::Address(_) => format!("Error: {}", e),
+                    _ => format!("Error: {:?}", e),
+                },
+            }));
+            info!("Sending back Ack: {:?}", reply);
+            return reply;
+        }
         // Database access/storage
         let remote_db = Database {
-            db_path: conf.get_str("remote_db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.remote_db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
-        match subscribe_event_helper(remote_db, request_id.to_string(), event_subscription, conf.clone()) {
+        // Unsubscriptions are never throttled, they only free up capacity.
+        let subscribe = event_subscription.operation == EventSubOperation::Subscribe as i32;
+        if subscribe {
+            let rate_limits = conf.rate_limits.clone();
+            if let Err(throttled) = self.rate_limiter.try_acquire_subscription(&request_id, &query, &rate_limits) {
+                warn!("Rejecting EventSubscription request {}: {}", request_id, throttled);
+                let reply = Ok(Response::new(Ack {
+                    status: ack::Status::Error as i32,
+                    request_id,
+                    message: format!("Error: {}", throttled),
+                }));
+                info!("Sending back Ack: {:?}", reply);
+                return reply;
+            }
+        }
+        match subscribe_event_helper(remote_db, request_id.to_string(), event_subscription, conf.clone(), self.rate_limiter.clone(), self.discovery.clone()) {
             Ok(ack) => {
                 let reply = Ok(Response::new(ack));
-                println!("Sending back Ack: {:?}\n", reply);
+                info!("Sending back Ack: {:?}", reply);
                 reply
             }
             Err(e) => {
-                println!("EventSubscription from Driver failed.");
+                warn!("EventSubscription from Driver failed.");
+                if subscribe {
+                    self.rate_limiter.release_subscription(&request_id);
+                }
                 let reply = Ok(Response::new(Ack {
                     status: ack::Status::Error as i32,
                     request_id,
-                    message: format!("Error: EventSubscription from Driver failed. {:?}", e),
+                    message: match e {
+                        Error::AccessDenied(reason) => format!("Error: {}", reason),
+                        _ => format!("Error: EventSubscription from Driver failed. {:?}", e),
+                    },
                 }));
-                println!("Sending back Ack: {:?}\n", reply);
+                info!("Sending back Ack: {:?}", reply);
                 reply
             }
         }
@@ -74,23 +126,24 @@ impl EventSubscribe for EventSubscribeService {
     ) -> Result<Response<Ack>, Status> {
         let ack = request.into_inner().clone();
         let ack_clone = ack.clone();
-        println!("Received Ack from driver: {:?}", ack_clone.request_id);
+        record_request_id(&ack.request_id);
+        info!("Received Ack from driver: {:?}", ack_clone.request_id);
 
         let request_id = ack.request_id.to_string();
-        let conf = self.config_lock.read().await;
+        let conf = self.config_lock.read().await.clone();
         // Database access/storage
         let remote_db = Database {
-            db_path: conf.get_str("remote_db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.remote_db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
         
         let result =
-            send_driver_subscription_status_helper(request_id.to_string(), remote_db, conf.clone(), ack);
+            send_driver_subscription_status_helper(request_id.to_string(), remote_db, conf.clone(), ack, &self.rate_limiter, &self.discovery).await;
         match result {
             Ok(resp) => {
                 let reply = Ok(resp);
-                println!("Sending back Ack to driver: {:?}\n", reply);
+                info!("Sending back Ack to driver: {:?}", reply);
                 return reply;
             }
             Err(e) => {
@@ -100,7 +153,7 @@ impl EventSubscribe for EventSubscribeService {
                     request_id: request_id.to_string(),
                     message: format!("Error: {:?}", e),
                 }));
-                println!("Sending back Ack to driver: {:?}\n", reply);
+                info!("Sending back Ack to driver: {:?}", reply);
                 return reply;
             }
         }
@@ -109,16 +162,17 @@ impl EventSubscribe for EventSubscribeService {
     // Src-relay will call this endpoint of dest-relay to send Subscription/Unsubscription response.
     async fn send_subscription_status(&self, request: Request<Ack>) -> Result<Response<Ack>, Status> {
         let request_ack = request.into_inner().clone();
-        println!(
+        record_request_id(&request_ack.request_id);
+        info!(
             "Received Ack from remote relay for Event Subscription Request ID = {:?}",
             request_ack.request_id
         );
         let request_id = &request_ack.request_id.to_string();
         let conf = self.config_lock.read().await.clone();
         // Database access/storage
-        let db_path = conf.get_str("db_path").unwrap();
-        let db_open_max_retries = conf.get_int("db_open_max_retries").unwrap_or(500) as u32;
-        let db_open_retry_backoff_msec = conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32;
+        let db_path = conf.db_path.to_string();
+        let db_open_max_retries = conf.db_open_max_retries;
+        let db_open_retry_backoff_msec = conf.db_open_retry_backoff_msec;
         
         let result = send_subscription_status_helper(
             request_ack, 
@@ -129,15 +183,15 @@ impl EventSubscribe for EventSubscribeService {
         );
 
         match result {
-            Ok(_) => println!("Successfully set event subscription status in DB."),
-            Err(e) => println!("Setting value in DB failed: {:?}", e),
+            Ok(_) => info!("Successfully set event subscription status in DB."),
+            Err(e) => error!("Setting value in DB failed: {:?}", e),
         }
         let reply = Ok(Response::new(Ack {
             status: ack::Status::Ok as i32,
             request_id: request_id.to_string(),
             message: "".to_string(),
         }));
-        println!("Sending back Ack to remote relay: {:?}", reply);
+        info!("Sending back Ack to remote relay: {:?}", reply);
         reply
     }
 
@@ -150,19 +204,29 @@ fn subscribe_event_helper(
     remote_db: Database,
     request_id: String,
     event_subscription: EventSubscription,
-    conf: config::Config,
+    conf: RelayConfig,
+    rate_limiter: Arc<RateLimiter>,
+    discovery: Arc<RelayDiscovery>,
 ) -> Result<Ack, Error> {
+    let mut event_subscription = event_subscription;
+    let query = event_subscription.query.as_mut().expect("No query passed with EventSubscription request");
+    let parsed_address = parse_address(query.address.to_string())?;
+    if event_subscription.operation == EventSubOperation::Subscribe as i32 {
+        // Reject subscriptions the local network's access control policies deny before any work is done.
+        check_access(query, &parsed_address, &conf)?;
+    }
+    // The driver is sent the view that the access control policies were checked against.
+    query.address = decoded_address(&query.address)?;
     let event_sub_key = get_event_subscription_key(request_id.to_string());
     let _set_event_sub = remote_db
         .set(&event_sub_key.to_string(), &event_subscription)
         .map_err(|e| Error::Simple(format!("DB Failure: {:?}", e)))?;
         
-    let query = event_subscription.query.clone().expect("No query passed with EventSubscription request");
-    let parsed_address = parse_address(query.address.to_string())?;
     let result = get_driver(parsed_address.network_id.to_string(), conf.clone());
     match result {
         Ok(driver_info) => {
-            spawn_driver_subscribe_event(event_subscription, driver_info, conf.clone());
+            let driver_name = get_driver_name(&parsed_address.network_id, &conf);
+            spawn_driver_subscribe_event(event_subscription, driver_info, driver_name, conf.clone(), rate_limiter, discovery);
             return Ok(Ack {
                 status: ack::Status::Ok as i32,
                 request_id,
@@ -174,24 +238,36 @@ fn subscribe_event_helper(
 }
 
 // Function that starts a thread which sends the event subscription request to the driver
-fn spawn_driver_subscribe_event(event_subscription: EventSubscription, driver_info: Driver, conf: config::Config) {
-    tokio::spawn(async move {
-        let result = spawn_driver_subscribe_event_helper(event_subscription.clone(), driver_info).await;
+pub fn spawn_driver_subscribe_event(
+    event_subscription: EventSubscription,
+    driver_info: Driver,
+    driver_name: String,
+    conf: RelayConfig,
+    rate_limiter: Arc<RateLimiter>,
+    discovery: Arc<RelayDiscovery>,
+) {
+    let work = PendingWork::DriverSubscribeEvent {
+        event_subscription: event_subscription.clone(),
+        driver_name: driver_name.to_string(),
+    };
+    spawn_tracked(work, async move {
+        let result = spawn_driver_subscribe_event_helper(event_subscription.clone(), driver_info, &driver_name, &conf.grpc).await;
         match result {
             Ok(_) => {
                 // Do nothing
-                println!("Ack Ok from driver\n")
+                info!("Ack Ok from driver")
             }
             Err(e) => {
-                println!("Error sending event subscription request to driver: {:?}\n", e);
+                warn!("Error sending event subscription request to driver: {:?}", e);
+                metrics().record_driver_error(&driver_name);
                 // In Error case we send an error_ack to requesting relay.
                 let query = event_subscription.query.clone().expect("No query passed with EventSubscription request");
                 let request_id = query.request_id.to_string();
                 // Database access/storage
                 let remote_db = Database {
-                    db_path: conf.get_str("remote_db_path").unwrap(),
-                    db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-                    db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+                    db_path: conf.remote_db_path.to_string(),
+                    db_open_max_retries: conf.db_open_max_retries,
+                    db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
                 };
                 let error_ack = Ack {
                     status: ack::Status::Error as i32,
@@ -203,32 +279,38 @@ fn spawn_driver_subscribe_event(event_subscription: EventSubscription, driver_in
                     remote_db,
                     conf.clone(),
                     error_ack,
-                );
+                    &rate_limiter,
+                    &discovery,
+                )
+                .await;
                 match result {
                     Ok(_) => {
-                        println!(
+                        info!(
                             "Driver's subscribe_event error successfully sent back to requesting relay"
                         );
                     }
-                    Err(e) => println!("Error sending Ack: {:?}", e),
+                    Err(e) => error!("Error sending Ack: {:?}", e),
                 }
             }
         }
-    });
+    }.in_current_span());
 }
 
 async fn spawn_driver_subscribe_event_helper(
     event_subscription: EventSubscription,
     driver_info: Driver,
+    driver_name: &str,
+    grpc_options: &GrpcOptions,
 ) -> Result<(), Error> {
-    let client = get_driver_client(driver_info).await?;
-    println!("Sending EventSubscription Request to driver: {:?}", event_subscription.clone());
-    let ack = client
-        .clone()
-        .subscribe_event(event_subscription)
-        .await?
-        .into_inner();
-    println!("Response ACK from driver={:?}\n", ack);
+    info!("Sending EventSubscription Request to driver: {:?}", Redacted(&event_subscription));
+    let (ack, instance) = call_driver(driver_name, &driver_info, grpc_options, |mut client| {
+        let event_subscription = event_subscription.clone();
+        async move { client.subscribe_event(traced(event_subscription)).await }
+    })
+    .await?;
+    let ack = ack.into_inner();
+    info!("EventSubscription taken by driver {} instance {}", driver_name, instance.address());
+    info!("Response ACK from driver={:?}", ack);
     let status = ack::Status::from_i32(ack.status)
         .ok_or(Error::Simple("Status from Driver error".to_string()))?;
     match status {
@@ -244,11 +326,13 @@ async fn spawn_driver_subscribe_event_helper(
 /// send_driver_subscription_status is run on the remote relay. Runs when the driver sends the
 /// ack back to the remote relay or if there was an error making the
 /// suscribe_event gRPC call.
-fn send_driver_subscription_status_helper(
+async fn send_driver_subscription_status_helper(
     request_id: String,
     remote_db: Database,
-    conf: config::Config,
+    conf: RelayConfig,
     ack: Ack,
+    rate_limiter: &RateLimiter,
+    discovery: &RelayDiscovery,
 ) -> Result<Response<Ack>, Error> {
     let event_sub_key = get_event_subscription_key(request_id.to_string());
     let event_sub: EventSubscription =  remote_db
@@ -258,19 +342,20 @@ fn send_driver_subscription_status_helper(
     let operation = EventSubOperation::from_i32(event_sub.operation)
         .ok_or(Error::Simple("Error: No Event Subscription operation defined".to_string()))?;
     let unsubscribe = operation == EventSubOperation::Unsubscribe;
-    let relays_table = conf.get_table("relays")?;
-    let relay_uri = relays_table
-        .get(&query.requesting_relay.to_string())
-        .ok_or(Error::Simple("Relay name not found".to_string()))?;
-    let uri = relay_uri.clone().try_into::<LocationSegment>()?;
+    // A successful unsubscription or a failed subscription frees up the requester's subscription quota.
+    if unsubscribe == (ack.status == ack::Status::Ok as i32) {
+        rate_limiter.release_subscription(&request_id);
+    }
+    let endpoints = discovery.resolve_relay(&query.requesting_relay, &conf).await.inspect_err(|_| {
+        metrics().record_callback_failure(&query.requesting_relay);
+    })?;
     spawn_send_subscription_status(
         ack,
-        uri.hostname.to_string(),
-        uri.port.to_string(),
-        uri.tls,
-        uri.tlsca_cert_path.to_string(),
+        query.requesting_relay.to_string(),
+        endpoints,
         unsubscribe,
-        remote_db
+        remote_db,
+        conf.grpc.clone(),
     );
     let reply = Ack {
         status: ack::Status::Ok as i32,
@@ -285,63 +370,48 @@ fn send_driver_subscription_status_helper(
 // spawn_send_subscription_status sends event subscription status from the remote relay back 
 // to the requesting relay.
 // When it errors it currently logs to console. Needs improving
-fn spawn_send_subscription_status(
+pub fn spawn_send_subscription_status(
     ack: Ack,
-    requestor_host: String,
-    requester_port: String,
-    use_tls: bool,
-    tlsca_cert_path: String,
+    requesting_relay: String,
+    endpoints: Vec<LocationSegment>,
     unsubscribe: bool,
     remote_db: Database,
+    grpc_options: GrpcOptions,
 ) {
-    tokio::spawn(async move {
+    let work = PendingWork::SendSubscriptionStatus {
+        ack: ack.clone(),
+        requesting_relay: requesting_relay.to_string(),
+        endpoints: endpoints.clone(),
+        unsubscribe,
+    };
+    spawn_tracked(work, async move {
         let request_id = ack.clone().request_id;
-        println!("Sending Subscription Status back to requesting relay: Request ID = {:?}", request_id);
-        let mut response: Result<tonic::Response<Ack>, tonic::Status> = Err(tonic::Status::unimplemented("uninitialized"));
-        if use_tls {
-            let client_addr = format!("https://{}:{}", requestor_host, requester_port);
-            let pem = tokio::fs::read(tlsca_cert_path).await.unwrap();
-            let ca = Certificate::from_pem(pem);
-
-            let tls = ClientTlsConfig::new()
-                .ca_certificate(ca)
-                .domain_name(requestor_host);
-
-            let channel = Channel::from_shared(client_addr.clone()).unwrap()
-                .tls_config(tls).expect(&format!("Error in TLS configuration for client: {}", client_addr.to_string()))
-                .connect()
-                .await
-                .unwrap();
-
-            let mut client_result = EventSubscribeClient::new(channel);
-            response = client_result.send_subscription_status(ack.clone()).await;
-            println!("Response ACK from requesting relay={:?}\n", response);
-        } else {
-            let client_addr = format!("http://{}:{}", requestor_host, requester_port);
-            let client_result = EventSubscribeClient::connect(client_addr.clone()).await;
-            match client_result {
-                Ok(client) => {
-                    response = client.clone().send_subscription_status(ack.clone()).await;
-                    println!("Response ACK from requesting relay={:?}\n", response);
-                    // Not returning anything here
-                }
-                Err(e) => {
-                    // TODO: Add better error handling (Attempt a few times?)
-                    println!(
-                        "Failed to connect to client: ${:?}. Error: {}\n",
-                        client_addr,
-                        e.to_string()
-                    );
-                    // TODO: Handle this error thorugh join handle after thread completes.
-                    // Not actually returning anything here yet
-                }
+        info!("Sending Subscription Status back to requesting relay: Request ID = {:?}", request_id);
+        // The status goes to whichever endpoint of the requesting relay can be reached.
+        let result = call_relay(&endpoints, |channel| {
+            let mut client = EventSubscribeClient::new(channel).with_grpc_options(&grpc_options);
+            let ack = ack.clone();
+            async move { client.send_subscription_status(traced(ack)).await }
+        })
+        .await;
+        let response = match result {
+            Ok((response, endpoint)) => {
+                info!("Response ACK from requesting relay {}={:?}", endpoint.address(), response);
+                let response = Ok(response);
+                metrics().record_callback_result(&requesting_relay, &response);
+                response
             }
-        }
+            Err(e) => {
+                warn!("Failed to send subscription status to requesting relay {}: {:?}", requesting_relay, e);
+                metrics().record_callback_failure(&requesting_relay);
+                Err(Status::unavailable(format!("{:?}", e)))
+            }
+        };
         if unsubscribe {
-            println!("\nUnsubscribe Operation, deleting entry from remote_db\n");
+            info!("Unsubscribe Operation, deleting entry from remote_db");
             match response {
                 Ok(response_ack) => {
-                    println!("Response ACK from relay={:?}\n", ack);
+                    info!("Response ACK from relay={:?}", ack);
                     match ack::Status::from_i32(response_ack.into_inner().status) {
                         Some(status) => match status {
                             ack::Status::Ok => {
@@ -349,28 +419,28 @@ fn spawn_send_subscription_status(
                                 let db_result = remote_db.unset::<EventSubscription>(event_sub_key);
                                 match db_result {
                                     Ok(old_state) => {
-                                        println!("Removed EventSubscription from database: {:?}", old_state);
+                                        info!("Removed EventSubscription from database: {:?}", Redacted(&old_state));
                                     },
                                     Err(e) => {
-                                        println!("EventSubscription Request not found. Error: {:?}", e);
+                                        warn!("EventSubscription Request not found. Error: {:?}", e);
                                     }
                                 }
                             },
                             ack::Status::Error => {
-                                println!("Error from relay: {}", ack.message)
+                                warn!("Error from relay: {}", ack.message)
                             }
                         }
                         None => {
-                            println!("Error: No Ack Status from relay")
+                            warn!("Error: No Ack Status from relay")
                         }
                     }
                 }
                 Err(_e) => {
-                    println!("Error: error connecting to relay");
+                    warn!("Error: error connecting to relay");
                 }
             }
         }
-    });
+    }.in_current_span());
 }
 
 fn send_subscription_status_helper(
@@ -10,12 +10,19 @@ use weaverpb::common::events::{EventSubscription, EventStates, EventState, Event
 use weaverpb::driver::driver::driver_communication_client::DriverCommunicationClient;
 
 use crate::db::Database;
-use crate::services::types::{Driver, Network};
+use crate::driver_pool::call_driver;
+use crate::services::types::{Driver, DriverEndpoint};
 use crate::error::Error;
+use crate::relay_config::RelayConfig;
+use crate::grpc_options::{GrpcOptions, WithGrpcOptions};
+use crate::logging::Redacted;
+use crate::metrics::metrics;
+use crate::relay_proto::LocationSegment;
+use crate::trace_context::traced;
 
-use config;
 use tonic::transport::{Certificate, Channel, ClientTlsConfig};
 use std::fs;
+use tracing::{debug, info, warn};
 
 // Locally scoped function to update request status in db. This function is
 // called for the first time after an Ack is received from the remote relay.
@@ -115,7 +122,7 @@ pub fn update_event_subscription_status(
                 match message.find(driver_sub_exists_error_without_args) {
                     Some(_index) => {
                         let old_request_id = *(message.split(driver_sub_exists_error_without_args).collect::<Vec<&str>>().last().unwrap());
-                        println!("Adding event publication spec to existing EventSubscriptionState. Extracted request id from message: {}", old_request_id.to_string());
+                        info!("Adding event publication spec to existing EventSubscriptionState. Extracted request id from message: {}", old_request_id.to_string());
 
                         let old_event_sub_key = get_event_subscription_key(old_request_id.to_string());
                         let mut existing_event_sub_state = db.get::<EventSubscriptionState>(old_event_sub_key.to_string())
@@ -177,53 +184,48 @@ pub fn update_event_subscription_status(
             // Panic if this fails, atm the panic is just logged by the tokio runtime
             db.set(&event_sub_key.to_string(), &target)
                 .expect("Failed to insert into DB");
-            println!("Successfully written EventSubscriptionState to database");
-            println!("{:?}\n", db.get::<EventSubscriptionState>(event_sub_key.to_string()).unwrap())
+            info!("Successfully written EventSubscriptionState to database");
+            debug!("{:?}", db.get::<EventSubscriptionState>(event_sub_key.to_string()).unwrap())
         },
         Err(e) => {
-            println!("EventSubscription Request not found. Error: {:?}", e);
+            warn!("EventSubscription Request not found. Error: {:?}", e);
         },
     }
 }
 
 pub fn get_driver(
     network_id: String,
-    conf: config::Config,
+    conf: RelayConfig,
 ) -> Result<Driver, Error> {
-    // get the driver type from the networks map
-    let networks_table = conf
-        .get_table("networks")
-        .map_err(|e| Error::Simple(format!("Unable to find networks table. Error: {:?}", e)))?;
-    let network_table = networks_table
-        .get::<String>(&network_id.to_string())
+    // get the network's driver name from the networks map
+    let network = conf
+        .networks
+        .get(&network_id.to_string())
         .ok_or(Error::Simple(format!(
             "Unable to find Network_id \"{}\" in config",
             network_id.to_string()
         )))?;
-    let network_type = network_table
-        .clone()
-        .try_into::<Network>()
-        .expect("Error in config file networks table")
-        .clone();
     // get the driver host:port from the drivers map
-    let drivers_table = conf
-        .get_table("drivers")
-        .map_err(|e| Error::Simple(format!("Unable to find driver table. Error: {:?}", e)))?;
-    let driver_table = drivers_table
-        .get::<String>(&network_type.network)
+    let driver_info = conf
+        .drivers
+        .get(network.driver_name())
         .ok_or(Error::Simple(format!(
             "Unable to find driver port for network: {}",
             network_id.to_string()
         )))?;
-    let driver_info = driver_table
-        .clone()
-        .try_into::<Driver>()
-        .expect("Error in config file drivers table");
-    return Ok(driver_info);
+    Ok(driver_info.clone())
+}
+
+/// Name of the driver serving a network, as used in `[drivers]`.
+pub fn get_driver_name(network_id: &str, conf: &RelayConfig) -> String {
+    conf.networks
+        .get(network_id)
+        .map_or(network_id.to_string(), |network| network.driver_name().to_string())
 }
 
 pub async fn get_driver_client(
-    driver_info: Driver,
+    driver_info: &DriverEndpoint,
+    grpc_options: &GrpcOptions,
 ) -> Result<DriverCommunicationClient<Channel>, Error> {
     let port = driver_info.port.to_string();
     let hostname = driver_info.hostname.to_string();
@@ -239,42 +241,71 @@ pub async fn get_driver_client(
             .ca_certificate(ca)
             .domain_name(hostname);
 
-        let channel = Channel::from_shared(driver_address).unwrap()
+        // An unreachable driver instance is an error, so that the next instance can be tried.
+        let channel = Channel::from_shared(driver_address)
+            .map_err(|e| Error::Simple(format!("Invalid driver address: {}", e)))?
             .tls_config(tls)?
             .connect()
-            .await
-            .unwrap();
+            .await?;
 
         client = DriverCommunicationClient::new(channel);
     } else {
         let driver_address = format!("http://{}:{}", hostname, port);
         client = DriverCommunicationClient::connect(driver_address).await?;
     }
-    return Ok(client)
+    Ok(client.with_grpc_options(grpc_options))
 }
 
 
+/// Connects to an endpoint of another relay, over TLS if the relay uses it.
+pub async fn get_relay_channel(relay: &LocationSegment) -> Result<Channel, Error> {
+    let channel = if relay.tls {
+        let pem = tokio::fs::read(&relay.tlsca_cert_path).await?;
+        let tls = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(pem))
+            .domain_name(relay.hostname.to_string());
+        Channel::from_shared(format!("https://{}", relay.address()))
+            .map_err(|e| Error::Simple(format!("Invalid relay endpoint: {}", e)))?
+            .tls_config(tls)?
+            .connect()
+            .await?
+    } else {
+        Channel::from_shared(format!("http://{}", relay.address()))
+            .map_err(|e| Error::Simple(format!("Invalid relay endpoint: {}", e)))?
+            .connect()
+            .await?
+    };
+    Ok(channel)
+}
+
 pub async fn driver_sign_subscription_helper(
     event_subscription: EventSubscription,
     request_id: String,
     driver_id: String,
-    conf: config::Config,
+    conf: RelayConfig,
 ) -> Result<Query, Error> {
     let result = get_driver(driver_id.to_string(), conf.clone());
     match result {
         Ok(driver_info) => {
-            let client = get_driver_client(driver_info).await?;
-            println!("Sending Sign EventSubscription Request to driver: {:?}", event_subscription.clone());
-            let signed_query = client
-                .clone()
-                .request_signed_event_subscription_query(event_subscription)
-                .await?
-                .into_inner();
-            if signed_query.clone().request_id.to_string() == request_id.to_string() {
-                println!("Signed Query Response from driver={:?}\n", signed_query);
-                return Ok(signed_query)
+            let result = async {
+                debug!("Sending Sign EventSubscription Request to driver: {:?}", Redacted(&event_subscription));
+                let driver_name = get_driver_name(&driver_id, &conf);
+                let (signed_query, _) = call_driver(&driver_name, &driver_info, &conf.grpc, |mut client| {
+                    let event_subscription = event_subscription.clone();
+                    async move { client.request_signed_event_subscription_query(traced(event_subscription)).await }
+                })
+                .await?;
+                let signed_query = signed_query.into_inner();
+                if signed_query.request_id == request_id {
+                    debug!("Signed Query Response from driver={:?}", Redacted(&signed_query));
+                    return Ok(signed_query)
+                }
+                Err(Error::Simple(format!("Error while requesting signature from driver: got the signed query of request {} instead", signed_query.request_id)))
+            }.await;
+            if result.is_err() {
+                metrics().record_driver_error(&get_driver_name(&driver_id, &conf));
             }
-            Err(Error::Simple(format!("Error while requesting signature from driver: {:?}", signed_query)))
+            result
         },
         Err(e) => Err(e),
     }
@@ -305,6 +336,8 @@ pub fn update_event_state(
                         status: new_status as i32,
                         request_id: request_id.to_string(),
                         state: fetched_event_state.state.expect("No State found").state,
+                        relay_path: vec![],
+                        callback: None,
                     };
                     let new_event_state: EventState = EventState {
                         state: Some(new_request_state),
@@ -320,10 +353,10 @@ pub fn update_event_state(
             // Panic if this fails, atm the panic is just logged by the tokio runtime
             db.set(&event_publish_key.to_string(), &updated_event_states)
                 .expect("Failed to insert into DB");
-            println!("Successfully updated EventStates in database");
+            info!("Successfully updated EventStates in database");
         },
         Err(e) => {
-            println!("EventStates not found. Error: {:?}", e);
+            warn!("EventStates not found. Error: {:?}", e);
         },
     }
 }
@@ -336,9 +369,12 @@ pub fn try_mark_request_state_deleted(state: RequestState, request_id: String, d
             status: request_state::Status::Deleted as i32,
             request_id: request_id.to_string(),
             state: state.state,
+            relay_path: state.relay_path,
+            callback: state.callback,
         };
         db.set(&request_id, &deleted_request_state)
             .expect("RequestState Delete: Failed to insert into DB");
+        metrics().record_request_state(deleted_request_state.status);
     }
 }
 
@@ -349,6 +385,8 @@ pub fn mark_event_states_deleted(fetched_event_states: EventStates, request_id:
             status: request_state::Status::Deleted as i32,
             request_id: request_id.to_string(),
             state: fetched_event_state.state.expect("No State found").state,
+            relay_path: vec![],
+            callback: None,
         };
         let deleted_event_state = EventState {
             state: Some(deleted_request_state),
@@ -400,7 +438,7 @@ pub fn delete_event_pub_spec(
             .expect("Failed to insert into DB");
 
         del_event_pub_spec = updated_state.clone().event_publication_specs.first().unwrap().clone();
-        println!("Removed EventSubscriptionState from database: {:?}", updated_state);
+        debug!("Removed EventSubscriptionState from database: {:?}", updated_state);
         event_sub_key = get_event_subscription_key(updated_state.publishing_request_id.to_string());
         event_sub_state = db.get::<EventSubscriptionState>(event_sub_key.to_string())
             .expect(&format!("No EventSubscriptionState found in DB for request_id provided {}", updated_state.publishing_request_id.to_string()));
@@ -422,7 +460,7 @@ pub fn delete_event_pub_spec(
     }
     db.set(&event_sub_key.to_string(), &event_sub_state)
         .expect("Failed to insert into DB");
-    println!("Successfully deleted Event Publication from existing EventSubscriptionState from DB");
+    info!("Successfully deleted Event Publication from existing EventSubscriptionState from DB");
     
     return 0;
 }
@@ -432,4 +470,19 @@ pub fn get_event_subscription_key(request_id: String) -> String {
 }
 pub fn get_event_publication_key(request_id: String) -> String {
     return format!("event_pub_{}", request_id);
-}
\ No newline at end of file
+}
+pub fn get_forwarded_query_key(request_id: String) -> String {
+    format!("forwarded_{}", request_id)
+}
+pub fn get_batch_key(batch_id: String) -> String {
+    format!("batch_{}", batch_id)
+}
+pub fn get_cancelled_query_key(request_id: String) -> String {
+    format!("cancelled_{}", request_id)
+}
+pub fn get_sent_query_key(request_id: String) -> String {
+    format!("sent_{}", request_id)
+}
+pub fn get_callback_key(request_id: String) -> String {
+    format!("callback_{}", request_id)
+}
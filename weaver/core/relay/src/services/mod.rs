@@ -6,5 +6,6 @@ pub mod data_transfer_service;
 pub mod network_service;
 pub mod event_subscribe_service;
 pub mod event_publish_service;
+pub mod admin_service;
 pub mod helpers;
 pub mod types;
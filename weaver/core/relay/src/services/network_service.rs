@@ -8,25 +8,43 @@ use weaverpb::common::query::Query;
 use weaverpb::common::state::{request_state, RequestState};
 use weaverpb::common::events::{EventSubscription, event_subscription_state, EventSubscriptionState, EventSubOperation, event_publication, EventPublication, EventStates};
 use weaverpb::networks::networks::network_server::Network;
-use weaverpb::networks::networks::{DbName, GetStateMessage, NetworkQuery, RelayDatabase, NetworkEventSubscription, NetworkEventUnsubscription};
+use weaverpb::networks::networks::{BatchAck, BatchState, CancelRequestMessage, DbName, GetBatchStateMessage, GetStateMessage, NetworkQuery, NetworkQueryBatch, NetworkQuerySync, RelayDatabase, NetworkEventSubscription, NetworkEventUnsubscription};
 use weaverpb::relay::datatransfer::data_transfer_client::DataTransferClient;
+use weaverpb::relay::datatransfer::{CancelQuery, QueryBatch};
 use weaverpb::relay::events::event_subscribe_client::EventSubscribeClient;
 use crate::relay_proto::{parse_address, LocationSegment};
 // Internal modules
+use crate::callbacks::{deliver_final_state, forget_callback, remember_callback};
 use crate::db::Database;
-use crate::services::helpers::{update_event_subscription_status, driver_sign_subscription_helper, try_mark_request_state_deleted, mark_event_states_deleted, delete_event_pub_spec, get_event_publication_key, get_event_subscription_key};
+use crate::discovery::RelayDiscovery;
+use crate::error::Error;
+use crate::relay_config::RelayConfig;
+use crate::grpc_options::{GrpcOptions, WithGrpcOptions};
+use crate::logging::{record_request_id, Redacted, ViewData};
+use crate::metrics::metrics;
+use crate::relay_pool::call_relay;
+use crate::result_cache::result_cache;
+use crate::routing::next_hop;
+use crate::services::helpers::{update_event_subscription_status, driver_sign_subscription_helper, try_mark_request_state_deleted, get_batch_key, get_sent_query_key, mark_event_states_deleted, delete_event_pub_spec, get_event_publication_key, get_event_subscription_key};
+use crate::trace_context::traced;
+use crate::shutdown::{spawn_tracked, PendingWork};
+use crate::state_watch::state_watch;
 
 // External modules
-use config;
 use sled::open;
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::RwLock;
 use tonic::{Code, Request, Response, Status};
 use uuid::Uuid;
+use tracing::{debug, error, info, warn, Instrument};
 
-use tonic::transport::{Certificate, Channel, ClientTlsConfig};
 
 pub struct NetworkService {
-    pub config_lock: RwLock<config::Config>,
+    pub config_lock: Arc<RwLock<RelayConfig>>,
+    pub discovery: Arc<RelayDiscovery>,
 }
 
 /// NetworkService handles logic related to communication between a requesting relay and a network
@@ -37,34 +55,18 @@ impl Network for NetworkService {
         &self,
         request: Request<GetStateMessage>,
     ) -> Result<Response<RequestState>, Status> {
-        println!("\nReceived GetState request from network: {:?}", request);
+        info!("Received GetState request from network: {:?}", request.get_ref());
         let conf = self.config_lock.read().await.clone();
         let db = Database {
-            db_path: conf.get_str("db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
         let request_id = request.into_inner().request_id;
+        record_request_id(&request_id);
         let result = db.get::<RequestState>(request_id.to_string());
         match result {
-            Ok(request_state) => {
-                println!("Sending back RequestState to network: Request ID = {:?}, Status = {:?}",
-                         request_state.request_id,
-                         request_state.status
-                         );
-                match request_state.state.as_ref() {
-                    Some(state) => {
-                        // Because already state is passed to client, deleting the state if status is completed or error
-                        try_mark_request_state_deleted(request_state.clone(), request_id.to_string(), db);
-                        match state {
-                            request_state::State::View(v) => println!("View Meta: {:?}, View Data: {:?}", v.meta, base64::encode(&v.data)),
-                            request_state::State::Error(e) => println!("Error: {:?}", e),
-                        }
-                    },
-                    None => {},
-                }
-                return Ok(Response::new(request_state));
-            },
+            Ok(request_state) => Ok(Response::new(state_reply(request_state, db, &conf))),
             Err(e) => Err(Status::new(
                 Code::NotFound,
                 format!("Request not found. Error: {:?}", e),
@@ -72,6 +74,84 @@ impl Network for NetworkService {
         }
     }
 
+    /// cancel_request is run on the client to abandon a pending request. The
+    /// request is marked as cancelled, and the remote relay is told to drop it.
+    async fn cancel_request(&self, request: Request<CancelRequestMessage>) -> Result<Response<Ack>, Status> {
+        info!("Received CancelRequest from network: {:?}", request.get_ref());
+        let conf = self.config_lock.read().await.clone();
+        let db = Database {
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        let request_id = request.into_inner().request_id;
+        record_request_id(&request_id);
+        let reply = match mark_request_cancelled(&db, &request_id) {
+            Ok(sent_to) => {
+                result_cache().forget(&request_id);
+                if let Some((location, network_id)) = sent_to {
+                    spawn_send_cancellation(conf, self.discovery.clone(), request_id.to_string(), location, network_id);
+                }
+                Ack {
+                    status: ack::Status::Ok as i32,
+                    request_id,
+                    message: "".to_string(),
+                }
+            }
+            Err(e) => {
+                warn!("Unable to cancel request {}: {:?}", request_id, e);
+                Ack {
+                    status: ack::Status::Error as i32,
+                    request_id,
+                    message: format!("Error: {:?}", e),
+                }
+            }
+        };
+        info!("Sending Ack back to network: {:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    /// request_state_sync is run on the client to query the requesting relay
+    /// and wait for the result in one call. The query goes through the same
+    /// steps as with request_state, then the relay waits until the state is
+    /// final or the timeout is reached, and answers as get_state does.
+    async fn request_state_sync(&self, request: Request<NetworkQuerySync>) -> Result<Response<RequestState>, Status> {
+        info!("Received RequestStateSync request from {:?}", request.remote_addr());
+        let message = request.into_inner();
+        let network_query = message
+            .query
+            .ok_or_else(|| Status::new(Code::InvalidArgument, "A query is required".to_string()))?;
+        let conf = self.config_lock.read().await.clone();
+        let timeout_msec = match message.timeout_msec as u64 {
+            0 => conf.max_sync_wait_msec,
+            timeout_msec => timeout_msec.min(conf.max_sync_wait_msec),
+        };
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(timeout_msec);
+        let ack = self.request_state(Request::new(network_query)).await?.into_inner();
+        if ack.status != ack::Status::Ok as i32 {
+            return Err(Status::new(Code::Aborted, format!("Query not sent: {}", ack.message)));
+        }
+        let db = Database {
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        let watcher = state_watch().watch(&ack.request_id);
+        let request_state = loop {
+            let request_state = db
+                .get::<RequestState>(ack.request_id.to_string())
+                .map_err(|e| Status::new(Code::NotFound, format!("Request not found. Error: {:?}", e)))?;
+            if is_final(&request_state) {
+                break request_state;
+            }
+            if tokio::time::timeout_at(deadline, watcher.changed()).await.is_err() {
+                info!("Request {} is not done after {} ms, returning its current state", ack.request_id, timeout_msec);
+                break request_state;
+            }
+        };
+        Ok(Response::new(state_reply(request_state, db, &conf)))
+    }
+
     // NOTE: This is just for debugging
     async fn request_database(
         &self,
@@ -93,7 +173,7 @@ impl Network for NetworkService {
                     format!("{:?}", decoded_key),
                     format!("{:?}", decoded_result),
                 );
-                println!("Event Sub {:?} => {:?}", decoded_key, decoded_result);
+                debug!("Event Sub {:?} => {:?}", decoded_key, decoded_result);
             } else if decoded_key.to_string().contains(&"event_pub".to_string()) {
                 let decoded_result: Result<EventStates, bincode::Error> =
                     bincode::deserialize(&key.1[..]);
@@ -101,7 +181,7 @@ impl Network for NetworkService {
                     format!("{:?}", decoded_key),
                     format!("{:?}", decoded_result),
                 );
-                println!("Event Pub {:?} => {:?}", decoded_key, decoded_result);
+                debug!("Event Pub {:?} => {:?}", decoded_key, decoded_result.as_ref().map(Redacted));
             } else {
                 let decoded_result: Result<RequestState, bincode::Error> =
                     bincode::deserialize(&key.1[..]);
@@ -109,7 +189,7 @@ impl Network for NetworkService {
                     format!("{:?}", decoded_key),
                     format!("{:?}", decoded_result),
                 );
-                println!("RequestState {:?} => {:?}", decoded_key, decoded_result);
+                debug!("RequestState {:?} => {:?}", decoded_key, decoded_result.as_ref().map(Redacted));
             }
             curr_key = req_db.get_gt(key.0).unwrap();
         }
@@ -120,36 +200,44 @@ impl Network for NetworkService {
     /// Since this request is async w.r.t the network, the request info/state machine is
     /// stored in a db on the requesting relay (status polled using get_state)
     async fn request_state(&self, request: Request<NetworkQuery>) -> Result<Response<Ack>, Status> {
-        println!(
+        info!(
             "Got a NetworkQuery request from {:?} - {:?}",
             request.remote_addr(),
-            request
+            Redacted(request.get_ref())
         );
         let conf = self.config_lock.read().await.clone();
         // Database access/storage
         let db = Database {
-            db_path: conf.get_str("db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
 
         let request_id = Uuid::new_v4();
-        // Initial request state stored in DB.
-        let target: RequestState = RequestState {
+        record_request_id(&request_id.to_string());
+        let network_query = request.into_inner().clone();
+        // Initial request state stored in DB, or the final one for a query answered from the result cache.
+        let target: RequestState = cached_state(&network_query, &request_id.to_string(), &conf).unwrap_or(RequestState {
             status: request_state::Status::PendingAck as i32,
             request_id: request_id.to_string(),
             state: None,
-        };
-        let message_insert = db.set(&request_id.to_string(), &target);
+            relay_path: vec![],
+            callback: None,
+        });
+        let message_insert = remember_callback(&db, &request_id.to_string(), network_query.callback.as_ref())
+            .and_then(|_| db.set(&request_id.to_string(), &target));
         // Kept this as a match as the error case returns an Ok.
         match message_insert {
-            Ok(_) => println!(
-                "Successfully stored NetworkQuery in db with request_id: {}",
-                request_id.to_string()
-            ),
+            Ok(_) => {
+                metrics().record_request_state(target.status);
+                info!(
+                    "Successfully stored NetworkQuery in db with request_id: {}",
+                    request_id.to_string()
+                )
+            }
             Err(e) => {
                 // Internal failure of sled. Send Error response
-                println!(
+                error!(
                     "Error storing NetworkQuery in db for request_id: {}",
                     request_id.to_string()
                 );
@@ -158,12 +246,21 @@ impl Network for NetworkService {
                     request_id: request_id.to_string(),
                     message: format!("{:?}", e),
                 }));
-                println!("Sending Ack back to network: {:?}\n", reply);
+                info!("Sending Ack back to network: {:?}", reply);
                 return reply;
             }
         }
+        if target.status == request_state::Status::Completed as i32 {
+            deliver_final_state(&conf, &request_id.to_string());
+            let reply = Ack {
+                status: ack::Status::Ok as i32,
+                request_id: request_id.to_string(),
+                message: "".to_string(),
+            };
+            info!("Sending Ack back to network: {:?}", reply);
+            return Ok(Response::new(reply));
+        }
 
-        let network_query = request.into_inner().clone();
         let parsed_address = parse_address(network_query.address.to_string());
         match parsed_address {
             Ok(address) => {
@@ -171,10 +268,11 @@ impl Network for NetworkService {
                 // Spawns a child process to handle sending request
                 spawn_send_request(
                     conf,
+                    self.discovery.clone(),
                     network_query,
                     request_id.to_string(),
-                    address.location.hostname.to_string(),
-                    address.location.port.to_string()
+                    address.location,
+                    address.network_id,
                 );
                 // Send Ack back to network while request is happening in a thread
                 let reply = Ack {
@@ -182,38 +280,205 @@ impl Network for NetworkService {
                     request_id: request_id.to_string(),
                     message: "".to_string(),
                 };
-                println!("Sending Ack back to network: {:?}\n", reply);
+                info!("Sending Ack back to network: {:?}", reply);
                 Ok(Response::new(reply))
             }
             Err(e) => {
-                println!("Invalid Address");
+                warn!("{}", e);
                 let reply = Ack {
                     status: ack::Status::Error as i32,
                     request_id: request_id.to_string(),
-                    message: format!("Error: {:?}", e),
+                    message: format!("Error: {}", e),
                 };
-                println!("Sending Ack back to network: {:?}\n", reply);
+                info!("Sending Ack back to network: {:?}", reply);
                 Ok(Response::new(reply))
             }
         }
     }
     
+    /// request_state_batch is run on the client to query the requesting relay for
+    /// many states at once. Each query gets a request id of its own, and the
+    /// queries of the same remote relay are sent to it in one call. The states
+    /// are polled all at once using get_batch_state, or one by one using get_state.
+    async fn request_state_batch(&self, request: Request<NetworkQueryBatch>) -> Result<Response<BatchAck>, Status> {
+        info!(
+            "Got a NetworkQueryBatch request from {:?} with {} queries",
+            request.remote_addr(),
+            request.get_ref().queries.len()
+        );
+        let conf = self.config_lock.read().await.clone();
+        // Every query gets an ack in the reply, which has to fit in one message.
+        let reply_len = batch_reply_len(request.get_ref().queries.len());
+        if reply_len > conf.grpc.max_message_size() {
+            return Err(Status::new(
+                Code::ResourceExhausted,
+                format!(
+                    "A batch of {} queries is too large: the reply needs at least {} bytes, more than the relay's limit of {} bytes. Split the batch",
+                    request.get_ref().queries.len(),
+                    reply_len,
+                    conf.grpc.max_message_size()
+                ),
+            ));
+        }
+        // Database access/storage
+        let db = Database {
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        let batch_id = Uuid::new_v4().to_string();
+        record_request_id(&batch_id);
+        let mut request_ids = Vec::new();
+        let mut acks = Vec::new();
+        // Queries by the remote relay they go to, keyed by its location or route.
+        let mut groups: HashMap<String, (LocationSegment, String, Vec<(NetworkQuery, String)>)> = HashMap::new();
+        for network_query in request.into_inner().queries {
+            debug!("Batched query: {:?}", Redacted(&network_query));
+            let request_id = Uuid::new_v4().to_string();
+            request_ids.push(request_id.to_string());
+            let parsed_address = parse_address(network_query.address.to_string());
+            let target = RequestState {
+                status: request_state::Status::PendingAck as i32,
+                request_id: request_id.to_string(),
+                state: None,
+                relay_path: vec![],
+                callback: None,
+            };
+            let cached = cached_state(&network_query, &request_id, &conf);
+            let target = match &parsed_address {
+                Ok(_) => cached.unwrap_or(target),
+                Err(e) => {
+                    warn!("{}", e);
+                    RequestState {
+                        status: request_state::Status::Error as i32,
+                        state: Some(request_state::State::Error(format!("Error: {}", e))),
+                        ..target
+                    }
+                }
+            };
+            if let Err(e) = remember_callback(&db, &request_id, network_query.callback.as_ref()).and_then(|_| db.set(&request_id, &target)) {
+                error!("Error storing NetworkQuery in db for request_id: {}", request_id);
+                acks.push(Ack {
+                    status: ack::Status::Error as i32,
+                    request_id,
+                    message: format!("{:?}", e),
+                });
+                continue;
+            }
+            metrics().record_request_state(target.status);
+            if target.status != request_state::Status::PendingAck as i32 {
+                deliver_final_state(&conf, &request_id);
+            }
+            let ack = match parsed_address {
+                Ok(_) if target.status == request_state::Status::Completed as i32 => Ack {
+                    status: ack::Status::Ok as i32,
+                    request_id,
+                    message: "".to_string(),
+                },
+                Ok(address) => {
+                    let key = match next_hop(&address.network_id, &conf) {
+                        Some(next_hop) => format!("relay {}", next_hop),
+                        None if address.location.hostname.is_empty() => format!("network {}", address.network_id),
+                        None => format!("{} {}", address.location.address().to_lowercase(), address.location.tls),
+                    };
+                    groups
+                        .entry(key)
+                        .or_insert_with(|| (address.location, address.network_id, vec![]))
+                        .2
+                        .push((network_query, request_id.to_string()));
+                    Ack {
+                        status: ack::Status::Ok as i32,
+                        request_id,
+                        message: "".to_string(),
+                    }
+                }
+                Err(e) => Ack {
+                    status: ack::Status::Error as i32,
+                    request_id,
+                    message: format!("Error: {}", e),
+                },
+            };
+            acks.push(ack);
+        }
+        if let Err(e) = db.set(&get_batch_key(batch_id.to_string()), &request_ids) {
+            error!("Error storing batch {} in db", batch_id);
+            return Err(Status::internal(format!("Unable to store the batch. Error: {:?}", e)));
+        }
+        info!("Stored batch {} with {} queries for {} remote relays", batch_id, request_ids.len(), groups.len());
+        for (_, (location, network_id, queries)) in groups {
+            spawn_send_batch(conf.clone(), self.discovery.clone(), queries, location, network_id);
+        }
+        Ok(Response::new(BatchAck { batch_id, acks }))
+    }
+
+    // Used by client/network to get the RequestStates of a batch from the local relay DB.
+    async fn get_batch_state(
+        &self,
+        request: Request<GetBatchStateMessage>,
+    ) -> Result<Response<BatchState>, Status> {
+        info!("Received GetBatchState request from network: {:?}", request.get_ref());
+        let conf = self.config_lock.read().await.clone();
+        let db = Database {
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        let batch_id = request.into_inner().batch_id;
+        record_request_id(&batch_id);
+        let request_ids = db
+            .get::<Vec<String>>(get_batch_key(batch_id.to_string()))
+            .map_err(|e| Status::new(Code::NotFound, format!("Batch not found. Error: {:?}", e)))?;
+        let states: Vec<RequestState> = request_ids
+            .into_iter()
+            .map(|request_id| {
+                db.get::<RequestState>(request_id.to_string()).unwrap_or_else(|e| RequestState {
+                    status: request_state::Status::Error as i32,
+                    request_id,
+                    state: Some(request_state::State::Error(format!("Request not found. Error: {:?}", e))),
+                    relay_path: vec![],
+                    callback: None,
+                })
+            })
+            .collect();
+        let batch_state = BatchState { batch_id, states };
+        // States that cannot be sent together within the relay's message size limit are left to be fetched one by one.
+        if let Err(reason) = conf.grpc.clone().check_encoding_size(batch_state.encoded_len()) {
+            return Err(Status::new(
+                Code::ResourceExhausted,
+                format!("Batch states are too large: {}. Fetch them one by one with GetState", reason),
+            ));
+        }
+        for request_state in batch_state.states.iter() {
+            // Because already state is passed to client, deleting the state if status is completed or error
+            try_mark_request_state_deleted(request_state.clone(), request_state.request_id.to_string(), db.clone());
+        }
+        // Once every state of the batch has been passed to the client, the batch itself is done with.
+        if batch_state.states.iter().all(is_final) {
+            if let Err(e) = db.remove(get_batch_key(batch_state.batch_id.to_string())) {
+                warn!("Unable to remove finished batch {}: {:?}", batch_state.batch_id, e);
+            }
+        }
+        info!("Sending back {} RequestStates of batch {} to network", batch_state.states.len(), batch_state.batch_id);
+        Ok(Response::new(batch_state))
+    }
+
     // Subscribe Event Endpoints
     async fn subscribe_event(&self, request: Request<NetworkEventSubscription>) -> Result<Response<Ack>, Status> {
-        println!(
+        info!(
             "Got a Network Event Subscription request from {:?} - {:?}",
             request.remote_addr(),
-            request
+            Redacted(request.get_ref())
         );
         let conf = self.config_lock.read().await.clone();
         // Database access/storage
         let db = Database {
-            db_path: conf.get_str("db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
 
         let request_id = Uuid::new_v4();
+        record_request_id(&request_id.to_string());
         let network_event_subscription = request.into_inner().clone();
         
         let mut event_publication_specs: Vec<EventPublication> = Vec::new();
@@ -231,7 +496,7 @@ impl Network for NetworkService {
         
         // Create EventSubscription
         let network_query = network_event_subscription.query.clone().expect("No query passed with NetworkEventSubscription request");
-        let relay_name = conf.get_str("name").unwrap();
+        let relay_name = conf.name.to_string();
         let query: Query = Query {
             policy: network_query.policy,
             address: network_query.address,
@@ -243,6 +508,7 @@ impl Network for NetworkService {
             nonce: network_query.nonce,
             request_id: request_id.to_string(),
             confidential: network_query.confidential,
+            relay_path: vec![],
         };
         let event_subscription: EventSubscription = EventSubscription {
             event_matcher: network_event_subscription.event_matcher,
@@ -251,21 +517,23 @@ impl Network for NetworkService {
         };
         let event_publication_spec = network_event_subscription.event_publication_spec.clone().expect("No Event Publication Specification passed with NetworkEventSubscription request");
 
-        return event_subscription_helper(event_subscription, event_publication_spec, target, request_id.to_string(), db, conf).await;
+        return event_subscription_helper(event_subscription, event_publication_spec, target, request_id.to_string(), db, conf, self.discovery.clone()).await;
     }
     
     async fn get_event_subscription_state(
         &self,
         request: Request<GetStateMessage>,
     ) -> Result<Response<EventSubscriptionState>, Status> {
-        println!("\nReceived GetEventSubscriptionState request from network: {:?}", request);
+        info!("Received GetEventSubscriptionState request from network: {:?}", request.get_ref());
         let conf = self.config_lock.read().await.clone();
         let db = Database {
-            db_path: conf.get_str("db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
-        let event_sub_key = get_event_subscription_key(request.into_inner().request_id);
+        let request_id = request.into_inner().request_id;
+        record_request_id(&request_id);
+        let event_sub_key = get_event_subscription_key(request_id);
         let result = db.get::<EventSubscriptionState>(event_sub_key.to_string());
         match result {
             Ok(fetched_event_sub_state) => {
@@ -275,10 +543,10 @@ impl Network for NetworkService {
                             let result = db.unset::<EventSubscriptionState>(event_sub_key.to_string());
                             match result {
                                 Ok(old_state) => {
-                                    println!("Removed EventSubscription from database: {:?}", old_state);
+                                    info!("Removed EventSubscription from database: {:?}", old_state);
                                 },
                                 Err(e) => {
-                                    println!("EventSubscription Request not found. Error: {:?}", e);
+                                    warn!("EventSubscription Request not found. Error: {:?}", e);
                                 }
                             }
                         },
@@ -287,7 +555,7 @@ impl Network for NetworkService {
                     None => {},
                 }
 
-                println!("Sending back EventSubscriptionState to network: Request ID = {:?}, Status = {:?}",
+                info!("Sending back EventSubscriptionState to network: Request ID = {:?}, Status = {:?}",
                          fetched_event_sub_state.request_id,
                          fetched_event_sub_state.status
                          );
@@ -301,30 +569,31 @@ impl Network for NetworkService {
     }
     // Unsubscribe Event Endpoints
     async fn unsubscribe_event(&self, request: Request<NetworkEventUnsubscription>) -> Result<Response<Ack>, Status> {
-        println!(
+        info!(
             "Got a Network Event Unubscription request from {:?} - {:?}",
             request.remote_addr(),
-            request
+            Redacted(request.get_ref())
         );
         let conf = self.config_lock.read().await.clone();
         // Database access/storage
         let db = Database {
-            db_path: conf.get_str("db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
         
         let net_event_sub = request.into_inner().clone();
         let network_event_subscription = net_event_sub.request.clone().expect("No network event subscription passed");
         let request_id = net_event_sub.request_id.to_string();
+        record_request_id(&request_id);
         let requested_unsub_pub_spec = network_event_subscription.event_publication_spec.clone().expect("No event publication spec provided for unsubscription request.");
         
         let delete_pub_spec_status = delete_event_pub_spec(
             request_id.to_string(), 
             requested_unsub_pub_spec, 
-            conf.get_str("db_path").unwrap().to_string(),
-            conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32
+            conf.db_path.to_string().to_string(),
+            conf.db_open_max_retries,
+            conf.db_open_retry_backoff_msec
         );
         
         if delete_pub_spec_status == 0 {
@@ -333,7 +602,7 @@ impl Network for NetworkService {
                 request_id: request_id.to_string(),
                 message: "Unsubscribed requested event publication specification.".to_string(),
             };
-            println!("Sending Ack back to network: {:?}\n", reply);
+            info!("Sending Ack back to network: {:?}", reply);
             Ok(Response::new(reply))
         } else if delete_pub_spec_status == 2 {
             let reply = Ack {
@@ -341,7 +610,7 @@ impl Network for NetworkService {
                 request_id: request_id.to_string(),
                 message: "Unsubscription request does not match existing subscription: Check event publication specification.".to_string(),
             };
-            println!("Sending Ack back to network: {:?}\n", reply);
+            info!("Sending Ack back to network: {:?}", reply);
             Ok(Response::new(reply))
         } else {
             let mut event_publication_specs: Vec<EventPublication> = Vec::new();
@@ -359,7 +628,7 @@ impl Network for NetworkService {
             
             // Create EventSubscription
             let network_query = network_event_subscription.query.clone().expect("No query passed with NetworkEventSubscription request");
-            let relay_name = conf.get_str("name").unwrap();
+            let relay_name = conf.name.to_string();
             let query: Query = Query {
                 policy: network_query.policy,
                 address: network_query.address,
@@ -371,6 +640,7 @@ impl Network for NetworkService {
                 nonce: network_query.nonce,
                 request_id: request_id.to_string(),
                 confidential: network_query.confidential,
+                relay_path: vec![],
             };
             let event_subscription: EventSubscription = EventSubscription {
                 event_matcher: network_event_subscription.event_matcher,
@@ -379,7 +649,7 @@ impl Network for NetworkService {
             };
             let event_publication_spec = network_event_subscription.event_publication_spec.clone().expect("No Event Publication Specification passed with NetworkEventSubscription request");
 
-            return event_subscription_helper(event_subscription, event_publication_spec, target, request_id.to_string(), db, conf).await;
+            return event_subscription_helper(event_subscription, event_publication_spec, target, request_id.to_string(), db, conf, self.discovery.clone()).await;
         }
     }
     
@@ -388,22 +658,23 @@ impl Network for NetworkService {
         &self,
         request: Request<GetStateMessage>,
     ) -> Result<Response<EventStates>, Status> {
-        println!("\nReceived GetEventStates request from network: {:?}", request);
+        info!("Received GetEventStates request from network: {:?}", request.get_ref());
         let conf = self.config_lock.read().await.clone();
         let db = Database {
-            db_path: conf.get_str("db_path").unwrap(),
-            db_open_max_retries: conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-            db_open_retry_backoff_msec: conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
         };
         let request_id = request.into_inner().request_id;
+        record_request_id(&request_id);
         let event_publish_key = get_event_publication_key(request_id.to_string());
         let result = db.get::<EventStates>(event_publish_key.to_string());
         match result {
             Ok(fetched_event_states) => {
                 mark_event_states_deleted(fetched_event_states.clone(), request_id.to_string(), event_publish_key.to_string(), db);
-                println!("Sending back EventStates to network: Request ID = {:?}: {:?}",
+                info!("Sending back EventStates to network: Request ID = {:?}: {:?}",
                          request_id.to_string(),
-                         fetched_event_states.clone()
+                         Redacted(&fetched_event_states)
                         );
                 return Ok(Response::new(fetched_event_states));
             },
@@ -421,7 +692,8 @@ async fn event_subscription_helper(
     target_status: EventSubscriptionState,
     request_id: String,
     db: Database,
-    conf: config::Config,
+    conf: RelayConfig,
+    discovery: Arc<RelayDiscovery>,
 ) -> Result<Response<Ack>, Status> {
     let event_subscription;
     
@@ -430,7 +702,7 @@ async fn event_subscription_helper(
         Some(data) => match data {
             event_publication::PublicationTarget::Ctx(ctx) => {
                 let driver_id = ctx.clone().driver_id.to_string();
-                println!("Requesting Driver {} to sign", driver_id.clone().to_string());
+                info!("Requesting Driver {} to sign", driver_id.clone().to_string());
                 let result = driver_sign_subscription_helper(
                     req_event_subscription.clone(),
                     request_id.to_string(),
@@ -446,30 +718,30 @@ async fn event_subscription_helper(
                         };
                     }
                     Err(e) => {
-                        println!("Driver did not signed the subscription request");
+                        warn!("Driver did not signed the subscription request");
                         let reply = Ack {
                             status: ack::Status::Error as i32,
                             request_id: request_id.to_string(),
                             message: format!("Error: {:?}", e),
                         };
-                        println!("Sending Ack back to network: {:?}\n", reply);
+                        info!("Sending Ack back to network: {:?}", reply);
                         return Ok(Response::new(reply))
                     }
                 }
             }
             event_publication::PublicationTarget::AppUrl(app_url) => {
-                println!("Registering for Client using App URL: {}", app_url.to_string());
+                info!("Registering for Client using App URL: {}", app_url.to_string());
                 event_subscription = req_event_subscription;
             }
         },
         None => {
-            println!("No Publication Target");
+            warn!("No Publication Target");
             let reply = Ack {
                 status: ack::Status::Error as i32,
                 request_id: request_id.to_string(),
                 message: format!("No Publication Target provided"),
             };
-            println!("Sending Ack back to network: {:?}\n", reply);
+            info!("Sending Ack back to network: {:?}", reply);
             return Ok(Response::new(reply))
         }
     };
@@ -478,13 +750,13 @@ async fn event_subscription_helper(
     let message_insert = db.set(&event_sub_key.to_string(), &target_status);
     // Kept this as a match as the error case returns an Ok.
     match message_insert {
-        Ok(_) => println!(
+        Ok(_) => info!(
             "Successfully stored EventSubscriptionState in db with request_id: {}",
             request_id.to_string()
         ),
         Err(e) => {
             // Internal failure of sled. Send Error response
-            println!(
+            error!(
                 "Error storing EventSubscriptionState in db for request_id: {}",
                 request_id.to_string()
             );
@@ -493,7 +765,7 @@ async fn event_subscription_helper(
                 request_id: request_id.to_string(),
                 message: format!("{:?}", e),
             }));
-            println!("Sending Ack back to network: {:?}\n", reply);
+            info!("Sending Ack back to network: {:?}", reply);
             return reply;
         }
     }
@@ -505,10 +777,11 @@ async fn event_subscription_helper(
             // Spawns a child process to handle sending request
             spawn_send_event_subscription_request(
                 conf,
+                discovery,
                 event_subscription,
                 request_id.to_string(),
-                address.location.hostname.to_string(),
-                address.location.port.to_string(),
+                address.location,
+                address.network_id,
             );
             // Send Ack back to network while request is happening in a thread
             let reply = Ack {
@@ -516,88 +789,232 @@ async fn event_subscription_helper(
                 request_id: request_id.to_string(),
                 message: "".to_string(),
             };
-            println!("Sending Ack back to network: {:?}\n", reply);
+            info!("Sending Ack back to network: {:?}", reply);
             Ok(Response::new(reply))
         }
         Err(e) => {
-            println!("Invalid Address");
+            warn!("{}", e);
             let reply = Ack {
                 status: ack::Status::Error as i32,
                 request_id: request_id.to_string(),
-                message: format!("Error: {:?}", e),
+                message: format!("Error: {}", e),
             };
-            println!("Sending Ack back to network: {:?}\n", reply);
+            info!("Sending Ack back to network: {:?}", reply);
             Ok(Response::new(reply))
         }
     }
 }
 
-// Sends a request to the remote relay
-fn spawn_send_request(
-    conf: config::Config,
-    network_query: NetworkQuery,
+// Size of the reply to a batch of `queries` queries with no error messages,
+// each ack carrying a request id as long as a generated one.
+fn batch_reply_len(queries: usize) -> usize {
+    let ack = Ack {
+        status: ack::Status::Error as i32,
+        request_id: Uuid::nil().to_string(),
+        message: "".to_string(),
+    };
+    let ack_len = 1 + prost::encoding::encoded_len_varint(ack.encoded_len() as u64) + ack.encoded_len();
+    BatchAck { batch_id: Uuid::nil().to_string(), acks: vec![] }.encoded_len() + queries * ack_len
+}
+
+// Marks a pending request as cancelled. Returns the location and network the
+// query was sent to, if it was.
+fn mark_request_cancelled(db: &Database, request_id: &str) -> Result<Option<(LocationSegment, String)>, Error> {
+    let request_state = db.get::<RequestState>(request_id.to_string())?;
+    let status = request_state::Status::try_from(request_state.status).ok();
+    if !matches!(status, Some(request_state::Status::PendingAck) | Some(request_state::Status::Pending)) {
+        return Err(Error::Simple(format!("Request {} is not pending, its status is {:?}", request_id, status)));
+    }
+    let cancelled_state = RequestState {
+        status: request_state::Status::Cancelled as i32,
+        request_id: request_id.to_string(),
+        state: Some(request_state::State::Error("Request cancelled by the network".to_string())),
+        relay_path: vec![],
+        callback: None,
+    };
+    db.set(&request_id.to_string(), &cancelled_state)?;
+    state_watch().notify(request_id);
+    forget_callback(db, request_id);
+    metrics().record_request_state(cancelled_state.status);
+    info!("Cancelled request {}", request_id);
+    Ok(db.unset::<(LocationSegment, String)>(get_sent_query_key(request_id.to_string())).ok())
+}
+
+// The reply to a network asking for the state of a request. Because the
+// state is passed to the network, a completed or failed state is marked as
+// deleted.
+fn state_reply(request_state: RequestState, db: Database, conf: &RelayConfig) -> RequestState {
+    let request_id = request_state.request_id.to_string();
+    // A state that cannot be sent within the relay's message size limit is reported as an error instead.
+    let request_state = match conf.grpc.clone().check_encoding_size(request_state.encoded_len()) {
+        Ok(()) => request_state,
+        Err(reason) => RequestState {
+            status: request_state::Status::Error as i32,
+            request_id: request_state.request_id,
+            state: Some(request_state::State::Error(format!("View payload is too large: {}", reason))),
+            relay_path: request_state.relay_path,
+            callback: request_state.callback,
+        },
+    };
+    info!("Sending back RequestState to network: Request ID = {:?}, Status = {:?}",
+             request_state.request_id,
+             request_state.status
+             );
+    if let Some(state) = request_state.state.as_ref() {
+        // Because already state is passed to client, deleting the state if status is completed or error
+        try_mark_request_state_deleted(request_state.clone(), request_id.to_string(), db);
+        match state {
+            request_state::State::View(v) => debug!("View Meta: {:?}, View Data: {}", v.meta, ViewData(&v.data)),
+            request_state::State::Error(e) => debug!("Error: {:?}", e),
+        }
+    }
+    request_state
+}
+
+// Whether a request is done, i.e. its state will not change anymore.
+fn is_final(request_state: &RequestState) -> bool {
+    matches!(
+        request_state::Status::try_from(request_state.status),
+        Ok(request_state::Status::Completed)
+            | Ok(request_state::Status::Error)
+            | Ok(request_state::Status::Cancelled)
+            | Ok(request_state::Status::Deleted)
+    )
+}
+
+// Tells the remote relay a query was sent to that the query was cancelled.
+pub fn spawn_send_cancellation(
+    conf: RelayConfig,
+    discovery: Arc<RelayDiscovery>,
     request_id: String,
-    relay_host: String,
-    relay_port: String,
+    location: LocationSegment,
+    network_id: String,
 ) {
-    println!("Sending Query to remote relay: {:?}:{:?}", relay_host, relay_port);
-    // Locally scoped function to update request status in db. This function is
-    // called for the first time after an Ack is received from the remote relay.
-    // A locally created RequestState with status Pending or Error is stored.
-    // When a response is received from the remote relay it will write the
-    // returned RequestState with status Completed or Error.
-    fn update_request_status(
-        curr_request_id: String,
-        new_status: request_state::Status,
-        curr_db_path: String,
-        db_open_max_retries: u32,
-        db_open_retry_backoff_msec: u32,
-        state: Option<request_state::State>,
-    ) {
-        let db = Database {
-            db_path: curr_db_path,
-            db_open_max_retries: db_open_max_retries,
-            db_open_retry_backoff_msec: db_open_retry_backoff_msec,
+    let work = PendingWork::SendCancellation {
+        request_id: request_id.to_string(),
+        location: location.clone(),
+        network_id: network_id.to_string(),
+    };
+    spawn_tracked(work, async move {
+        let resolved = match next_hop(&network_id, &conf) {
+            Some(next_hop) => discovery.resolve_relay(next_hop, &conf).await,
+            None => discovery.resolve_location(&location, &network_id, &conf).await,
+        };
+        let cancel = CancelQuery {
+            request_id: request_id.to_string(),
+            requesting_relay: conf.name.to_string(),
         };
-        let target: RequestState = RequestState {
-            status: new_status as i32,
-            request_id: curr_request_id.clone(),
-            state,
+        let result = match resolved {
+            Ok(endpoints) => call_relay(&endpoints, |channel| {
+                let mut client = DataTransferClient::new(channel).with_grpc_options(&conf.grpc);
+                let cancel = cancel.clone();
+                async move { client.cancel_request(traced(cancel)).await }
+            })
+            .await
+            .map(|(response, _)| response.into_inner()),
+            Err(e) => Err(e),
         };
+        match result {
+            Ok(ack) if ack.status == ack::Status::Ok as i32 => info!("Remote relay cancelled query {}", request_id),
+            Ok(ack) => warn!("Remote relay did not cancel query {}: {}", request_id, ack.message),
+            Err(e) => warn!("Unable to tell the remote relay of network {} to cancel query {}: {:?}", network_id, request_id, e),
+        }
+    }.in_current_span());
+}
+
+// Updates the request status in db. This function is called for the first
+// time after an Ack is received from the remote relay.
+// A locally created RequestState with status Pending or Error is stored.
+// When a response is received from the remote relay it will write the
+// returned RequestState with status Completed or Error.
+fn update_request_status(
+    conf: &RelayConfig,
+    curr_request_id: String,
+    new_status: request_state::Status,
+    state: Option<request_state::State>,
+) {
+    let db = Database {
+        db_path: conf.db_path.to_string(),
+        db_open_max_retries: conf.db_open_max_retries,
+        db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+    };
+    // A request cancelled in the meantime stays cancelled.
+    if let Ok(request_state) = db.get::<RequestState>(curr_request_id.to_string()) {
+        if request_state.status == request_state::Status::Cancelled as i32 {
+            info!("Request {} was cancelled, not updating its status", curr_request_id);
+            return;
+        }
+    }
+    let target: RequestState = RequestState {
+        status: new_status as i32,
+        request_id: curr_request_id.clone(),
+        state,
+        relay_path: vec![],
+        callback: None,
+    };
 
-        // Panic if this fails, atm the panic is just logged by the tokio runtime
-        db.set(&curr_request_id, &target)
-            .expect("Failed to insert into DB");
-        println!("Successfully written RequestState to database");
-        println!("{:?}\n", db.get::<RequestState>(curr_request_id).unwrap())
+    // Panic if this fails, atm the panic is just logged by the tokio runtime
+    db.set(&curr_request_id, &target)
+        .expect("Failed to insert into DB");
+    metrics().record_request_state(target.status);
+    state_watch().notify(&curr_request_id);
+    info!("Successfully written RequestState to database");
+    debug!("{:?}", Redacted(&db.get::<RequestState>(curr_request_id.to_string()).unwrap()));
+    if new_status == request_state::Status::Error {
+        // A failed query is not served by the remote relay, so there is nothing to cancel there.
+        let _ = db.remove(get_sent_query_key(curr_request_id.to_string()));
+        result_cache().forget(&curr_request_id);
+        deliver_final_state(conf, &curr_request_id);
     }
+}
+
+// Sends a request to the remote relay
+pub fn spawn_send_request(
+    conf: RelayConfig,
+    discovery: Arc<RelayDiscovery>,
+    network_query: NetworkQuery,
+    request_id: String,
+    location: LocationSegment,
+    network_id: String,
+) {
+    result_cache().expect(&request_id, &network_query, &conf.cache);
+    remember_sent_query(&conf, &request_id, &location, &network_id);
+    let work = PendingWork::SendRequest {
+        network_query: network_query.clone(),
+        request_id: request_id.to_string(),
+        location: location.clone(),
+        network_id: network_id.to_string(),
+    };
     // Spawning new thread to make the data_transfer_call to remote relay
-    tokio::spawn(async move {
-        let db_path = conf.get_str("db_path").unwrap();
-
-        // Iterate through the relay entries in the configuration to find a match
-        let relays_table = conf.get_table("relays").unwrap();
-        let mut relay_tls = false;
-        let mut relay_tlsca_cert_path = "".to_string();
-        for (_relay_name, relay_spec) in relays_table {
-            let relay_uri = relay_spec.clone().try_into::<LocationSegment>().unwrap();
-            if relay_host == relay_uri.hostname && relay_port == relay_uri.port {
-                relay_tls = relay_uri.tls;
-                relay_tlsca_cert_path = relay_uri.tlsca_cert_path;
+    spawn_tracked(work, async move {
+        // Find the remote relay's endpoint and TLS settings. Networks reached
+        // through intermediary relays are queried through the first of them.
+        let resolved = match next_hop(&network_id, &conf) {
+            Some(next_hop) => discovery.resolve_relay(next_hop, &conf).await,
+            None => discovery.resolve_location(&location, &network_id, &conf).await,
+        };
+        let endpoints = match resolved {
+            Ok(endpoints) => endpoints,
+            Err(e) => {
+                warn!("Unable to find the remote relay for network {}: {:?}", network_id, e);
+                update_request_status(
+                    &conf,
+                    request_id.to_string(),
+                    request_state::Status::Error,
+                    Some(request_state::State::Error(format!("{:?}", e))),
+                );
+                return;
             }
-        }
-
+        };
         let result = data_transfer_call(
-            conf.get_str("name").unwrap(),
-            relay_host,
-            relay_port,
+            conf.name.to_string(),
+            &endpoints,
             network_query,
             request_id.clone(),
-            relay_tls,
-            relay_tlsca_cert_path.to_string(),
+            conf.grpc.clone(),
         )
         .await;
-        println!("Received Ack from remote relay: {:?}\n", result);
+        info!("Received Ack from remote relay: {:?}", result);
         // Potentially clean up when more skilled at Rust.
         // Updates the request in the DB depending on the response status from the remote relay
         match result {
@@ -607,30 +1024,24 @@ fn spawn_send_request(
                 match ack::Status::from_i32(ack_response_into_inner.status) {
                     Some(status) => match status {
                         ack::Status::Ok => update_request_status(
+                            &conf,
                             request_id.to_string(),
                             request_state::Status::Pending,
-                            db_path.to_string(),
-                            conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-                            conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
                             None,
                         ),
                         ack::Status::Error => update_request_status(
+                            &conf,
                             request_id.to_string(),
                             request_state::Status::Error,
-                            db_path.to_string(),
-                            conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-                            conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
                             Some(request_state::State::Error(
                                 ack_response_into_inner.message.to_string(),
                             )),
                         ),
                     },
                     None => update_request_status(
+                        &conf,
                         request_id.to_string(),
                         request_state::Status::Error,
-                        db_path.to_string(),
-                        conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-                        conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
                         Some(request_state::State::Error(
                             "Status is not supported or is invalid".to_string(),
                         )),
@@ -638,101 +1049,218 @@ fn spawn_send_request(
                 }
             }
             Err(result_error) => update_request_status(
+                &conf,
                 request_id.to_string(),
                 request_state::Status::Error,
-                db_path.to_string(),
-                conf.get_int("db_open_max_retries").unwrap_or(500) as u32,
-                conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32,
                 Some(request_state::State::Error(format!("{:?}", result_error))),
             ),
         }
-    });
+    }.in_current_span());
 }
-// Call to remote relay for the data transfer protocol.
+// Call to remote relay for the data transfer protocol, failing over between its endpoints.
 async fn data_transfer_call(
     relay_name: String,
-    relay_host: String,
-    relay_port: String,
+    endpoints: &[LocationSegment],
     network_query: NetworkQuery,
     request_id: String,
-    use_tls: bool,
-    tlsca_cert_path: String,
-) -> Result<Response<Ack>, Box<dyn std::error::Error>> {
-    let mut client;
-    if use_tls {
-        let client_addr = format!("https://{}:{}", relay_host, relay_port);
-        let pem = tokio::fs::read(tlsca_cert_path).await?;
-        let ca = Certificate::from_pem(pem);
-
-        let tls = ClientTlsConfig::new()
-            .ca_certificate(ca)
-            .domain_name(relay_host);
-
-        let channel = Channel::from_shared(client_addr)?
-            .tls_config(tls)?
-            .connect()
-            .await?;
-
-        client = DataTransferClient::new(channel);
-    } else {
-        let client_addr = format!("http://{}:{}", relay_host, relay_port);
-        client = DataTransferClient::connect(client_addr).await?;
-    }
-    let query_request = tonic::Request::new(Query {
+    grpc_options: GrpcOptions,
+) -> Result<Response<Ack>, Error> {
+    let query = remote_query(relay_name, network_query, request_id);
+    debug!("Query: {:?}", Redacted(&query));
+    let (response, endpoint) = call_relay(endpoints, |channel| {
+        let mut client = DataTransferClient::new(channel).with_grpc_options(&grpc_options);
+        let query = query.clone();
+        async move { client.request_state(traced(query)).await }
+    })
+    .await?;
+    info!("Sent Query to remote relay: {}", endpoint.address());
+    Ok(response)
+}
+
+// Records where a query is sent, so that the remote relay can be told when the
+// query is cancelled.
+fn remember_sent_query(conf: &RelayConfig, request_id: &str, location: &LocationSegment, network_id: &str) {
+    let db = Database {
+        db_path: conf.db_path.to_string(),
+        db_open_max_retries: conf.db_open_max_retries,
+        db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+    };
+    if let Err(e) = db.set(&get_sent_query_key(request_id.to_string()), &(location.clone(), network_id.to_string())) {
+        warn!("Unable to record where query {} is sent, it cannot be cancelled at the remote relay: {:?}", request_id, e);
+    }
+}
+
+// The completed state of a query answered from the result cache, if it can be.
+fn cached_state(network_query: &NetworkQuery, request_id: &str, conf: &RelayConfig) -> Option<RequestState> {
+    let view = result_cache().lookup(network_query, &conf.cache)?;
+    info!("Answering query {} from the result cache", request_id);
+    Some(RequestState {
+        status: request_state::Status::Completed as i32,
+        request_id: request_id.to_string(),
+        state: Some(request_state::State::View(view)),
+        relay_path: vec![],
+        callback: None,
+    })
+}
+
+// The query sent to the remote relay for a query of the network.
+fn remote_query(relay_name: String, network_query: NetworkQuery, request_id: String) -> Query {
+    Query {
         policy: network_query.policy,
         address: network_query.address,
-        requesting_relay: relay_name,
+        requesting_relay: relay_name.to_string(),
         requesting_org: network_query.requesting_org,
         requesting_network: network_query.requesting_network,
         certificate: network_query.certificate,
         requestor_signature: network_query.requestor_signature,
         nonce: network_query.nonce,
-        request_id: request_id.to_string(),
+        request_id,
         confidential: network_query.confidential,
-    });
-    println!("Query: {:?}", query_request);
-    let response = client.request_state(query_request).await?;
-    Ok(response)
+        relay_path: vec![relay_name],
+    }
+}
+
+// Sends the queries of a batch that go to the same remote relay in as few
+// calls as the message size limit allows. A remote relay that does not serve
+// batches gets the queries one by one.
+pub fn spawn_send_batch(
+    conf: RelayConfig,
+    discovery: Arc<RelayDiscovery>,
+    queries: Vec<(NetworkQuery, String)>,
+    location: LocationSegment,
+    network_id: String,
+) {
+    for (network_query, request_id) in queries.iter() {
+        result_cache().expect(request_id, network_query, &conf.cache);
+        remember_sent_query(&conf, request_id, &location, &network_id);
+    }
+    let work = PendingWork::SendBatch {
+        queries: queries.clone(),
+        location: location.clone(),
+        network_id: network_id.to_string(),
+    };
+    spawn_tracked(work, async move {
+        let resolved = match next_hop(&network_id, &conf) {
+            Some(next_hop) => discovery.resolve_relay(next_hop, &conf).await,
+            None => discovery.resolve_location(&location, &network_id, &conf).await,
+        };
+        let endpoints = match resolved {
+            Ok(endpoints) => endpoints,
+            Err(e) => {
+                warn!("Unable to find the remote relay for network {}: {:?}", network_id, e);
+                fail_batch(&conf, &queries, format!("{:?}", e));
+                return;
+            }
+        };
+        let batches = conf.grpc.split_to_fit(queries, |(network_query, request_id)| {
+            remote_query(conf.name.to_string(), network_query.clone(), request_id.to_string()).encoded_len()
+        });
+        for queries in batches {
+            send_batch(&conf, &discovery, &endpoints, queries, &location, &network_id).await;
+        }
+    }.in_current_span());
+}
+
+// Sends queries of a batch to the remote relay in one call, and records
+// whether it acknowledged each of them.
+async fn send_batch(
+    conf: &RelayConfig,
+    discovery: &Arc<RelayDiscovery>,
+    endpoints: &[LocationSegment],
+    queries: Vec<(NetworkQuery, String)>,
+    location: &LocationSegment,
+    network_id: &str,
+) {
+    let update = |request_id: &str, new_status: request_state::Status, state: Option<request_state::State>| {
+        update_request_status(conf, request_id.to_string(), new_status, state)
+    };
+    let batch = QueryBatch {
+        queries: queries
+            .iter()
+            .map(|(network_query, request_id)| remote_query(conf.name.to_string(), network_query.clone(), request_id.to_string()))
+            .collect(),
+    };
+    let result = call_relay(endpoints, |channel| {
+        let mut client = DataTransferClient::new(channel).with_grpc_options(&conf.grpc);
+        let batch = batch.clone();
+        async move { client.request_state_batch(traced(batch)).await }
+    })
+    .await;
+    match result {
+        Ok((response, endpoint)) => {
+            info!("Sent {} batched queries to remote relay: {}", queries.len(), endpoint.address());
+            let acks = response.into_inner().acks;
+            for (_, request_id) in queries.iter() {
+                match acks.iter().find(|ack| &ack.request_id == request_id) {
+                    Some(ack) if ack.status == ack::Status::Ok as i32 => update(request_id, request_state::Status::Pending, None),
+                    Some(ack) => update(request_id, request_state::Status::Error, Some(request_state::State::Error(ack.message.to_string()))),
+                    None => update(
+                        request_id,
+                        request_state::Status::Error,
+                        Some(request_state::State::Error("The remote relay did not acknowledge the query".to_string())),
+                    ),
+                }
+            }
+        }
+        Err(Error::StatusError(status)) if status.code() == Code::Unimplemented => {
+            info!("Remote relay of network {} does not serve batches, sending the queries one by one", network_id);
+            for (network_query, request_id) in queries {
+                spawn_send_request(conf.clone(), discovery.clone(), network_query, request_id, location.clone(), network_id.to_string());
+            }
+        }
+        Err(e) => {
+            warn!("Sending batched queries to the remote relay of network {} failed: {:?}", network_id, e);
+            fail_batch(conf, &queries, format!("{:?}", e));
+        }
+    }
+}
+
+fn fail_batch(conf: &RelayConfig, queries: &[(NetworkQuery, String)], message: String) {
+    for (_, request_id) in queries.iter() {
+        update_request_status(conf, request_id.to_string(), request_state::Status::Error, Some(request_state::State::Error(message.to_string())));
+    }
 }
 
 
 // Sends a request to the remote relay
-fn spawn_send_event_subscription_request(
-    conf: config::Config,
+pub fn spawn_send_event_subscription_request(
+    conf: RelayConfig,
+    discovery: Arc<RelayDiscovery>,
     event_subscription: EventSubscription,
     request_id: String,
-    relay_host: String,
-    relay_port: String,
+    location: LocationSegment,
+    network_id: String,
 ) {
-    println!("Sending EventSubscription to remote relay: {:?}:{:?}", relay_host, relay_port);
-    
+    let work = PendingWork::SendEventSubscriptionRequest {
+        event_subscription: event_subscription.clone(),
+        request_id: request_id.to_string(),
+        location: location.clone(),
+        network_id: network_id.to_string(),
+    };
     // Spawning new thread to make the subscribe_event_call to remote relay
-    tokio::spawn(async move {
-        let db_path = conf.get_str("db_path").unwrap();
-        let db_open_max_retries = conf.get_int("db_open_max_retries").unwrap_or(500) as u32;
-        let db_open_retry_backoff_msec = conf.get_int("db_open_retry_backoff_msec").unwrap_or(10) as u32;
-
-        // Iterate through the relay entries in the configuration to find a match
-        let relays_table = conf.get_table("relays").unwrap();
-        let mut relay_tls = false;
-        let mut relay_tlsca_cert_path = "".to_string();
-        for (_relay_name, relay_spec) in relays_table {
-            let relay_uri = relay_spec.clone().try_into::<LocationSegment>().unwrap();
-            if relay_host == relay_uri.hostname && relay_port == relay_uri.port {
-                relay_tls = relay_uri.tls;
-                relay_tlsca_cert_path = relay_uri.tlsca_cert_path;
-            }
-        }
+    spawn_tracked(work, async move {
+        let db_path = conf.db_path.to_string();
+        let db_open_max_retries = conf.db_open_max_retries;
+        let db_open_retry_backoff_msec = conf.db_open_retry_backoff_msec;
 
-        let result = suscribe_event_call(
-            relay_host,
-            relay_port,
-            event_subscription,
-            relay_tls,
-            relay_tlsca_cert_path.to_string(),
-        )
-        .await;
-        println!("Received Ack from remote relay: {:?}\n", result);
+        // Find the remote relay's endpoint and TLS settings
+        let endpoints = match discovery.resolve_location(&location, &network_id, &conf).await {
+            Ok(endpoints) => endpoints,
+            Err(e) => {
+                warn!("Unable to find the remote relay for network {}: {:?}", network_id, e);
+                update_event_subscription_status(
+                    request_id.to_string(),
+                    ack::Status::Error,
+                    db_path.to_string(),
+                    db_open_max_retries,
+                    db_open_retry_backoff_msec,
+                    format!("{:?}", e),
+                );
+                return;
+            }
+        };
+        let result = suscribe_event_call(&endpoints, event_subscription, conf.grpc.clone()).await;
+        info!("Received Ack from remote relay: {:?}", result);
         // Potentially clean up when more skilled at Rust.
         // Updates the request in the DB depending on the response status from the remote relay
         match result {
@@ -767,39 +1295,432 @@ fn spawn_send_event_subscription_request(
                 format!("{:?}", result_error).to_string(),
             ),
         }
-    });
+    }.in_current_span());
 }
-// // Call to remote relay for the event subscription protocol.
+// // Call to remote relay for the event subscription protocol, failing over between its endpoints.
 async fn suscribe_event_call(
-    relay_host: String,
-    relay_port: String,
+    endpoints: &[LocationSegment],
     event_subscription: EventSubscription,
-    use_tls: bool,
-    tlsca_cert_path: String,
-) -> Result<Response<Ack>, Box<dyn std::error::Error>> {
-    let mut client;
-    if use_tls {
-        let client_addr = format!("https://{}:{}", relay_host, relay_port);
-        let pem = tokio::fs::read(tlsca_cert_path).await?;
-        let ca = Certificate::from_pem(pem);
-    
-        let tls = ClientTlsConfig::new()
-            .ca_certificate(ca)
-            .domain_name(relay_host);
-    
-        let channel = Channel::from_shared(client_addr)?
-            .tls_config(tls)?
-            .connect()
-            .await?;
-    
-        client = EventSubscribeClient::new(channel);
-    } else {
-        let client_addr = format!("http://{}:{}", relay_host, relay_port);
-        client = EventSubscribeClient::connect(client_addr).await?;
-    }
-    
-    let event_subscription_request = tonic::Request::new(event_subscription);
-    println!("EventSubscription: {:?}", event_subscription_request);
-    let response = client.subscribe_event(event_subscription_request).await?;
+    grpc_options: GrpcOptions,
+) -> Result<Response<Ack>, Error> {
+    debug!("EventSubscription: {:?}", Redacted(&event_subscription));
+    let (response, endpoint) = call_relay(endpoints, |channel| {
+        let mut client = EventSubscribeClient::new(channel).with_grpc_options(&grpc_options);
+        let event_subscription = event_subscription.clone();
+        async move { client.subscribe_event(traced(event_subscription)).await }
+    })
+    .await?;
+    info!("Sent EventSubscription to remote relay: {}", endpoint.address());
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_config::TestConfig;
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+    use crate::nonce_store::NonceStore;
+    use crate::rate_limit::RateLimiter;
+    use crate::services::data_transfer_service::DataTransferService;
+    use weaverpb::common::state::{view_payload, Meta, View, ViewPayload};
+    use weaverpb::relay::datatransfer::data_transfer_server::{DataTransfer, DataTransferServer};
+    use weaverpb::relay::datatransfer::AckBatch;
+
+    // A remote relay recording the queries, batches and cancellations it
+    // receives, which acknowledges every query.
+    #[derive(Clone, Default)]
+    struct RemoteRelay {
+        queries: Arc<Mutex<Vec<String>>>,
+        batches: Arc<Mutex<Vec<Vec<String>>>>,
+        cancelled: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[tonic::async_trait]
+    impl DataTransfer for RemoteRelay {
+        async fn request_state(&self, request: Request<Query>) -> Result<Response<Ack>, Status> {
+            let request_id = request.into_inner().request_id;
+            self.queries.lock().unwrap().push(request_id.to_string());
+            Ok(Response::new(Ack {
+                status: ack::Status::Ok as i32,
+                request_id,
+                message: "".to_string(),
+            }))
+        }
+
+        async fn send_state(&self, _request: Request<ViewPayload>) -> Result<Response<Ack>, Status> {
+            Err(Status::unimplemented("SendState"))
+        }
+
+        async fn request_state_batch(&self, request: Request<QueryBatch>) -> Result<Response<AckBatch>, Status> {
+            let queries = request.into_inner().queries;
+            self.batches.lock().unwrap().push(queries.iter().map(|query| query.address.to_string()).collect());
+            let acks = queries
+                .into_iter()
+                .map(|query| Ack {
+                    status: ack::Status::Ok as i32,
+                    request_id: query.request_id,
+                    message: "".to_string(),
+                })
+                .collect();
+            Ok(Response::new(AckBatch { acks }))
+        }
+
+        async fn cancel_request(&self, request: Request<CancelQuery>) -> Result<Response<Ack>, Status> {
+            let request_id = request.into_inner().request_id;
+            self.cancelled.lock().unwrap().push(request_id.to_string());
+            Ok(Response::new(Ack {
+                status: ack::Status::Ok as i32,
+                request_id,
+                message: "".to_string(),
+            }))
+        }
+
+        async fn send_driver_state(&self, _request: Request<ViewPayload>) -> Result<Response<Ack>, Status> {
+            Err(Status::unimplemented("SendDriverState"))
+        }
+    }
+
+    fn serve_remote_relay() -> (String, RemoteRelay) {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port().to_string();
+        let remote_relay = RemoteRelay::default();
+        let addr: SocketAddr = format!("127.0.0.1:{}", port).parse().unwrap();
+        tokio::spawn(tonic::transport::Server::builder().add_service(DataTransferServer::new(remote_relay.clone())).serve(addr));
+        (port, remote_relay)
+    }
+
+    fn network_service(dir: &TestConfig) -> NetworkService {
+        network_service_with(dir, "")
+    }
+
+    fn network_service_with(dir: &TestConfig, extra: &str) -> NetworkService {
+        NetworkService {
+            config_lock: Arc::new(RwLock::new(dir.relay_config("Fabric_Relay", "9080", extra))),
+            discovery: Arc::new(RelayDiscovery::new()),
+        }
+    }
+
+    // Sends a view back for each query the remote relay receives, as the remote
+    // relay does once the remote network answered it.
+    fn answer_queries(relay: &RemoteRelay, service: &NetworkService) {
+        let replying = DataTransferService {
+            config_lock: service.config_lock.clone(),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            nonce_store: NonceStore::new(),
+            discovery: service.discovery.clone(),
+        };
+        let queries = relay.queries.clone();
+        tokio::spawn(async move {
+            let mut answered = 0;
+            loop {
+                let unanswered: Vec<String> = queries.lock().unwrap()[answered..].to_vec();
+                for request_id in unanswered {
+                    let view = View {
+                        meta: Some(Meta::default()),
+                        data: format!("view of {}", request_id).into_bytes(),
+                    };
+                    let payload = ViewPayload {
+                        request_id,
+                        relay_path: vec![],
+                        state: Some(view_payload::State::View(view)),
+                    };
+                    replying.send_state(Request::new(payload)).await.unwrap();
+                    answered += 1;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+    }
+
+    fn sync_query(address: &str, timeout_msec: u32) -> NetworkQuerySync {
+        NetworkQuerySync {
+            query: Some(network_query(address)),
+            timeout_msec,
+        }
+    }
+
+    fn network_query(address: &str) -> NetworkQuery {
+        NetworkQuery {
+            address: address.to_string(),
+            ..Default::default()
+        }
+    }
+
+    async fn wait_for_status(db: &Database, request_id: &str, status: request_state::Status) {
+        for _ in 0..100 {
+            if db.get::<RequestState>(request_id.to_string()).map(|state| state.status).ok() == Some(status as i32) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("request {} did not reach status {:?}", request_id, status);
+    }
+
+    #[tokio::test]
+    async fn batches_are_sent_once_per_remote_relay() {
+        let (port1, relay1) = serve_remote_relay();
+        let (port2, relay2) = serve_remote_relay();
+        let dir = TestConfig::default();
+        let service = network_service(&dir);
+        let conf = service.config_lock.read().await.clone();
+        let db = Database {
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        let addresses = [
+            format!("localhost:{}/network1/mychannel:simplestate:Read:a", port1),
+            format!("localhost:{}/network2/mychannel:simplestate:Read:b", port1),
+            "not an address".to_string(),
+            format!("localhost:{}/network3/mychannel:simplestate:Read:c", port2),
+        ];
+        let batch = NetworkQueryBatch { queries: addresses.iter().map(|address| network_query(address)).collect() };
+        let reply = service.request_state_batch(Request::new(batch)).await.unwrap().into_inner();
+        assert_eq!(reply.acks.len(), 4);
+        let statuses: Vec<i32> = reply.acks.iter().map(|ack| ack.status).collect();
+        assert_eq!(statuses, vec![ack::Status::Ok as i32, ack::Status::Ok as i32, ack::Status::Error as i32, ack::Status::Ok as i32]);
+        let request_ids: Vec<String> = reply.acks.iter().map(|ack| ack.request_id.to_string()).collect();
+        let bad_address = db.get::<RequestState>(request_ids[2].to_string()).unwrap();
+        assert_eq!(bad_address.status, request_state::Status::Error as i32);
+
+        for request_id in [&request_ids[0], &request_ids[1], &request_ids[3]] {
+            wait_for_status(&db, request_id, request_state::Status::Pending).await;
+        }
+        assert_eq!(*relay1.batches.lock().unwrap(), vec![addresses[0..2].to_vec()]);
+        assert_eq!(*relay2.batches.lock().unwrap(), vec![vec![addresses[3].to_string()]]);
+
+        // States passed to the network are marked deleted once final.
+        let completed = RequestState {
+            status: request_state::Status::Completed as i32,
+            ..db.get::<RequestState>(request_ids[0].to_string()).unwrap()
+        };
+        db.set(&request_ids[0], &completed).unwrap();
+        let message = GetBatchStateMessage { batch_id: reply.batch_id.to_string() };
+        let batch_state = service.get_batch_state(Request::new(message)).await.unwrap().into_inner();
+        let statuses: Vec<i32> = batch_state.states.iter().map(|state| state.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                request_state::Status::Completed as i32,
+                request_state::Status::Pending as i32,
+                request_state::Status::Error as i32,
+                request_state::Status::Pending as i32,
+            ]
+        );
+        let stored: Vec<i32> = request_ids.iter().map(|request_id| db.get::<RequestState>(request_id.to_string()).unwrap().status).collect();
+        assert_eq!(
+            stored,
+            vec![
+                request_state::Status::Deleted as i32,
+                request_state::Status::Pending as i32,
+                request_state::Status::Deleted as i32,
+                request_state::Status::Pending as i32,
+            ]
+        );
+        assert!(db.has_key(get_batch_key(reply.batch_id.to_string())).unwrap());
+
+        // The batch is removed once all its states were passed to the network.
+        for request_id in [&request_ids[1], &request_ids[3]] {
+            let completed = RequestState {
+                status: request_state::Status::Completed as i32,
+                ..db.get::<RequestState>(request_id.to_string()).unwrap()
+            };
+            db.set(request_id, &completed).unwrap();
+        }
+        let message = GetBatchStateMessage { batch_id: reply.batch_id.to_string() };
+        let batch_state = service.get_batch_state(Request::new(message.clone())).await.unwrap().into_inner();
+        assert_eq!(batch_state.states.len(), 4);
+        assert!(!db.has_key(get_batch_key(reply.batch_id.to_string())).unwrap());
+        let status = service.get_batch_state(Request::new(message)).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn batches_are_split_to_fit_the_message_size() {
+        let (port, relay) = serve_remote_relay();
+        let dir = TestConfig::default();
+        let service = network_service(&dir);
+        service.config_lock.write().await.grpc.max_encoding_message_size = Some(300);
+        let addresses: Vec<String> = (0..3).map(|i| format!("localhost:{}/network1/mychannel:simplestate:Read:{}", port, i)).collect();
+        let batch = NetworkQueryBatch { queries: addresses.iter().map(|address| network_query(address)).collect() };
+        let reply = service.request_state_batch(Request::new(batch)).await.unwrap().into_inner();
+        let conf = service.config_lock.read().await.clone();
+        let db = Database {
+            db_path: conf.db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        for ack in reply.acks.iter() {
+            wait_for_status(&db, &ack.request_id, request_state::Status::Pending).await;
+        }
+        let batches = relay.batches.lock().unwrap().clone();
+        assert!(batches.len() > 1, "{} queries fit in one message of 300 bytes", addresses.len());
+        assert_eq!(batches.concat(), addresses);
+    }
+
+    #[tokio::test]
+    async fn batches_whose_reply_is_too_large_are_refused() {
+        let dir = TestConfig::default();
+        let service = network_service(&dir);
+        service.config_lock.write().await.grpc.max_encoding_message_size = Some(1000);
+        let fitting = (1000 - batch_reply_len(0)) / (batch_reply_len(1) - batch_reply_len(0));
+        let batch = |len: usize| NetworkQueryBatch { queries: vec![network_query("not an address"); len] };
+        assert!(service.request_state_batch(Request::new(batch(fitting))).await.is_ok());
+        let refused = service.request_state_batch(Request::new(batch(fitting + 1))).await.unwrap_err();
+        assert_eq!(refused.code(), Code::ResourceExhausted);
+    }
+
+    fn request_db(dir: &TestConfig) -> Database {
+        Database {
+            db_path: dir.path().join("requests").display().to_string(),
+            db_open_max_retries: 500,
+            db_open_retry_backoff_msec: 10,
+        }
+    }
+
+    fn request_state(request_id: &str, status: request_state::Status) -> RequestState {
+        RequestState {
+            status: status as i32,
+            request_id: request_id.to_string(),
+            state: None,
+            relay_path: vec![],
+            callback: None,
+        }
+    }
+
+    #[test]
+    fn only_pending_requests_are_cancelled() {
+        let dir = TestConfig::default();
+        let db = request_db(&dir);
+        let location = LocationSegment {
+            hostname: "localhost".to_string(),
+            port: "9081".to_string(),
+            tls: false,
+            tlsca_cert_path: "".to_string(),
+            priority: 0,
+            endpoints: vec![],
+        };
+        db.set(&"r1".to_string(), &request_state("r1", request_state::Status::Pending)).unwrap();
+        db.set(&get_sent_query_key("r1".to_string()), &(location.clone(), "network2".to_string())).unwrap();
+        assert_eq!(mark_request_cancelled(&db, "r1").unwrap(), Some((location, "network2".to_string())));
+        let cancelled = db.get::<RequestState>("r1".to_string()).unwrap();
+        assert_eq!(cancelled.status, request_state::Status::Cancelled as i32);
+        assert!(!db.has_key(get_sent_query_key("r1".to_string())).unwrap());
+        // A cancelled request cannot be cancelled again.
+        assert!(mark_request_cancelled(&db, "r1").is_err());
+
+        // A query not sent yet has nothing to cancel at the remote relay.
+        db.set(&"r2".to_string(), &request_state("r2", request_state::Status::PendingAck)).unwrap();
+        assert_eq!(mark_request_cancelled(&db, "r2").unwrap(), None);
+
+        let completed = request_state("r3", request_state::Status::Completed);
+        db.set(&"r3".to_string(), &completed).unwrap();
+        assert!(mark_request_cancelled(&db, "r3").is_err());
+        assert_eq!(db.get::<RequestState>("r3".to_string()).unwrap(), completed);
+        assert!(mark_request_cancelled(&db, "unknown").is_err());
+    }
+
+    #[tokio::test]
+    async fn cancelled_requests_are_cancelled_at_the_remote_relay() {
+        let (port, relay) = serve_remote_relay();
+        let dir = TestConfig::default();
+        let service = network_service(&dir);
+        let db = request_db(&dir);
+        let address = format!("localhost:{}/network1/mychannel:simplestate:Read:a", port);
+        let batch = NetworkQueryBatch { queries: vec![network_query(&address)] };
+        let reply = service.request_state_batch(Request::new(batch)).await.unwrap().into_inner();
+        let request_id = reply.acks[0].request_id.to_string();
+        wait_for_status(&db, &request_id, request_state::Status::Pending).await;
+        assert!(db.has_key(get_sent_query_key(request_id.to_string())).unwrap());
+
+        let cancel = || CancelRequestMessage { request_id: request_id.to_string() };
+        let ack = service.cancel_request(Request::new(cancel())).await.unwrap().into_inner();
+        assert_eq!(ack.status, ack::Status::Ok as i32, "{}", ack.message);
+        assert_eq!(db.get::<RequestState>(request_id.to_string()).unwrap().status, request_state::Status::Cancelled as i32);
+        assert!(!db.has_key(get_sent_query_key(request_id.to_string())).unwrap());
+        for _ in 0..100 {
+            if !relay.cancelled.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(*relay.cancelled.lock().unwrap(), vec![request_id.to_string()]);
+
+        let ack = service.cancel_request(Request::new(cancel())).await.unwrap().into_inner();
+        assert_eq!(ack.status, ack::Status::Error as i32);
+    }
+
+    #[tokio::test]
+    async fn failed_requests_forget_where_they_were_sent() {
+        // Nothing listens on the port once the listener is dropped.
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let dir = TestConfig::default();
+        let service = network_service(&dir);
+        let db = request_db(&dir);
+        let address = format!("localhost:{}/network1/mychannel:simplestate:Read:a", port);
+        let batch = NetworkQueryBatch { queries: vec![network_query(&address)] };
+        let reply = service.request_state_batch(Request::new(batch)).await.unwrap().into_inner();
+        let request_id = reply.acks[0].request_id.to_string();
+        wait_for_status(&db, &request_id, request_state::Status::Error).await;
+        assert!(!db.has_key(get_sent_query_key(request_id.to_string())).unwrap());
+
+        let ack = service.cancel_request(Request::new(CancelRequestMessage { request_id })).await.unwrap().into_inner();
+        assert_eq!(ack.status, ack::Status::Error as i32);
+    }
+
+    #[tokio::test]
+    async fn request_state_sync_returns_the_final_state_once_it_arrives() {
+        let (port, relay) = serve_remote_relay();
+        let dir = TestConfig::default();
+        let service = network_service(&dir);
+        let db = request_db(&dir);
+        answer_queries(&relay, &service);
+        let address = format!("localhost:{}/network1/mychannel:simplestate:Read:a", port);
+
+        let state = service.request_state_sync(Request::new(sync_query(&address, 10000))).await.unwrap().into_inner();
+        assert_eq!(state.status, request_state::Status::Completed as i32);
+        let expected = format!("view of {}", state.request_id).into_bytes();
+        assert!(matches!(state.state, Some(request_state::State::View(view)) if view.data == expected));
+        // The state was passed to the network.
+        assert_eq!(db.get::<RequestState>(state.request_id.to_string()).unwrap().status, request_state::Status::Deleted as i32);
+    }
+
+    #[tokio::test]
+    async fn request_state_sync_returns_the_current_state_when_the_relay_stops_waiting() {
+        let (port, relay) = serve_remote_relay();
+        let dir = TestConfig::default();
+        // The relay waits less long than the client asks for.
+        let service = network_service_with(&dir, "max_sync_wait_msec=300\n");
+        let db = request_db(&dir);
+        let address = format!("localhost:{}/network1/mychannel:simplestate:Read:a", port);
+
+        let started = tokio::time::Instant::now();
+        let state = service.request_state_sync(Request::new(sync_query(&address, 60000))).await.unwrap().into_inner();
+        let waited = started.elapsed();
+        assert!(waited >= Duration::from_millis(300) && waited < Duration::from_secs(10), "{:?}", waited);
+        assert_eq!(state.status, request_state::Status::Pending as i32);
+        assert_eq!(*relay.queries.lock().unwrap(), vec![state.request_id.to_string()]);
+        // The request goes on, its state can still be fetched.
+        assert_eq!(db.get::<RequestState>(state.request_id.to_string()).unwrap().status, request_state::Status::Pending as i32);
+    }
+
+    #[tokio::test]
+    async fn request_state_sync_returns_a_state_that_is_already_final() {
+        let (port, relay) = serve_remote_relay();
+        let dir = TestConfig::default();
+        let address = format!("localhost:{}/network1/mychannel:simplestate:Read:a", port);
+        let cache = format!("[[cache.rules]]\naddress=\"{}\"\nttl_secs=60\n", address);
+        let service = network_service_with(&dir, &cache);
+        answer_queries(&relay, &service);
+        let first = service.request_state_sync(Request::new(sync_query(&address, 10000))).await.unwrap().into_inner();
+        assert_eq!(first.status, request_state::Status::Completed as i32);
+
+        // The query is now answered from the cache, its state is final as soon as it is stored.
+        let started = tokio::time::Instant::now();
+        let state = service.request_state_sync(Request::new(sync_query(&address, 10000))).await.unwrap().into_inner();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(state.status, request_state::Status::Completed as i32);
+        assert!(matches!(state.state, Some(request_state::State::View(view)) if view.meta.as_ref().is_some_and(|meta| meta.from_cache)));
+        assert_eq!(relay.queries.lock().unwrap().len(), 1);
+    }
+}
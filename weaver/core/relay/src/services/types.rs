@@ -8,11 +8,80 @@ use serde;
 pub struct Driver {
     pub port: String,
     pub hostname: String,
+    #[serde(default)]
     pub tls: bool,
+    #[serde(default)]
     pub tlsca_cert_path: String,
+    // Further instances of the driver, which requests are spread over and fail over to.
+    #[serde(default)]
+    pub endpoints: Vec<DriverEndpoint>,
+    // How an instance is picked for a request, "round_robin" or "least_loaded".
+    #[serde(default = "default_selection")]
+    pub selection: String,
+    // How long an instance that could not be reached is passed over.
+    #[serde(default = "default_ejection_msec")]
+    pub ejection_msec: u64,
+}
+
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct DriverEndpoint {
+    pub port: String,
+    pub hostname: String,
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default)]
+    pub tlsca_cert_path: String,
+}
+
+pub const ROUND_ROBIN: &str = "round_robin";
+pub const LEAST_LOADED: &str = "least_loaded";
+
+pub fn default_selection() -> String {
+    ROUND_ROBIN.to_string()
+}
+
+pub fn default_ejection_msec() -> u64 {
+    30000
+}
+
+impl Driver {
+    /// Every instance of the driver, the one given by `hostname` and `port`
+    /// first.
+    pub fn instances(&self) -> Vec<DriverEndpoint> {
+        let primary = DriverEndpoint {
+            port: self.port.to_string(),
+            hostname: self.hostname.to_string(),
+            tls: self.tls,
+            tlsca_cert_path: self.tlsca_cert_path.to_string(),
+        };
+        std::iter::once(primary).chain(self.endpoints.iter().cloned()).collect()
+    }
+}
+
+impl DriverEndpoint {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.hostname, self.port)
+    }
 }
 
 #[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
 pub struct Network {
+    /// Ledger type of the network, e.g. `Fabric`. Also names the network's
+    /// driver when `driver` is not set.
     pub network: String,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub driver: Option<String>,
+    #[serde(default)]
+    pub access_control_policies: Option<String>,
+    #[serde(default)]
+    pub allowed_relays: Option<Vec<String>>,
+    #[serde(default)]
+    pub denied_relays: Option<Vec<String>>,
+}
+
+impl Network {
+    /// Name of the driver serving the network, as used in `[drivers]`.
+    pub fn driver_name(&self) -> &str {
+        self.driver.as_deref().unwrap_or(&self.network)
+    }
+}
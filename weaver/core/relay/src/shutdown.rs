@@ -0,0 +1,467 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use weaverpb::common::ack::Ack;
+use weaverpb::common::events::{EventPublication, EventSubscription};
+use weaverpb::common::query::Query;
+use weaverpb::common::state::{RequestState, ViewPayload};
+use weaverpb::networks::networks::NetworkQuery;
+
+use crate::callbacks;
+use crate::db::{decode_exact, Database};
+use crate::discovery::RelayDiscovery;
+use crate::error::Error;
+use crate::rate_limit::RateLimiter;
+use crate::relay_config::RelayConfig;
+use crate::relay_proto::LocationSegment;
+use crate::services::{data_transfer_service, event_publish_service, event_subscribe_service, network_service};
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+const PENDING_WORK_KEY_PREFIX: &str = "pending_work_";
+
+/// Layout version of the work the relay persists when it stops. It has to be
+/// bumped whenever a variant of PendingWork, or a type it holds, changes, so
+/// that work persisted by an earlier version is not misread.
+const PENDING_WORK_VERSION: u32 = 1;
+
+/// The `[shutdown]` section of the relay config.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct ShutdownSettings {
+    // How long in-flight work may take to finish once the relay is asked to stop.
+    #[serde(default = "default_drain_timeout_msec")]
+    pub drain_timeout_msec: u64,
+}
+
+fn default_drain_timeout_msec() -> u64 {
+    10000
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        ShutdownSettings {
+            drain_timeout_msec: default_drain_timeout_msec(),
+        }
+    }
+}
+
+impl ShutdownSettings {
+    /// Reads the `[shutdown]` table, defaults apply when it is missing.
+    pub fn from_config(conf: &config::Config) -> Result<ShutdownSettings, String> {
+        match conf.get::<ShutdownSettings>("shutdown") {
+            Ok(settings) => Ok(settings),
+            Err(config::ConfigError::NotFound(_)) => Ok(ShutdownSettings::default()),
+            Err(e) => Err(format!("shutdown: {}", e)),
+        }
+    }
+}
+
+/// Work the relay does in the background on behalf of a request. Work that
+/// is not done when the relay stops is persisted in its db and started again
+/// on the next start, so it may be done more than once.
+#[derive(Clone, serde::Serialize, serde::Deserialize, Debug)]
+pub enum PendingWork {
+    // A query of a network, to be sent to the remote relay.
+    SendRequest {
+        network_query: NetworkQuery,
+        request_id: String,
+        location: LocationSegment,
+        network_id: String,
+    },
+    // An event subscription of a network, to be sent to the remote relay.
+    SendEventSubscriptionRequest {
+        event_subscription: EventSubscription,
+        request_id: String,
+        location: LocationSegment,
+        network_id: String,
+    },
+    // A query of a remote relay, to be sent to the driver.
+    RequestDriverState {
+        query: Query,
+        driver_name: String,
+    },
+    // An event subscription of a remote relay, to be sent to the driver.
+    DriverSubscribeEvent {
+        event_subscription: EventSubscription,
+        driver_name: String,
+    },
+    // A query result, to be sent back to the requesting relay.
+    SendState {
+        state: ViewPayload,
+        requesting_relay: String,
+        endpoints: Vec<LocationSegment>,
+    },
+    // An event, to be sent back to the subscribing relay.
+    PublishState {
+        state: ViewPayload,
+        requesting_relay: String,
+        endpoints: Vec<LocationSegment>,
+    },
+    // The status of an event subscription, to be sent back to the subscribing relay.
+    SendSubscriptionStatus {
+        ack: Ack,
+        requesting_relay: String,
+        endpoints: Vec<LocationSegment>,
+        unsubscribe: bool,
+    },
+    // An event received from the remote relay, to be delivered to its publication target.
+    HandleEvent {
+        state: ViewPayload,
+        publication_spec: EventPublication,
+        request_id: String,
+        event_id: String,
+    },
+    // An event, to be posted to an application.
+    PostToAppUrl {
+        app_url: String,
+        state: ViewPayload,
+    },
+    // The queries of a batch that go to the same remote relay, with their request ids.
+    SendBatch {
+        queries: Vec<(NetworkQuery, String)>,
+        location: LocationSegment,
+        network_id: String,
+    },
+    // The cancellation of a query, to be sent to the remote relay.
+    SendCancellation {
+        request_id: String,
+        location: LocationSegment,
+        network_id: String,
+    },
+    // The final state of a request, to be pushed to the callback of its query.
+    DeliverCallback {
+        state: RequestState,
+        callback: EventPublication,
+    },
+}
+
+impl PendingWork {
+    fn kind(&self) -> &'static str {
+        match self {
+            PendingWork::SendRequest { .. } => "SendRequest",
+            PendingWork::SendEventSubscriptionRequest { .. } => "SendEventSubscriptionRequest",
+            PendingWork::RequestDriverState { .. } => "RequestDriverState",
+            PendingWork::DriverSubscribeEvent { .. } => "DriverSubscribeEvent",
+            PendingWork::SendState { .. } => "SendState",
+            PendingWork::PublishState { .. } => "PublishState",
+            PendingWork::SendSubscriptionStatus { .. } => "SendSubscriptionStatus",
+            PendingWork::HandleEvent { .. } => "HandleEvent",
+            PendingWork::PostToAppUrl { .. } => "PostToAppUrl",
+            PendingWork::SendBatch { .. } => "SendBatch",
+            PendingWork::SendCancellation { .. } => "SendCancellation",
+            PendingWork::DeliverCallback { .. } => "DeliverCallback",
+        }
+    }
+}
+
+// Work as it is persisted: the work encoded in the layout of its version.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StoredWork {
+    version: u32,
+    work: Vec<u8>,
+}
+
+fn encode_work(work: &PendingWork) -> Result<StoredWork, Error> {
+    Ok(StoredWork {
+        version: PENDING_WORK_VERSION,
+        work: bincode::serialize(work)?,
+    })
+}
+
+fn decode_work(bytes: &[u8]) -> Result<PendingWork, Error> {
+    let stored: StoredWork = decode_exact(bytes)?;
+    if stored.version != PENDING_WORK_VERSION {
+        return Err(Error::Simple(format!(
+            "stored in layout version {}, the relay reads version {}",
+            stored.version, PENDING_WORK_VERSION
+        )));
+    }
+    decode_exact(&stored.work)
+}
+
+/// Background work of the relay that has been started and is not done yet.
+struct WorkTracker {
+    next_id: AtomicU64,
+    in_flight: Mutex<HashMap<u64, PendingWork>>,
+    // Number of in-flight tasks, watched while draining.
+    count: watch::Sender<usize>,
+}
+
+impl WorkTracker {
+    fn new() -> Arc<WorkTracker> {
+        Arc::new(WorkTracker {
+            next_id: AtomicU64::new(0),
+            in_flight: Mutex::new(HashMap::new()),
+            count: watch::channel(0).0,
+        })
+    }
+
+    fn spawn<F>(self: &Arc<Self>, work: PendingWork, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let finished = Finished(self.clone(), self.start(work));
+        tokio::spawn(async move {
+            let _finished = finished;
+            task.await;
+        });
+    }
+
+    // Waits up to `drain_timeout` for the in-flight work, and returns the work
+    // that did not finish in time.
+    async fn drain(&self, drain_timeout: Duration) -> Vec<PendingWork> {
+        let mut count = self.count.subscribe();
+        let in_flight = *count.borrow();
+        if in_flight > 0 {
+            info!("Waiting up to {} ms for {} in-flight tasks", drain_timeout.as_millis(), in_flight);
+        }
+        if tokio::time::timeout(drain_timeout, count.wait_for(|in_flight| *in_flight == 0)).await.is_ok() {
+            return vec![];
+        }
+        self.take_unfinished()
+    }
+
+    fn start(&self, work: PendingWork) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut in_flight = self.in_flight.lock().unwrap();
+        in_flight.insert(id, work);
+        self.count.send_replace(in_flight.len());
+        id
+    }
+
+    fn finish(&self, id: u64) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        in_flight.remove(&id);
+        self.count.send_replace(in_flight.len());
+    }
+
+    fn take_unfinished(&self) -> Vec<PendingWork> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let unfinished = in_flight.drain().map(|(_, work)| work).collect();
+        self.count.send_replace(0);
+        unfinished
+    }
+}
+
+fn tracker() -> &'static Arc<WorkTracker> {
+    static TRACKER: OnceLock<Arc<WorkTracker>> = OnceLock::new();
+    TRACKER.get_or_init(WorkTracker::new)
+}
+
+// Marks the work of a task as done when the task ends, also when it panics.
+struct Finished(Arc<WorkTracker>, u64);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        self.0.finish(self.1);
+    }
+}
+
+/// Spawns the task doing `work`. On shutdown the relay waits for it, and
+/// persists `work` when the task does not finish in time.
+pub fn spawn_tracked<F>(work: PendingWork, task: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tracker().spawn(work, task);
+}
+
+/// Completes when the relay is asked to stop, with SIGINT or SIGTERM.
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Unable to listen for SIGINT: {:?}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Unable to listen for SIGTERM: {:?}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+    info!("Shutting down, no longer accepting requests");
+}
+
+/// Waits up to `drain_timeout_msec` for the in-flight background work, then
+/// persists the work that is not done yet in the relay db and flushes the
+/// relay dbs.
+pub async fn drain_work(conf: &RelayConfig) {
+    let unfinished = tracker().drain(Duration::from_millis(conf.shutdown.drain_timeout_msec)).await;
+    let db = pending_work_db(conf);
+    for work in unfinished {
+        let stored = encode_work(&work).and_then(|stored| db.set(&format!("{}{}", PENDING_WORK_KEY_PREFIX, Uuid::new_v4()), &stored));
+        match stored {
+            Ok(_) => info!("Persisted unfinished {} to resume on the next start", work.kind()),
+            Err(e) => error!("Unable to persist unfinished {}: {:?}", work.kind(), e),
+        }
+    }
+    for db_path in [&conf.db_path, &conf.remote_db_path] {
+        let db = Database {
+            db_path: db_path.to_string(),
+            db_open_max_retries: conf.db_open_max_retries,
+            db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+        };
+        if let Err(e) = db.flush() {
+            error!("Unable to flush db {}: {:?}", db_path, e);
+        }
+    }
+}
+
+/// Starts the work persisted when the relay last stopped.
+pub fn resume_pending_work(conf: &RelayConfig, rate_limiter: Arc<RateLimiter>, discovery: Arc<RelayDiscovery>) {
+    let db = pending_work_db(conf);
+    let pending = match db.scan_prefix_bytes(PENDING_WORK_KEY_PREFIX.to_string()) {
+        Ok(pending) => pending,
+        Err(e) => {
+            error!("Unable to read the work persisted at the last shutdown: {:?}", e);
+            return;
+        }
+    };
+    for (key, bytes) in pending {
+        // Work that cannot be read is dropped rather than resumed as something else.
+        let work = decode_work(&bytes);
+        if let Err(e) = db.remove(key.to_string()) {
+            error!("Unable to remove persisted work {}, not resuming it: {:?}", key, e);
+            continue;
+        }
+        let work = match work {
+            Ok(work) => work,
+            Err(e) => {
+                warn!("Dropping persisted work {} that cannot be read: {:?}", key, e);
+                continue;
+            }
+        };
+        info!("Resuming {} persisted at the last shutdown", work.kind());
+        let span = tracing::info_span!("resume", work = work.kind());
+        let _entered = span.enter();
+        resume(work, conf.clone(), rate_limiter.clone(), discovery.clone());
+    }
+}
+
+fn resume(work: PendingWork, conf: RelayConfig, rate_limiter: Arc<RateLimiter>, discovery: Arc<RelayDiscovery>) {
+    match work {
+        PendingWork::SendRequest { network_query, request_id, location, network_id } => {
+            network_service::spawn_send_request(conf, discovery, network_query, request_id, location, network_id)
+        }
+        PendingWork::SendEventSubscriptionRequest { event_subscription, request_id, location, network_id } => {
+            network_service::spawn_send_event_subscription_request(conf, discovery, event_subscription, request_id, location, network_id)
+        }
+        PendingWork::RequestDriverState { query, driver_name } => match conf.drivers.get(&driver_name) {
+            Some(driver) => data_transfer_service::spawn_request_driver_state(query, driver.clone(), driver_name, conf, rate_limiter, discovery),
+            None => warn!("Driver {} is no longer configured, dropping query {}", driver_name, query.request_id),
+        },
+        PendingWork::DriverSubscribeEvent { event_subscription, driver_name } => match conf.drivers.get(&driver_name) {
+            Some(driver) => event_subscribe_service::spawn_driver_subscribe_event(event_subscription, driver.clone(), driver_name, conf, rate_limiter, discovery),
+            None => warn!("Driver {} is no longer configured, dropping its event subscription", driver_name),
+        },
+        PendingWork::SendState { state, requesting_relay, endpoints } => {
+            data_transfer_service::spawn_send_state(state, requesting_relay, endpoints, conf.grpc)
+        }
+        PendingWork::PublishState { state, requesting_relay, endpoints } => {
+            event_publish_service::spawn_send_state(state, requesting_relay, endpoints, conf.grpc)
+        }
+        PendingWork::SendSubscriptionStatus { ack, requesting_relay, endpoints, unsubscribe } => {
+            let remote_db = Database {
+                db_path: conf.remote_db_path.to_string(),
+                db_open_max_retries: conf.db_open_max_retries,
+                db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+            };
+            event_subscribe_service::spawn_send_subscription_status(ack, requesting_relay, endpoints, unsubscribe, remote_db, conf.grpc)
+        }
+        PendingWork::HandleEvent { state, publication_spec, request_id, event_id } => {
+            event_publish_service::spawn_handle_event(state, publication_spec, request_id, event_id, conf)
+        }
+        PendingWork::PostToAppUrl { app_url, state } => event_publish_service::spawn_post_to_app_url(app_url, state),
+        PendingWork::SendBatch { queries, location, network_id } => {
+            network_service::spawn_send_batch(conf, discovery, queries, location, network_id)
+        }
+        PendingWork::SendCancellation { request_id, location, network_id } => {
+            network_service::spawn_send_cancellation(conf, discovery, request_id, location, network_id)
+        }
+        PendingWork::DeliverCallback { state, callback } => callbacks::spawn_deliver_callback(conf, state, callback),
+    }
+}
+
+fn pending_work_db(conf: &RelayConfig) -> Database {
+    Database {
+        db_path: conf.db_path.to_string(),
+        db_open_max_retries: conf.db_open_max_retries,
+        db_open_retry_backoff_msec: conf.db_open_retry_backoff_msec,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(app_url: &str) -> PendingWork {
+        PendingWork::PostToAppUrl {
+            app_url: app_url.to_string(),
+            state: ViewPayload::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn unfinished_work_is_handed_over() {
+        let tracker = WorkTracker::new();
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let (done, finished) = tokio::sync::oneshot::channel::<()>();
+        tracker.spawn(post("http://app/done"), async move {
+            let _ = released.await;
+            let _ = done.send(());
+        });
+        tracker.spawn(post("http://app/stuck"), std::future::pending());
+        assert_eq!(*tracker.count.borrow(), 2);
+
+        release.send(()).unwrap();
+        finished.await.unwrap();
+        let unfinished = tracker.drain(Duration::from_millis(100)).await;
+        assert!(matches!(unfinished.as_slice(), [PendingWork::PostToAppUrl { app_url, .. }] if app_url == "http://app/stuck"));
+        assert_eq!(*tracker.count.borrow(), 0);
+    }
+
+    #[tokio::test]
+    async fn draining_finished_work_hands_nothing_over() {
+        let tracker = WorkTracker::new();
+        tracker.spawn(post("http://app/done"), async {});
+        assert!(tracker.drain(Duration::from_secs(5)).await.is_empty());
+    }
+
+    #[test]
+    fn persisted_work_is_read_back() {
+        let stored = bincode::serialize(&encode_work(&post("http://app/later")).unwrap()).unwrap();
+        assert!(matches!(decode_work(&stored), Ok(PendingWork::PostToAppUrl { app_url, .. }) if app_url == "http://app/later"));
+    }
+
+    #[test]
+    fn work_persisted_in_another_layout_is_not_read() {
+        let mut stored = encode_work(&post("http://app/later")).unwrap();
+        stored.version = PENDING_WORK_VERSION + 1;
+        let err = decode_work(&bincode::serialize(&stored).unwrap()).unwrap_err();
+        assert!(format!("{:?}", err).contains("layout version 2"));
+
+        // Work persisted before it carried a version is not misread either.
+        assert!(decode_work(&bincode::serialize(&post("http://app/later")).unwrap()).is_err());
+    }
+}
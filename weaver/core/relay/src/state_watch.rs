@@ -0,0 +1,84 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::Notify;
+
+/// Wakes up the callers waiting for the state of a request to change, so
+/// that they do not have to poll the db.
+pub struct StateWatch {
+    watched: Mutex<HashMap<String, Arc<Notify>>>,
+}
+
+static STATE_WATCH: OnceLock<StateWatch> = OnceLock::new();
+
+/// The relay's state watch, created on first use.
+pub fn state_watch() -> &'static StateWatch {
+    STATE_WATCH.get_or_init(StateWatch::new)
+}
+
+impl StateWatch {
+    fn new() -> StateWatch {
+        StateWatch {
+            watched: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Watches the state of a request until the watcher is dropped. A change
+    /// made after this call is never missed, even when the watcher is not
+    /// waiting yet.
+    pub fn watch(&'static self, request_id: &str) -> Watcher {
+        let notify = self.watched.lock().unwrap().entry(request_id.to_string()).or_default().clone();
+        Watcher {
+            watch: self,
+            request_id: request_id.to_string(),
+            notify,
+        }
+    }
+
+    /// Tells the watcher of a request, if any, that its state changed.
+    pub fn notify(&self, request_id: &str) {
+        if let Some(notify) = self.watched.lock().unwrap().get(request_id) {
+            notify.notify_one();
+        }
+    }
+}
+
+pub struct Watcher {
+    watch: &'static StateWatch,
+    request_id: String,
+    notify: Arc<Notify>,
+}
+
+impl Watcher {
+    /// Waits for the next change of the state.
+    pub async fn changed(&self) {
+        self.notify.notified().await
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.watch.watched.lock().unwrap().remove(&self.request_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn changes_before_waiting_are_not_missed() {
+        let watch: &'static StateWatch = Box::leak(Box::new(StateWatch::new()));
+        let watcher = watch.watch("r1");
+        watch.notify("r1");
+        watch.notify("r2");
+        assert!(tokio::time::timeout(Duration::from_secs(1), watcher.changed()).await.is_ok());
+        assert!(tokio::time::timeout(Duration::from_millis(10), watcher.changed()).await.is_err());
+        drop(watcher);
+        assert!(watch.watched.lock().unwrap().is_empty());
+    }
+}
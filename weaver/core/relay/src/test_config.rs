@@ -0,0 +1,57 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::relay_config::RelayConfig;
+
+use std::path::Path;
+use tempfile::TempDir;
+
+/// TestConfig is a temporary directory holding the databases and config file
+/// of a relay under test. The directory is removed when the TestConfig is
+/// dropped, so tests keep it alive for as long as they use the config.
+pub struct TestConfig {
+    dir: TempDir,
+}
+
+impl Default for TestConfig {
+    fn default() -> Self {
+        TestConfig {
+            dir: tempfile::Builder::new().prefix("relay-test-").tempdir().unwrap(),
+        }
+    }
+}
+
+impl TestConfig {
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Config file contents for a relay named `name` listening on `port`, with
+    /// its databases in the temporary directory, followed by `extra`.
+    pub fn toml(&self, name: &str, port: &str, extra: &str) -> String {
+        format!(
+            "name=\"{}\"\nport=\"{}\"\ndb_path=\"{}\"\nremote_db_path=\"{}\"\n{}",
+            name,
+            port,
+            self.path().join("requests").display(),
+            self.path().join("remote_request").display(),
+            extra
+        )
+    }
+
+    /// Writes `toml` to relay.toml in the temporary directory and returns the
+    /// path of the file.
+    pub fn write(&self, toml: &str) -> String {
+        let path = self.path().join("relay.toml");
+        std::fs::write(&path, toml).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// Parses the config given by `toml`, without runtime registrations.
+    pub fn relay_config(&self, name: &str, port: &str, extra: &str) -> RelayConfig {
+        let mut conf = config::Config::default();
+        conf.merge(config::File::from_str(&self.toml(name, port, extra), config::FileFormat::Toml)).unwrap();
+        RelayConfig::from_config(&conf, Some(&Default::default())).unwrap()
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright IBM Corp. All Rights Reserved.
+//
+// SPDX-License-Identifier: Apache-2.0
+
+use futures::future::BoxFuture;
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::{global, Context, Key, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Config, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tonic::metadata::{MetadataKey, MetadataMap};
+use tonic::{IntoRequest, Request};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The `[tracing]` section of the relay config.
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize, Debug)]
+pub struct TraceSettings {
+    // "none", "otlp" or "file". Trace context is propagated whichever exporter is used.
+    #[serde(default = "default_exporter")]
+    pub exporter: String,
+    // Collector the "otlp" exporter sends spans to over gRPC.
+    #[serde(default = "default_otlp_endpoint")]
+    pub otlp_endpoint: String,
+    // File the "file" exporter appends spans to, one JSON object per line.
+    #[serde(default = "default_file_path")]
+    pub file_path: String,
+    // Service name of the exported spans, the relay name when empty.
+    #[serde(default)]
+    pub service_name: String,
+}
+
+fn default_exporter() -> String {
+    "none".to_string()
+}
+
+fn default_otlp_endpoint() -> String {
+    "http://localhost:4317".to_string()
+}
+
+fn default_file_path() -> String {
+    "traces.json".to_string()
+}
+
+impl Default for TraceSettings {
+    fn default() -> Self {
+        TraceSettings {
+            exporter: default_exporter(),
+            otlp_endpoint: default_otlp_endpoint(),
+            file_path: default_file_path(),
+            service_name: "".to_string(),
+        }
+    }
+}
+
+impl TraceSettings {
+    /// Reads the `[tracing]` table, defaults apply when it is missing.
+    pub fn from_config(conf: &config::Config) -> Result<TraceSettings, String> {
+        let settings = match conf.get::<TraceSettings>("tracing") {
+            Ok(settings) => settings,
+            Err(config::ConfigError::NotFound(_)) => TraceSettings::default(),
+            Err(e) => return Err(format!("tracing: {}", e)),
+        };
+        match settings.exporter.as_str() {
+            "none" => {}
+            "otlp" => {
+                settings.otlp_endpoint.parse::<tonic::transport::Uri>()
+                    .map_err(|e| format!("tracing.otlp_endpoint: invalid endpoint \"{}\": {}", settings.otlp_endpoint, e))?;
+            }
+            "file" => {
+                if settings.file_path.is_empty() {
+                    return Err("tracing.file_path: required by the file exporter".to_string());
+                }
+            }
+            exporter => return Err(format!("tracing.exporter: unsupported exporter \"{}\", expected none, otlp or file", exporter)),
+        }
+        Ok(settings)
+    }
+}
+
+/// Creates the tracer the relay's spans are recorded with and installs the
+/// W3C trace context propagator. The exporter is only set up at startup.
+/// When it cannot be set up, spans are not exported but trace context is
+/// still propagated, and the reason is returned along with the tracer.
+pub fn init_tracer(settings: &TraceSettings, relay_name: &str) -> (Tracer, Option<TraceError>) {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let service_name = if settings.service_name.is_empty() { relay_name } else { &settings.service_name };
+    let config = Config::default().with_resource(Resource::new(vec![KeyValue::new("service.name", service_name.to_string())]));
+    let mut builder = TracerProvider::builder().with_config(config);
+    let mut exporter_error = None;
+    match settings.exporter.as_str() {
+        "otlp" => {
+            let exporter = opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(&settings.otlp_endpoint)
+                .build_span_exporter();
+            match exporter {
+                Ok(exporter) => builder = builder.with_batch_exporter(exporter, runtime::Tokio),
+                Err(e) => exporter_error = Some(e),
+            }
+        }
+        // Spans are written as soon as they end, so that they can be checked right away.
+        "file" => match FileExporter::open(&settings.file_path) {
+            Ok(exporter) => builder = builder.with_simple_exporter(exporter),
+            Err(e) => exporter_error = Some(e),
+        },
+        _ => {}
+    }
+    let provider = builder.build();
+    let tracer = provider.tracer("relay");
+    let _ = global::set_tracer_provider(provider);
+    (tracer, exporter_error)
+}
+
+/// Exports the spans that have not been exported yet. Blocks until they are.
+pub fn shutdown_tracer() {
+    global::shutdown_tracer_provider();
+}
+
+/// Makes the trace context of an incoming call, if any, the parent of its span.
+pub fn set_remote_parent(span: &tracing::Span, headers: &hyper::HeaderMap) {
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    span.set_parent(context);
+}
+
+/// Wraps an outbound message in a request that carries the trace context of
+/// the current span, e.g. `client.send_state(traced(state))`.
+pub fn traced<T>(message: impl IntoRequest<T>) -> Request<T> {
+    let mut request = message.into_request();
+    inject_context(&tracing::Span::current().context(), request.metadata_mut());
+    request
+}
+
+fn inject_context(context: &Context, metadata: &mut MetadataMap) {
+    global::get_text_map_propagator(|propagator| propagator.inject_context(context, &mut MetadataInjector(metadata)));
+}
+
+struct HeaderExtractor<'a>(&'a hyper::HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (MetadataKey::from_bytes(key.as_bytes()), value.parse()) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+/// Appends each span to a file as one JSON object per line.
+#[derive(Debug)]
+struct FileExporter {
+    file: File,
+}
+
+impl FileExporter {
+    fn open(file_path: &str) -> Result<FileExporter, TraceError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path)
+            .map_err(|e| TraceError::from(format!("Unable to open {}: {}", file_path, e)))?;
+        Ok(FileExporter { file })
+    }
+}
+
+impl SpanExporter for FileExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let result = batch
+            .iter()
+            .try_for_each(|span| writeln!(self.file, "{}", span_json(span)))
+            .map_err(|e| TraceError::from(e.to_string()));
+        Box::pin(std::future::ready(result))
+    }
+}
+
+fn span_json(span: &SpanData) -> serde_json::Value {
+    let attributes: serde_json::Map<String, serde_json::Value> = span
+        .attributes
+        .iter()
+        .map(|attribute| (attribute.key.to_string(), attribute.value.as_str().into()))
+        .collect();
+    serde_json::json!({
+        "trace_id": span.span_context.trace_id().to_string(),
+        "span_id": span.span_context.span_id().to_string(),
+        "parent_span_id": span.parent_span_id.to_string(),
+        "name": span.name,
+        "service_name": span.resource.get(Key::new("service.name")).map(|name| name.to_string()),
+        "start_time_unix_nano": unix_nanos(span.start_time),
+        "end_time_unix_nano": unix_nanos(span.end_time),
+        "attributes": attributes,
+    })
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+
+    #[test]
+    fn trace_context_is_carried_in_metadata() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let span_context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let mut metadata = MetadataMap::new();
+        inject_context(&Context::new().with_remote_span_context(span_context.clone()), &mut metadata);
+        assert_eq!(
+            metadata.get("traceparent").unwrap(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+
+        let headers = metadata.into_headers();
+        let extracted = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(&headers)));
+        assert_eq!(extracted.span().span_context(), &span_context);
+    }
+}